@@ -6,11 +6,7 @@ use stm32f303::hal::{
 };
 
 use peach8::{
-    embedded_graphics::{
-        drawable::{Drawable, Pixel},
-        geometry::Point,
-        pixelcolor::BinaryColor,
-    },
+    embedded_graphics::{drawable::Drawable, pixelcolor::BinaryColor},
     frame::FrameView,
     Context,
 };
@@ -60,21 +56,10 @@ where
     fn on_frame(&mut self, frame: FrameView<'_>) {
         if self.frame_timer.wait().is_ok() {
             frame
-                .iter_pixelwise_scaled(2)
-                .enumerate()
-                .for_each(|(y, row_iter)| {
-                    row_iter.enumerate().for_each(|(x, &is_on)| {
-                        let p = Pixel(
-                            Point::new(x as i32, y as i32),
-                            if is_on {
-                                BinaryColor::On
-                            } else {
-                                BinaryColor::Off
-                            },
-                        );
-                        p.draw(&mut self.display).unwrap();
-                    });
-                });
+                .drawable(BinaryColor::On, BinaryColor::Off)
+                .with_scale(2)
+                .draw(&mut self.display)
+                .unwrap();
             self.display.flush().unwrap();
         }
     }
@@ -2,8 +2,7 @@
 #![no_std]
 
 // sets default panic handler
-#[allow(unused_imports)]
-use panic_itm;
+use panic_itm as _;
 
 #[allow(unused_imports)]
 use cortex_m::asm::{bkpt, nop};
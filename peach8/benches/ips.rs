@@ -0,0 +1,116 @@
+//! Instructions per second of `Peach8::tick_chip`
+//!
+//! Runs each program for a fixed number of instructions, ticking timers at
+//! the usual 500Hz / 60Hz ratio, and reports the median rate of several runs
+//! with the slowest and the fastest one, as single runs vary by up to 10%:
+//! ```
+//! cargo bench -p peach8 --bench ips
+//! ```
+
+use std::time::Instant;
+
+use peach8::{Builder, Context, FrameView};
+
+const INSTRUCTIONS: u32 = 2_000_000;
+const INSTRUCTIONS_PER_VBLANK: u32 = 8;
+/// Runs of each program, an odd number so that the median is one of them
+const SAMPLES: usize = 11;
+
+#[rustfmt::skip]
+const SPRITES: &[u8] = &[
+    0xA2, 0x0E, // 0x200 LD I, 0x20E
+    0x60, 0x00, // 0x202 LD V0, 0x00
+    0x61, 0x00, // 0x204 LD V1, 0x00
+    0xD0, 0x1F, // 0x206 DRW V0, V1, 15
+    0x70, 0x05, // 0x208 ADD V0, 0x05
+    0x71, 0x03, // 0x20A ADD V1, 0x03
+    0x12, 0x06, // 0x20C JP 0x206
+    0xFF, 0x81, 0xBD, 0xA5, 0xA5, 0xBD, 0x81, 0xFF, // 0x20E sprite data
+    0x18, 0x3C, 0x7E, 0xFF, 0x7E, 0x3C, 0x18,
+];
+
+/// Context doing no work, so that only the interpreter is measured
+///
+/// Keys are pressed in turns, so that programs waiting for input progress.
+struct Bench {
+    seed: u32,
+    polls: u32,
+}
+
+impl Context for Bench {
+    fn on_frame(&mut self, _frame: FrameView<'_>) {}
+
+    fn sound_on(&mut self) {}
+
+    fn sound_off(&mut self) {}
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.polls = self.polls.wrapping_add(1);
+        let mut keys = [false; 16];
+        keys[(self.polls / 512 % 16) as usize] = self.polls % 512 < 256;
+        keys
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        // xorshift32
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as u8
+    }
+}
+
+/// Run the program once, returning the number of executed instructions
+/// and instructions per second
+fn sample(program: &[u8]) -> (u32, f64) {
+    let mut chip = Builder::new()
+        .with_context(Bench {
+            seed: 0x1234_5678,
+            polls: 0,
+        })
+        .with_program(program)
+        .build()
+        .unwrap();
+    let started = Instant::now();
+    let mut executed = 0;
+    while executed < INSTRUCTIONS {
+        if chip.tick_chip().is_err() {
+            break;
+        }
+        executed += 1;
+        if executed % INSTRUCTIONS_PER_VBLANK == 0 {
+            chip.tick_timers();
+        }
+    }
+    (executed, executed as f64 / started.elapsed().as_secs_f64())
+}
+
+fn run(name: &str, program: &[u8]) {
+    let mut executed = 0;
+    let mut rates: Vec<f64> = (0..SAMPLES)
+        .map(|_| {
+            let (instructions, rate) = sample(program);
+            executed = instructions;
+            rate
+        })
+        .collect();
+    rates.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    println!(
+        "{:<10} {:>9} instructions, {:>12.0} IPS median of {}, {:.0} to {:.0}",
+        name,
+        executed,
+        rates[SAMPLES / 2],
+        SAMPLES,
+        rates[0],
+        rates[SAMPLES - 1]
+    );
+}
+
+fn main() {
+    run("SPRITES", SPRITES);
+    for name in &["BRIX", "INVADERS", "KALEID", "TETRIS"] {
+        let program = std::fs::read(format!("{}/../roms/{}", env!("CARGO_MANIFEST_DIR"), name))
+            .expect("ROM should be readable");
+        run(name, &program);
+    }
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use core::convert::TryFrom;
+
+use libfuzzer_sys::fuzz_target;
+use peach8::opcode::OpCode;
+
+fuzz_target!(|raw: u16| {
+    if let Ok(opcode) = OpCode::try_from(raw) {
+        let _ = format!("{} {:?}", opcode, opcode);
+    }
+});
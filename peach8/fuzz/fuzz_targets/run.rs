@@ -0,0 +1,6 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use peach8::testing::fuzz;
+
+fuzz_target!(|data: &[u8]| fuzz::run(data));
@@ -0,0 +1,235 @@
+//! Dynamic map of memory accesses
+//!
+//! `AccessMap` wraps a `Context` and records, for each byte of memory, how it
+//! was accessed by the running program:
+//! - `EXECUTED`: fetched as a part of an instruction,
+//! - `READ`: loaded into registers by `FX65`,
+//! - `WRITTEN`: stored by `FX55` or `FX33`,
+//! - `SPRITE`: drawn as sprite data by `DXYN`.
+//!
+//! Unlike static analysis, it follows computed jumps and self-modifying code.
+//! Written bytes inside of the program usually hold score counters or state of
+//! the game, while sprite bytes reveal sprite sheets.
+
+use core::convert::TryFrom;
+use core::ops::Range;
+
+use crate::context::Context;
+use crate::frame::FrameView;
+use crate::opcode::OpCode;
+use crate::peach::{State, MEM_LENGTH};
+use crate::storage::Storage;
+
+/// Width of the image produced by `AccessMap::write_ppm`, in pixels
+pub const IMAGE_WIDTH: usize = 64;
+/// Height of the image produced by `AccessMap::write_ppm`, in pixels
+pub const IMAGE_HEIGHT: usize = MEM_LENGTH / IMAGE_WIDTH;
+
+/// RGB colours of `EXECUTED`, `READ`, `WRITTEN` and `SPRITE` flags
+/// respectively. Colours of multiple flags are added.
+pub const COLOURS: [[u8; 3]; 4] = [[0, 160, 0], [0, 0, 160], [160, 0, 0], [160, 160, 0]];
+
+/// `Context` adapter recording accesses to memory
+pub struct AccessMap<C> {
+    pub inner: C,
+    flags: [u8; MEM_LENGTH],
+}
+
+impl<C: Context> AccessMap<C> {
+    pub const EXECUTED: u8 = 0b0001;
+    pub const READ: u8 = 0b0010;
+    pub const WRITTEN: u8 = 0b0100;
+    pub const SPRITE: u8 = 0b1000;
+
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            flags: [0; MEM_LENGTH],
+        }
+    }
+
+    /// Drop the map, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    /// Flags of each byte of memory, indexed by address
+    pub fn as_raw(&self) -> &[u8] {
+        &self.flags
+    }
+
+    /// Flags of byte at given address
+    pub fn get(&self, addr: u16) -> u8 {
+        self.flags.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// Forget all recorded accesses
+    pub fn clear(&mut self) {
+        self.flags = [0; MEM_LENGTH];
+    }
+
+    /// Iterate over continuous ranges of addresses having all of given flags
+    pub fn ranges(&self, flags: u8) -> impl Iterator<Item = Range<u16>> + '_ {
+        let mut addr = 0;
+        core::iter::from_fn(move || {
+            let matches = |addr: &usize| self.flags[*addr] & flags == flags;
+            let start = (addr..MEM_LENGTH).find(matches)?;
+            let end = (start..MEM_LENGTH)
+                .find(|a| !matches(a))
+                .unwrap_or(MEM_LENGTH);
+            addr = end;
+            Some(start as u16..end as u16)
+        })
+    }
+
+    /// Write map as binary PPM image into the buffer, returns number of written bytes
+    ///
+    /// Each row of the image consists of `IMAGE_WIDTH` bytes of memory. Pixels
+    /// are coloured according to `COLOURS`, bytes never accessed are black.
+    pub fn write_ppm(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        const HEADER: &[u8] = b"P6\n64 64\n255\n";
+        let len = HEADER.len() + MEM_LENGTH * 3;
+        if buf.len() < len {
+            return Err("Buffer too small for the image");
+        }
+        buf[..HEADER.len()].copy_from_slice(HEADER);
+        buf[HEADER.len()..len]
+            .chunks_mut(3)
+            .zip(self.flags.iter())
+            .for_each(|(pixel, &flags)| {
+                pixel.copy_from_slice(&[0; 3]);
+                COLOURS
+                    .iter()
+                    .enumerate()
+                    .filter(|(n, _)| flags & 1 << n != 0)
+                    .for_each(|(_, colour)| {
+                        pixel
+                            .iter_mut()
+                            .zip(colour)
+                            .for_each(|(p, &c)| *p = p.saturating_add(c))
+                    });
+            });
+        Ok(len)
+    }
+
+    fn mark(&mut self, start: u16, len: usize, flag: u8) {
+        let start = start as usize;
+        let end = core::cmp::min(start + len, MEM_LENGTH);
+        if start < end {
+            self.flags[start..end].iter_mut().for_each(|f| *f |= flag);
+        }
+    }
+}
+
+impl<C: Context> Context for AccessMap<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.mark(state.pc, 2, Self::EXECUTED);
+        match OpCode::try_from(opcode) {
+            Ok(OpCode::_DXYN { n, .. }) => self.mark(state.i, n as usize, Self::SPRITE),
+            Ok(OpCode::_FX33 { .. }) => self.mark(state.i, 3, Self::WRITTEN),
+            Ok(OpCode::_FX55 { x }) => self.mark(state.i, x as usize + 1, Self::WRITTEN),
+            Ok(OpCode::_FX65 { x }) => self.mark(state.i, x as usize + 1, Self::READ),
+            _ => (),
+        }
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec;
+    use std::vec::Vec;
+
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+
+    type Map = AccessMap<TestingContext>;
+
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0xA3, 0x00, // 0x200 LD I, 0x300
+        0xF2, 0x65, // 0x202 LD V2, [I]
+        0xF1, 0x55, // 0x204 LD [I], V1
+        0xF0, 0x33, // 0x206 LD B, V0
+        0xA0, 0x50, // 0x208 LD I, 0x050
+        0xD0, 0x05, // 0x20A DRW V0, V0, 5
+        0x12, 0x0C, // 0x20C JP 0x20C
+    ];
+
+    #[test]
+    fn access_map() {
+        let mut chip = Builder::new()
+            .with_context(AccessMap::new(TestingContext::new(0)))
+            .with_program(PROGRAM)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            chip.tick_chip().unwrap();
+        }
+        let map = &chip.ctx;
+
+        assert_eq!(
+            map.ranges(Map::EXECUTED).collect::<Vec<_>>(),
+            vec![0x200..0x20E]
+        );
+        assert_eq!(
+            map.ranges(Map::READ).collect::<Vec<_>>(),
+            vec![0x300..0x303]
+        );
+        assert_eq!(
+            map.ranges(Map::WRITTEN).collect::<Vec<_>>(),
+            vec![0x303..0x308]
+        );
+        assert_eq!(
+            map.ranges(Map::SPRITE).collect::<Vec<_>>(),
+            vec![0x050..0x055]
+        );
+        assert_eq!(map.get(0x302), Map::READ);
+        assert_eq!(map.get(0x20C), Map::EXECUTED);
+        assert_eq!(map.get(0x20E), 0);
+        assert_eq!(map.as_raw().iter().filter(|&&f| f != 0).count(), 27);
+
+        let mut image = [0; 13 + 3 * 4096];
+        assert_eq!(map.write_ppm(&mut image), Ok(image.len()));
+        assert!(image.starts_with(b"P6\n64 64\n255\n"));
+        let pixel = |addr: usize| &image[13 + addr * 3..13 + addr * 3 + 3];
+        assert_eq!(pixel(0x200), &COLOURS[0]);
+        assert_eq!(pixel(0x050), &COLOURS[3]);
+        assert_eq!(pixel(0x000), &[0, 0, 0]);
+        assert_eq!(
+            map.write_ppm(&mut image[..100]),
+            Err("Buffer too small for the image")
+        );
+    }
+}
@@ -0,0 +1,247 @@
+//! Async run loop, for executors like `embassy`
+//!
+//! Available with `async` feature on. `Peach8::run` awaits deadlines of
+//! ticks given by a `Timer`, instead of being called from a blocking loop.
+//! While a program waits for a key with FX0A, it sleeps until the keys change
+//! rather than fetching the instruction over and over. After each tick of
+//! timers, ie. once per frame, it yields to other tasks, so eg. flushing the
+//! display or driving sound can run on the same executor.
+//!
+//! ```ignore
+//! #[embassy_executor::task]
+//! async fn emulate(mut chip: Peach8<Ctx>, mut timer: EmbassyTimer) {
+//!     let error = chip.run(&mut timer).await.unwrap_err();
+//!     error!("Peach8 crashed: {}", error);
+//! }
+//! ```
+
+use core::convert::Infallible;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{self, Poll};
+use core::time::Duration;
+
+use crate::context::Context;
+use crate::peach::Peach8;
+use crate::schedule::{Schedule, Tick};
+
+/// Source of time and of key events for `Peach8::run`
+pub trait Timer {
+    /// Time elapsed since an arbitrary fixed point
+    fn now(&mut self) -> Duration;
+
+    /// Complete at `deadline`, as returned by `now`
+    fn wait_until(&mut self, deadline: Duration) -> impl Future<Output = ()>;
+
+    /// Complete when keys returned by `Context::get_keys` may have changed,
+    /// or at `deadline` at the latest
+    ///
+    /// # Note
+    /// Platforms without interrupts of the keyboard can complete this after
+    /// a short delay, to scan keys periodically.
+    fn wait_for_keys(&mut self, deadline: Duration) -> impl Future<Output = ()>;
+}
+
+/// Future returning `Pending` once, letting other tasks run
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<()> {
+        if self.0 {
+            Poll::Ready(())
+        } else {
+            self.0 = true;
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl<C: Context + Sized> Peach8<C> {
+    /// Run chip at 500Hz and timers at 60Hz in time of `timer`
+    ///
+    /// Returns only with the first error of `tick_chip`.
+    pub async fn run<T: Timer>(&mut self, timer: &mut T) -> Result<Infallible, &'static str> {
+        self.run_at(timer, 500, 60).await
+    }
+
+    /// Run chip and timers with given frequencies in Hz in time of `timer`
+    ///
+    /// Returns only with the first error of `tick_chip`. Panics when any
+    /// frequency is 0.
+    pub async fn run_at<T: Timer>(
+        &mut self,
+        timer: &mut T,
+        chip_freq: u32,
+        timers_freq: u32,
+    ) -> Result<Infallible, &'static str> {
+        let start = timer.now();
+        let mut schedule = Schedule::new(chip_freq, timers_freq);
+        let mut waiting = false;
+        loop {
+            if waiting {
+                timer
+                    .wait_for_keys(start + schedule.next(Tick::Timers))
+                    .await;
+                // the chip slept meanwhile, so only timers can be due
+                schedule.skip_chip(timer.now() - start);
+                if schedule.is_due(timer.now() - start) {
+                    schedule.advance();
+                    self.tick_timers();
+                    YieldNow(false).await;
+                }
+                // sample keys after they changed, or once per frame
+                waiting = self.tick_waiting()?;
+            } else {
+                let tick = schedule.peek();
+                timer.wait_until(start + schedule.next(tick)).await;
+                schedule.advance();
+                match tick {
+                    Tick::Chip => waiting = self.tick_waiting()?,
+                    Tick::Timers => {
+                        self.tick_timers();
+                        YieldNow(false).await;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Tick chip, returning whether it waits for a key with FX0A
+    fn tick_waiting(&mut self) -> Result<bool, &'static str> {
+        let pc = self.state().pc;
+        self.tick_chip()?;
+        let state = self.state();
+        let at = state.pc as usize;
+        let fx0a = match state.memory.get(at..at + 2) {
+            Some(&[high, low]) => high & 0xF0 == 0xF0 && low == 0x0A,
+            _ => false,
+        };
+        Ok(fx0a && state.pc == pc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::boxed::Box;
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::vec::Vec;
+
+    use crate::builder::Builder;
+    use crate::frame::FrameView;
+    use crate::testing::{block_on, VirtualTimer};
+
+    /// `Context` holding key 5 during given periods of virtual time
+    struct KeysContext {
+        now: Rc<Cell<Duration>>,
+        held: Vec<(Duration, Duration)>,
+        frames: u32,
+        sound: bool,
+    }
+
+    impl Context for KeysContext {
+        fn on_frame(&mut self, _: FrameView<'_>) {
+            self.frames += 1;
+        }
+
+        fn sound_on(&mut self) {
+            self.sound = true;
+        }
+
+        fn sound_off(&mut self) {
+            self.sound = false;
+        }
+
+        fn get_keys(&mut self) -> [bool; 16] {
+            let now = self.now.get();
+            let mut keys = [false; 16];
+            keys[5] = self.held.iter().any(|&(from, to)| from <= now && now < to);
+            keys
+        }
+
+        fn gen_random(&mut self) -> u8 {
+            0
+        }
+    }
+
+    fn chip(timer: &VirtualTimer, program: &[u8], held: &[(u64, u64)]) -> Peach8<KeysContext> {
+        let ctx = KeysContext {
+            now: timer.now_handle(),
+            held: held
+                .iter()
+                .map(|&(from, to)| (Duration::from_millis(from), Duration::from_millis(to)))
+                .collect(),
+            frames: 0,
+            sound: false,
+        };
+        Builder::new()
+            .with_context(ctx)
+            .with_program(program)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn runs_at_frequencies() {
+        // 0x200: V0 += 1, loop while V0 != 0, jump to 0x000
+        let program = [0x70, 0x01, 0x30, 0x00, 0x12, 0x00, 0x10, 0x00];
+        let mut timer = VirtualTimer::new();
+        let mut chip = chip(&timer, &program, &[]);
+
+        let result = block_on(chip.run(&mut timer));
+        assert_eq!(
+            result,
+            Err("Attempted to jump out of program's address space")
+        );
+        // 256 iterations, the last one skipping the jump back
+        assert_eq!(chip.cycles(), 3 * 256);
+        assert_eq!(timer.now(), Duration::from_millis(2 * 3 * 256));
+    }
+
+    #[test]
+    fn sleeps_until_key_is_released() {
+        // 0x200: V0 = 30, sound = V0, V1 = key, V2 = delay, jump to 0x000
+        let program = [0x60, 0x1E, 0xF0, 0x18, 0xF1, 0x0A, 0xF2, 0x07, 0x10, 0x00];
+        let mut timer = VirtualTimer::new();
+        timer.key_events(&[Duration::from_millis(1000), Duration::from_millis(1100)]);
+        let mut chip = chip(&timer, &program, &[(1000, 1100)]);
+
+        block_on(chip.run(&mut timer)).unwrap_err();
+        assert_eq!(chip.state().v[1], 5);
+        // FX0A runs once per frame until 1.1s, where the key is released,
+        // instead of each 2ms
+        assert_eq!(chip.cycles(), 3 + 66 + 2);
+        assert_eq!(chip.ctx.frames as u64, chip.cycles());
+        // timers kept ticking meanwhile
+        assert!(!chip.ctx.sound);
+        assert_eq!(chip.state().v[2], 0);
+        assert_eq!(timer.now(), Duration::from_millis(1100 + 2 + 2));
+    }
+
+    #[test]
+    fn yields_after_each_frame() {
+        // 0x200: loop 100 times, then jump to 0x000
+        let program = [0x70, 0x01, 0x30, 0x64, 0x12, 0x00, 0x10, 0x00];
+        let mut timer = VirtualTimer::new();
+        let mut chip = chip(&timer, &program, &[]);
+
+        let mut run = Box::pin(chip.run(&mut timer));
+        let mut cx = task::Context::from_waker(task::Waker::noop());
+        let mut polls = 1;
+        let result = loop {
+            match run.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => break result,
+                Poll::Pending => polls += 1,
+            }
+        };
+        drop(run);
+        assert!(result.is_err());
+        // 300 instructions take 600ms, the last one before the 36th frame
+        assert_eq!(chip.cycles(), 300);
+        assert_eq!(polls, 35 + 1);
+    }
+}
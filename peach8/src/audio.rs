@@ -0,0 +1,419 @@
+//! Synthesis of the buzzer as PCM audio
+//!
+//! `Synth` turns sound on and off events, timestamped by cycles of the chip,
+//! into a square wave of chosen frequency at chosen sample rate. The wave is
+//! band-limited with PolyBLEP, and an attack and a release ramp shape each
+//! tone, so toggling the sound doesn't click. Samples are written into
+//! buffers given by the caller, eg. halves of a DMA buffer of a DAC.
+//!
+//! `Beeper` wraps a `Context`, counts executed instructions and feeds changes
+//! of the sound timer into its `Synth`.
+//!
+//! ```ignore
+//! let mut chip = Builder::new()
+//!     .with_context(Beeper::new(ctx, Config::default()))
+//!     .with_program(rom)
+//!     .build()?;
+//! loop {
+//!     // emulate 1/60s, then render it
+//!     runner.poll(&mut chip)?;
+//!     chip.ctx.synth.fill(&mut samples[..735]);
+//!     play(&samples[..735]);
+//! }
+//! ```
+
+use core::time::Duration;
+
+use heapless::{consts::U16, spsc::Queue};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::context::Context;
+use crate::frame::FrameView;
+use crate::peach::State;
+use crate::storage::Storage;
+
+/// Configuration of `Synth`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Config {
+    /// Samples per second of the output
+    pub sample_rate: u32,
+    /// Frequency of the tone in Hz
+    pub frequency: u32,
+    /// Peak value of samples
+    pub amplitude: i16,
+    /// Time of rising to full volume after sound is turned on
+    pub attack: Duration,
+    /// Time of fading out after sound is turned off
+    pub release: Duration,
+    /// Frequency of chip in Hz, converting cycles of events into time
+    pub chip_freq: u32,
+}
+
+impl Default for Config {
+    /// 440Hz at 44.1kHz with quarter of full volume, chip at 500Hz
+    fn default() -> Self {
+        Self {
+            sample_rate: 44_100,
+            frequency: 440,
+            amplitude: i16::MAX / 4,
+            attack: Duration::from_millis(2),
+            release: Duration::from_millis(5),
+            chip_freq: 500,
+        }
+    }
+}
+
+/// Change of sound, at the cycle of chip it happened on
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Event {
+    pub cycle: u64,
+    pub on: bool,
+}
+
+/// Generator of square wave switched by `Event`s
+pub struct Synth {
+    config: Config,
+    events: Queue<Event, U16>,
+    /// Number of samples rendered so far
+    position: u64,
+    on: bool,
+    /// Phase of the wave in periods, from 0 to 1
+    phase: f32,
+    /// Volume of the envelope, from 0 to 1
+    gain: f32,
+}
+
+impl Synth {
+    /// Panics when sample rate or frequency of chip is 0, or frequency of the
+    /// tone isn't between 0 and half of sample rate
+    pub fn new(config: Config) -> Self {
+        assert!(
+            config.sample_rate > 0 && config.chip_freq > 0,
+            "Frequency must be positive"
+        );
+        assert!(
+            config.frequency > 0 && config.frequency as u64 * 2 < config.sample_rate as u64,
+            "Frequency of tone must be below half of sample rate"
+        );
+        Self {
+            config,
+            events: Queue::new(),
+            position: 0,
+            on: false,
+            phase: 0.0,
+            gain: 0.0,
+        }
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// Queue an event, to be rendered when its cycle is reached
+    pub fn push(&mut self, event: Event) -> Result<(), &'static str> {
+        self.events
+            .enqueue(event)
+            .map_err(|_| "Queue of sound events is full")
+    }
+
+    /// Number of samples rendered so far
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+
+    /// Whether any sound is being rendered, including fading out
+    pub fn is_audible(&self) -> bool {
+        self.on || self.gain > 0.0
+    }
+
+    /// Render the next samples into the buffer
+    ///
+    /// Events with cycles before the end of the buffer are applied at their
+    /// samples, late ones at the start of the buffer.
+    pub fn fill(&mut self, buf: &mut [i16]) {
+        let rate = self.config.sample_rate as f32;
+        let step = self.config.frequency as f32 / rate;
+        let attack = 1.0 / (self.config.attack.as_secs_f32() * rate).max(1.0);
+        let release = 1.0 / (self.config.release.as_secs_f32() * rate).max(1.0);
+        let amplitude = self.config.amplitude as f32;
+
+        for sample in buf.iter_mut() {
+            while let Some(event) = self.events.peek() {
+                if self.sample_of(event.cycle) > self.position {
+                    break;
+                }
+                self.on = event.on;
+                self.events.dequeue();
+            }
+            self.gain = if self.on {
+                (self.gain + attack).min(1.0)
+            } else {
+                (self.gain - release).max(0.0)
+            };
+            if self.gain > 0.0 {
+                *sample = (square(self.phase, step) * self.gain * amplitude) as i16;
+                self.phase += step;
+                if self.phase >= 1.0 {
+                    self.phase -= 1.0;
+                }
+            } else {
+                // start each tone at the same phase
+                *sample = 0;
+                self.phase = 0.0;
+            }
+            self.position += 1;
+        }
+    }
+
+    /// Index of the sample at which a cycle happens
+    fn sample_of(&self, cycle: u64) -> u64 {
+        (cycle as u128 * self.config.sample_rate as u128 / self.config.chip_freq as u128) as u64
+    }
+}
+
+/// Square wave at `phase` with its steps smoothed by PolyBLEP
+fn square(phase: f32, step: f32) -> f32 {
+    let naive = if phase < 0.5 { 1.0 } else { -1.0 };
+    let mut falling = phase + 0.5;
+    if falling >= 1.0 {
+        falling -= 1.0;
+    }
+    naive + blep(phase, step) - blep(falling, step)
+}
+
+/// Residual of a band-limited step at phase 0, spanning one sample around it
+fn blep(phase: f32, step: f32) -> f32 {
+    if phase < step {
+        let x = phase / step;
+        2.0 * x - x * x - 1.0
+    } else if phase > 1.0 - step {
+        let x = (phase - 1.0) / step;
+        x * x + 2.0 * x + 1.0
+    } else {
+        0.0
+    }
+}
+
+/// `Context` adapter rendering the sound timer with a `Synth`
+pub struct Beeper<C> {
+    pub inner: C,
+    pub synth: Synth,
+    cycles: u64,
+    on: bool,
+}
+
+impl<C: Context> Beeper<C> {
+    pub fn new(inner: C, config: Config) -> Self {
+        Self {
+            inner,
+            synth: Synth::new(config),
+            cycles: 0,
+            on: false,
+        }
+    }
+
+    /// Drop the adapter, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    /// Queue an event when sound changes
+    fn switch(&mut self, on: bool) {
+        if self.on == on {
+            return;
+        }
+        self.on = on;
+        let event = Event {
+            cycle: self.cycles,
+            on,
+        };
+        if let Err(e) = self.synth.push(event) {
+            warn!("{}, dropped {:?}", e, event);
+        }
+    }
+}
+
+impl<C: Context> Context for Beeper<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.switch(true);
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.switch(false);
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.cycles += 1;
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec;
+
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+
+    /// 1kHz tone at 8kHz, ramps of 1ms, chip at 1kHz
+    fn config() -> Config {
+        Config {
+            sample_rate: 8000,
+            frequency: 1000,
+            amplitude: 1000,
+            attack: Duration::from_millis(1),
+            release: Duration::from_millis(1),
+            chip_freq: 1000,
+        }
+    }
+
+    fn on(cycle: u64) -> Event {
+        Event { cycle, on: true }
+    }
+
+    fn off(cycle: u64) -> Event {
+        Event { cycle, on: false }
+    }
+
+    #[test]
+    #[should_panic(expected = "below half of sample rate")]
+    fn rejects_frequency_above_nyquist() {
+        Synth::new(Config {
+            frequency: 4000,
+            ..config()
+        });
+    }
+
+    #[test]
+    fn silent_without_events() {
+        let mut synth = Synth::new(config());
+        let mut buf = [1; 64];
+        synth.fill(&mut buf);
+        assert!(buf.iter().all(|&s| s == 0));
+        assert_eq!(synth.position(), 64);
+        assert!(!synth.is_audible());
+    }
+
+    #[test]
+    fn events_start_at_their_samples() {
+        let mut synth = Synth::new(config());
+        synth.push(on(2)).unwrap();
+        synth.push(off(10)).unwrap();
+        let mut buf = [0; 160];
+        synth.fill(&mut buf);
+        // cycle 2 is sample 16, cycle 10 is sample 80
+        assert!(buf[..16].iter().all(|&s| s == 0));
+        assert!(buf[16..24].iter().any(|&s| s != 0));
+        assert!(buf[80..88].iter().any(|&s| s != 0));
+        // released within 1ms, ie. 8 samples
+        assert!(buf[88..].iter().all(|&s| s == 0));
+        assert!(!synth.is_audible());
+    }
+
+    #[test]
+    fn ramps_limit_volume() {
+        let mut synth = Synth::new(config());
+        synth.push(on(0)).unwrap();
+        let mut buf = [0; 64];
+        synth.fill(&mut buf);
+        let peak = |samples: &[i16]| samples.iter().map(|s| s.abs()).max().unwrap();
+        // rising for 8 samples, one period of the tone
+        assert!(peak(&buf[..4]) <= 500);
+        assert!(peak(&buf[8..]) <= 1000);
+        assert!(peak(&buf[8..]) > 900);
+
+        synth.push(off(8)).unwrap();
+        synth.fill(&mut buf);
+        assert!(peak(&buf[..4]) < 900);
+        assert!(buf[8..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn steps_are_band_limited() {
+        let mut synth = Synth::new(Config {
+            attack: Duration::ZERO,
+            ..config()
+        });
+        synth.push(on(0)).unwrap();
+        let mut buf = [0; 32];
+        synth.fill(&mut buf);
+        // samples next to each step are smoothed instead of being at full swing
+        assert!(buf[8..].iter().any(|&s| s.abs() < 1000));
+        // wave is balanced around 0
+        let sum: i32 = buf[8..].iter().map(|&s| s as i32).sum();
+        assert!(sum.abs() < 100, "{}", sum);
+    }
+
+    #[test]
+    fn late_events_apply_at_once() {
+        let mut synth = Synth::new(config());
+        let mut buf = [0; 16];
+        synth.fill(&mut buf);
+        synth.push(on(1)).unwrap();
+        synth.fill(&mut buf);
+        assert!(buf[..4].iter().any(|&s| s != 0));
+    }
+
+    #[test]
+    fn queue_overflow() {
+        let mut synth = Synth::new(config());
+        for cycle in 0..16 {
+            synth.push(on(cycle)).unwrap();
+        }
+        assert_eq!(synth.push(on(16)), Err("Queue of sound events is full"));
+    }
+
+    #[test]
+    fn beeper_follows_sound_timer() {
+        // V0 = 2, sound = V0, loop
+        let program = [0x60, 0x02, 0xF0, 0x18, 0x12, 0x04];
+        let ctx = Beeper::new(TestingContext::new(0), config());
+        let mut chip = Builder::new()
+            .with_context(ctx)
+            .with_program(&program)
+            .build()
+            .unwrap();
+        let mut run = |cycles| {
+            for _ in 0..cycles {
+                chip.tick_chip().unwrap();
+            }
+            chip.tick_timers();
+        };
+        // sound on at cycle 4, finished at 8 and off at 12
+        run(4);
+        run(4);
+        run(4);
+        let synth = &mut chip.ctx.synth;
+        let mut buf = vec![0; 128];
+        synth.fill(&mut buf);
+        // cycle 4 is sample 32, cycle 12 is sample 96, released in 8 samples
+        assert!(buf[..32].iter().all(|&s| s == 0));
+        assert!(buf[32..96].iter().filter(|&&s| s != 0).count() > 40);
+        assert!(buf[104..].iter().all(|&s| s == 0));
+        assert!(!synth.is_audible());
+    }
+}
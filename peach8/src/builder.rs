@@ -1,9 +1,22 @@
 use crate::context::Context;
+use crate::extension::{Extensions, Instruction, Routine};
 use crate::peach::Peach8;
+#[cfg(any(feature = "atomic", feature = "critical-section"))]
+use crate::timer::{Slot, Timers};
 
 pub struct Builder<'a, C: Context + Sized> {
     context: Option<C>,
     program: Option<&'a [u8]>,
+    extensions: Extensions<C>,
+    #[cfg(any(feature = "atomic", feature = "critical-section"))]
+    timers: Option<&'static Timers>,
+    error: Option<&'static str>,
+}
+
+impl<'a, C: Context + Sized> Default for Builder<'a, C> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<'a, C: Context + Sized> Builder<'a, C> {
@@ -11,6 +24,10 @@ impl<'a, C: Context + Sized> Builder<'a, C> {
         Self {
             context: None,
             program: None,
+            extensions: Extensions::new(),
+            #[cfg(any(feature = "atomic", feature = "critical-section"))]
+            timers: None,
+            error: None,
         }
     }
 
@@ -24,10 +41,50 @@ impl<'a, C: Context + Sized> Builder<'a, C> {
         self
     }
 
+    /// Register native handler for machine code routine called by `0NNN`
+    pub fn with_routine(mut self, addr: u16, routine: Routine<C>) -> Self {
+        if let Err(e) = self.extensions.add_routine(addr, routine) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// Register native handler for undefined opcodes matching `raw & mask == pattern`
+    pub fn with_instruction(
+        mut self,
+        mask: u16,
+        pattern: u16,
+        instruction: Instruction<C>,
+    ) -> Self {
+        if let Err(e) = self.extensions.add_instruction(mask, pattern, instruction) {
+            self.error.get_or_insert(e);
+        }
+        self
+    }
+
+    /// Keep delay and sound timers in shared `timers`, which can be ticked
+    /// from an interrupt through `Timers::handle` or `Peach8::timer_handle`
+    ///
+    /// Timers ticked this way skip `Peach8::tick_timers`, so the main loop
+    /// must call `Peach8::vblank` with the state returned by each tick.
+    #[cfg(any(feature = "atomic", feature = "critical-section"))]
+    pub fn with_timers(mut self, timers: &'static Timers) -> Self {
+        self.timers = Some(timers);
+        self
+    }
+
     pub fn build(self) -> Result<Peach8<C>, &'static str> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
         let context = self.context.ok_or("Context not provided")?;
         let program = self.program.ok_or("Program not provided")?;
         let mut peach = Peach8::new(context);
+        peach.extensions = self.extensions;
+        #[cfg(any(feature = "atomic", feature = "critical-section"))]
+        if let Some(timers) = self.timers {
+            peach.timers = Slot::Shared(timers);
+        }
         peach.load(program);
         Ok(peach)
     }
@@ -36,7 +93,7 @@ impl<'a, C: Context + Sized> Builder<'a, C> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::context::testing::TestingContext;
+    use crate::testing::TestingContext;
 
     #[test]
     fn with_context_and_prog() {
@@ -53,6 +110,20 @@ mod tests {
         assert!(result.is_err());
     }
 
+    #[test]
+    fn with_too_many_routines() {
+        let mut builder = Builder::new()
+            .with_context(TestingContext::new(0))
+            .with_program(&[]);
+        for addr in 0..=16 {
+            builder = builder.with_routine(addr, |_, _| Ok(()));
+        }
+        assert_eq!(
+            builder.build().err(),
+            Some("Cannot register routine, table is full")
+        );
+    }
+
     #[test]
     fn with_program_only() {
         let result = Builder::<'_, TestingContext>::new()
@@ -5,6 +5,8 @@
 //! although it is not required.
 
 use crate::frame::FrameView;
+use crate::peach::State;
+use crate::storage::Storage;
 
 /// Trait aggregating platform functionalities
 pub trait Context {
@@ -28,97 +30,22 @@ pub trait Context {
     ///
     /// Called by `tick_chip` whenever requested by executing program
     fn gen_random(&mut self) -> u8;
-}
-
-#[cfg(test)]
-pub mod testing {
-    use super::*;
-
-    use nanorand::{rand::pcg64::Pcg64 as Rng, RNG};
-
-    use crate::frame::MEM_LENGTH;
-    use crate::utils::testing::{ImageMask, ToMask};
-
-    pub struct TestingContext {
-        sound: bool,
-        frame: Option<ImageMask>,
-        keys: [bool; 16],
-        rng: Rng,
-    }
-
-    impl TestingContext {
-        pub fn new(seed: u128) -> Self {
-            Self {
-                sound: false,
-                frame: None,
-                keys: [false; 16],
-                rng: Rng::new_seed(seed),
-            }
-        }
-
-        pub fn is_sound_on(&self) -> bool {
-            self.sound
-        }
-
-        pub fn get_frame(&self) -> Option<&ImageMask> {
-            self.frame.as_ref()
-        }
-
-        pub fn set_key(&mut self, n: u8) {
-            self.keys[n as usize] = true;
-        }
-
-        pub fn reset_key(&mut self, n: u8) {
-            self.keys[n as usize] = false;
-        }
-    }
-
-    impl Context for TestingContext {
-        fn on_frame(&mut self, frame: FrameView<'_>) {
-            self.frame = Some(frame.to_mask());
-        }
-
-        fn sound_on(&mut self) {
-            self.sound = true;
-        }
-
-        fn sound_off(&mut self) {
-            self.sound = false;
-        }
-
-        fn gen_random(&mut self) -> u8 {
-            self.rng.generate::<u8>()
-        }
-
-        fn get_keys(&mut self) -> [bool; 16] {
-            self.keys
-        }
-    }
-
-    #[test]
-    fn testing_context() {
-        let mut ctx = TestingContext::new(0);
-
-        let full_mask_str = include_str!("../test-data/context/full_mask");
-        let full_mask_data: &[u8; MEM_LENGTH] = &[255; MEM_LENGTH];
-
-        ctx.on_frame(FrameView::new(full_mask_data));
-        assert!(ctx.frame.is_some());
-        assert_eq!(ctx.frame.unwrap(), full_mask_str.to_mask());
-
-        ctx.sound_on();
-        assert!(ctx.is_sound_on());
-
-        ctx.sound_off();
-        assert!(!ctx.is_sound_on());
-
-        ctx.set_key(0x01u8);
-        ctx.set_key(0x0Fu8);
-        assert_eq!(ctx.get_keys().iter().filter(|&&k| k == true).count(), 2);
-        assert_eq!((ctx.keys[0x01], ctx.keys[0x0F]), (true, true));
-
-        ctx.reset_key(0x0Fu8);
-        assert_eq!(ctx.get_keys().iter().filter(|&&k| k == true).count(), 1);
-        assert_eq!((ctx.keys[0x01], ctx.keys[0x0F]), (true, false));
+    /// Observe instruction about to be executed
+    ///
+    /// Called by `tick_chip` after fetching each instruction. Does nothing
+    /// by default, meant for debugging tools, eg. `trace::Tracer`
+    fn on_instruction(&mut self, _state: &State<'_>, _opcode: u16) {}
+    /// Observe end of a 60Hz frame
+    ///
+    /// Called by `tick_timers`, or `vblank` with external timers, after timers
+    /// are decremented. Does nothing by default, meant for debugging tools,
+    /// eg. `profile::Profiler`
+    fn on_vblank(&mut self) {}
+    /// Get storage of SCHIP RPL user flags
+    ///
+    /// Called by `tick_chip` when `FX75` or `FX85` is executed. Returns `None`
+    /// by default, which makes these opcodes fail, see `storage::WithStorage`
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        None
     }
 }
@@ -0,0 +1,227 @@
+//! Drawing frames with `embedded-graphics`
+//!
+//! `FrameDrawable` draws a frame onto any `DrawTarget` in one call, at given
+//! position, integer scale and with chosen colours. Unscaled frames are passed
+//! to `DrawTarget::draw_iter`. Scaled frames are drawn as filled rectangles:
+//! one for the background and one for each horizontal run of lit pixels, so
+//! drivers with accelerated `DrawTarget::draw_rectangle` get far fewer calls
+//! than pixels.
+//!
+//! `FrameView` itself is `Drawable<BinaryColor>`, unscaled at the origin.
+
+use embedded_graphics::{
+    drawable::{Drawable, Pixel},
+    geometry::{Point, Size},
+    pixelcolor::{BinaryColor, PixelColor},
+    primitives::{Primitive, Rectangle},
+    style::PrimitiveStyle,
+    DrawTarget,
+};
+
+use crate::frame::{FrameView, HEIGHT, WIDTH};
+
+/// Frame drawable with scale, position and colours, created by `FrameView::drawable`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct FrameDrawable<'a, C> {
+    frame: FrameView<'a>,
+    on: C,
+    off: C,
+    scale: u32,
+    position: Point,
+}
+
+impl<'a, C: PixelColor> FrameDrawable<'a, C> {
+    pub(crate) fn new(frame: FrameView<'a>, on: C, off: C) -> Self {
+        Self {
+            frame,
+            on,
+            off,
+            scale: 1,
+            position: Point::zero(),
+        }
+    }
+
+    /// Draw each pixel as a `scale` x `scale` square, 0 is treated as 1
+    pub fn with_scale(mut self, scale: u32) -> Self {
+        self.scale = scale.max(1);
+        self
+    }
+
+    /// Draw the frame with its top-left corner at `position`
+    pub fn with_position(mut self, position: Point) -> Self {
+        self.position = position;
+        self
+    }
+
+    /// Size of the drawn area
+    pub fn size(&self) -> Size {
+        Size::new(WIDTH as u32, HEIGHT as u32) * self.scale
+    }
+
+    /// Fill rectangle of given size in frame's pixels, from the pixel at (x, y)
+    fn fill<D: DrawTarget<C>>(
+        &self,
+        display: &mut D,
+        (x, y): (usize, usize),
+        (width, height): (usize, usize),
+        colour: C,
+    ) -> Result<(), D::Error> {
+        let scale = self.scale as i32;
+        let top_left = self.position + Point::new(x as i32, y as i32) * scale;
+        let size = Point::new(width as i32, height as i32) * scale;
+        display.draw_rectangle(
+            &Rectangle::new(top_left, top_left + size - Point::new(1, 1))
+                .into_styled(PrimitiveStyle::with_fill(colour)),
+        )
+    }
+}
+
+impl<C: PixelColor> Drawable<C> for FrameDrawable<'_, C> {
+    fn draw<D: DrawTarget<C>>(self, display: &mut D) -> Result<(), D::Error> {
+        if self.scale == 1 {
+            return display.draw_iter((0..WIDTH * HEIGHT).map(|n| {
+                let (x, y) = (n % WIDTH, n / WIDTH);
+                let colour = if self.frame.is_lit(x, y) {
+                    self.on
+                } else {
+                    self.off
+                };
+                Pixel(self.position + Point::new(x as i32, y as i32), colour)
+            }));
+        }
+
+        self.fill(display, (0, 0), (WIDTH, HEIGHT), self.off)?;
+        for y in 0..HEIGHT {
+            let mut x = 0;
+            while x < WIDTH {
+                if !self.frame.is_lit(x, y) {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x < WIDTH && self.frame.is_lit(x, y) {
+                    x += 1;
+                }
+                self.fill(display, (start, y), (x - start, 1), self.on)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drawable<BinaryColor> for FrameView<'_> {
+    fn draw<D: DrawTarget<BinaryColor>>(self, display: &mut D) -> Result<(), D::Error> {
+        self.drawable(BinaryColor::On, BinaryColor::Off)
+            .draw(display)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_graphics::{primitives::Rectangle, style::Styled};
+
+    use crate::frame::Frame;
+
+    /// 160x80 display counting calls of `DrawTarget` methods
+    struct Display {
+        pixels: [[Option<BinaryColor>; 160]; 80],
+        iters: usize,
+        rectangles: usize,
+    }
+
+    impl Display {
+        fn new() -> Self {
+            Self {
+                pixels: [[None; 160]; 80],
+                iters: 0,
+                rectangles: 0,
+            }
+        }
+    }
+
+    impl DrawTarget<BinaryColor> for Display {
+        type Error = core::convert::Infallible;
+
+        fn draw_pixel(
+            &mut self,
+            Pixel(point, colour): Pixel<BinaryColor>,
+        ) -> Result<(), Self::Error> {
+            self.pixels[point.y as usize][point.x as usize] = Some(colour);
+            Ok(())
+        }
+
+        fn draw_iter<T>(&mut self, item: T) -> Result<(), Self::Error>
+        where
+            T: IntoIterator<Item = Pixel<BinaryColor>>,
+        {
+            self.iters += 1;
+            item.into_iter().try_for_each(|p| self.draw_pixel(p))
+        }
+
+        fn draw_rectangle(
+            &mut self,
+            item: &Styled<Rectangle, PrimitiveStyle<BinaryColor>>,
+        ) -> Result<(), Self::Error> {
+            self.rectangles += 1;
+            item.into_iter().try_for_each(|p| self.draw_pixel(p))
+        }
+
+        fn size(&self) -> Size {
+            Size::new(160, 80)
+        }
+    }
+
+    fn frame() -> Frame {
+        let mut frame = Frame::new();
+        [(0, 0), (1, 0), (2, 0), (5, 0), (63, 31)]
+            .iter()
+            .for_each(|&(x, y)| frame.xor_bit(x, y, true).unwrap());
+        frame
+    }
+
+    #[test]
+    fn draw_unscaled() {
+        let frame = frame();
+        let mut display = Display::new();
+        frame.view().draw(&mut display).unwrap();
+
+        assert_eq!((display.iters, display.rectangles), (1, 0));
+        assert_eq!(
+            display.pixels[0][..4],
+            [
+                Some(BinaryColor::On),
+                Some(BinaryColor::On),
+                Some(BinaryColor::On),
+                Some(BinaryColor::Off)
+            ]
+        );
+        assert_eq!(display.pixels[31][63], Some(BinaryColor::On));
+        assert_eq!(display.pixels[32][0], None);
+        assert_eq!(display.pixels[0][64], None);
+    }
+
+    #[test]
+    fn draw_scaled() {
+        let frame = frame();
+        let mut display = Display::new();
+        let drawable = frame
+            .view()
+            .drawable(BinaryColor::Off, BinaryColor::On)
+            .with_scale(2)
+            .with_position(Point::new(10, 3));
+        assert_eq!(drawable.size(), Size::new(128, 64));
+        drawable.draw(&mut display).unwrap();
+
+        // background and three runs of lit pixels
+        assert_eq!((display.iters, display.rectangles), (0, 4));
+        assert_eq!(display.pixels[2][10], None);
+        assert_eq!(display.pixels[3][9], None);
+        assert_eq!(display.pixels[3][10..16], [Some(BinaryColor::Off); 6]);
+        assert_eq!(display.pixels[3][16], Some(BinaryColor::On));
+        assert_eq!(display.pixels[4][20..22], [Some(BinaryColor::Off); 2]);
+        assert_eq!(display.pixels[66][136..138], [Some(BinaryColor::Off); 2]);
+        assert_eq!(display.pixels[67][137], None);
+        assert_eq!(display.pixels[66][135], Some(BinaryColor::On));
+    }
+}
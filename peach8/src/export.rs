@@ -0,0 +1,544 @@
+//! Export of frames to image and text formats
+//!
+//! Supported formats:
+//! - binary PBM (`P4`) and PGM (`P5`), written into a buffer,
+//! - PNG, written into `std::io::Write` with `std` feature on,
+//! - text made of half-block (`▀`, `▄`, `█`) or braille characters,
+//!   to be printed in a terminal.
+//!
+//! Each exporter takes a scale factor, each pixel of the frame becomes
+//! a `scale` x `scale` square. Factor of 0 is treated as 1.
+//!
+//! PBM and text formats can be parsed back into a `Frame`, which makes them
+//! suitable for test fixtures. Parsers infer the scale factor from dimensions
+//! of the image and sample top-left pixel of each square.
+//!
+//! Following PBM convention, lit pixels are stored as 1 and appear black in
+//! image viewers. In PGM and PNG lit pixels are white.
+
+use core::fmt;
+
+use crate::frame::{Frame, FrameView, HEIGHT, WIDTH};
+
+const PBM_MAGIC: &str = "P4";
+const PGM_MAGIC: &str = "P5";
+
+/// Empty braille pattern, dots are added as bits to this code point
+const BRAILLE_BASE: u32 = 0x2800;
+/// Bits of braille dots, indexed by row and column of a 2x4 cell
+const BRAILLE_DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+
+fn is_lit_scaled(frame: FrameView<'_>, x: usize, y: usize, scale: usize) -> bool {
+    frame.is_lit(x / scale, y / scale)
+}
+
+/// Header of PBM or PGM image and its length
+fn header(magic: &str, maxval: bool, scale: usize) -> ([u8; 32], usize) {
+    struct Cursor([u8; 32], usize);
+
+    impl fmt::Write for Cursor {
+        fn write_str(&mut self, s: &str) -> fmt::Result {
+            let end = self.1 + s.len();
+            self.0
+                .get_mut(self.1..end)
+                .ok_or(fmt::Error)?
+                .copy_from_slice(s.as_bytes());
+            self.1 = end;
+            Ok(())
+        }
+    }
+
+    let mut cursor = Cursor([0; 32], 0);
+    let maxval = if maxval { "255\n" } else { "" };
+    // dimensions of any reasonable scale fit into the array
+    let _ = fmt::Write::write_fmt(
+        &mut cursor,
+        format_args!(
+            "{}\n{} {}\n{}",
+            magic,
+            WIDTH * scale,
+            HEIGHT * scale,
+            maxval
+        ),
+    );
+    (cursor.0, cursor.1)
+}
+
+/// Number of bytes needed by `write_pbm` for given scale factor
+pub fn pbm_len(scale: usize) -> usize {
+    let scale = scale.max(1);
+    header(PBM_MAGIC, false, scale).1 + (WIDTH * scale).div_ceil(8) * HEIGHT * scale
+}
+
+/// Number of bytes needed by `write_pgm` for given scale factor
+pub fn pgm_len(scale: usize) -> usize {
+    let scale = scale.max(1);
+    header(PGM_MAGIC, true, scale).1 + WIDTH * scale * HEIGHT * scale
+}
+
+/// Write frame as binary PBM image into the buffer, returns number of written bytes
+pub fn write_pbm(
+    frame: FrameView<'_>,
+    scale: usize,
+    buf: &mut [u8],
+) -> Result<usize, &'static str> {
+    let scale = scale.max(1);
+    let len = pbm_len(scale);
+    if buf.len() < len {
+        return Err("Buffer too small for the image");
+    }
+    let (head, start) = header(PBM_MAGIC, false, scale);
+    buf[..start].copy_from_slice(&head[..start]);
+    write_packed_rows(frame, scale, &mut buf[start..len]);
+    Ok(len)
+}
+
+/// Write frame as binary PGM image into the buffer, returns number of written bytes
+pub fn write_pgm(
+    frame: FrameView<'_>,
+    scale: usize,
+    buf: &mut [u8],
+) -> Result<usize, &'static str> {
+    let scale = scale.max(1);
+    let len = pgm_len(scale);
+    if buf.len() < len {
+        return Err("Buffer too small for the image");
+    }
+    let (head, start) = header(PGM_MAGIC, true, scale);
+    buf[..start].copy_from_slice(&head[..start]);
+    buf[start..len]
+        .iter_mut()
+        .enumerate()
+        .for_each(|(n, pixel)| {
+            let (x, y) = (n % (WIDTH * scale), n / (WIDTH * scale));
+            *pixel = if is_lit_scaled(frame, x, y, scale) {
+                255
+            } else {
+                0
+            };
+        });
+    Ok(len)
+}
+
+/// Pack rows of scaled frame into bits, lit pixels being 1, from the most
+/// significant bit. Each row is padded to the full byte.
+fn write_packed_rows(frame: FrameView<'_>, scale: usize, buf: &mut [u8]) {
+    let stride = (WIDTH * scale).div_ceil(8);
+    buf.chunks_mut(stride).enumerate().for_each(|(y, row)| {
+        row.iter_mut().enumerate().for_each(|(n, byte)| {
+            *byte = (0..8)
+                .map(|bit| n * 8 + bit)
+                .filter(|&x| x < WIDTH * scale && is_lit_scaled(frame, x, y, scale))
+                .fold(0, |acc, x| acc | 0x80 >> (x % 8));
+        })
+    });
+}
+
+/// Parse binary PBM image into a frame
+///
+/// Dimensions of the image have to be a multiple of the frame's dimensions.
+pub fn parse_pbm(data: &[u8]) -> Result<Frame, &'static str> {
+    if data.get(..2) != Some(PBM_MAGIC.as_bytes()) {
+        return Err("Not a binary PBM image");
+    }
+    let mut pos = 2;
+    let mut token = || -> Result<&[u8], &'static str> {
+        loop {
+            match data.get(pos) {
+                Some(b'#') => {
+                    while data.get(pos).is_some_and(|&c| c != b'\n') {
+                        pos += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => pos += 1,
+                Some(_) => break,
+                None => return Err("Unexpected end of PBM header"),
+            }
+        }
+        let start = pos;
+        while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+            pos += 1;
+        }
+        Ok(&data[start..pos])
+    };
+    let mut number = || -> Result<usize, &'static str> {
+        token().and_then(|t| {
+            core::str::from_utf8(t)
+                .ok()
+                .and_then(|t| t.parse().ok())
+                .ok_or("Invalid PBM dimensions")
+        })
+    };
+
+    let (width, height) = (number()?, number()?);
+    let scale = infer_scale(width, height)?;
+    // single whitespace separates header from the data
+    let stride = width.div_ceil(8);
+    let data = data
+        .get(pos + 1..)
+        .filter(|data| data.len() >= stride * height)
+        .ok_or("PBM image data too short")?;
+    let mut frame = Frame::new();
+    for y in 0..HEIGHT {
+        let row = &data[y * scale * stride..][..stride];
+        for x in 0..WIDTH {
+            let bit = row[x * scale / 8] & 0x80 >> (x * scale % 8) != 0;
+            frame.xor_bit(x, y, bit)?;
+        }
+    }
+    Ok(frame)
+}
+
+fn infer_scale(width: usize, height: usize) -> Result<usize, &'static str> {
+    let scale = width / WIDTH;
+    if scale == 0 || width != WIDTH * scale || height != HEIGHT * scale {
+        Err("Image dimensions are not a multiple of frame dimensions")
+    } else {
+        Ok(scale)
+    }
+}
+
+/// Write frame as PNG image
+///
+/// Image is grayscale with 1 bit per pixel, compressed with stored deflate
+/// blocks to stay independent of compression libraries.
+#[cfg(feature = "std")]
+pub fn write_png<W: std::io::Write>(
+    frame: FrameView<'_>,
+    scale: usize,
+    w: &mut W,
+) -> std::io::Result<()> {
+    use std::vec::Vec;
+
+    let scale = scale.max(1);
+    let (width, height) = ((WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+    let stride = (WIDTH * scale).div_ceil(8);
+
+    // each row is prepended with filter type, 0 meaning none
+    let mut raw = std::vec![0; (stride + 1) * HEIGHT * scale];
+    let mut packed = std::vec![0; stride * HEIGHT * scale];
+    write_packed_rows(frame, scale, &mut packed);
+    raw.chunks_mut(stride + 1)
+        .zip(packed.chunks(stride))
+        .for_each(|(dst, src)| dst[1..].copy_from_slice(src));
+
+    let mut zlib = Vec::with_capacity(raw.len() + raw.len() / 0xFFFF * 5 + 11);
+    zlib.extend_from_slice(&[0x78, 0x01]);
+    let blocks = raw.len().div_ceil(0xFFFF);
+    for (n, block) in raw.chunks(0xFFFF).enumerate() {
+        let len = block.len() as u16;
+        zlib.push((n + 1 == blocks) as u8);
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+
+    let mut ihdr = [0; 13];
+    ihdr[..4].copy_from_slice(&width.to_be_bytes());
+    ihdr[4..8].copy_from_slice(&height.to_be_bytes());
+    // bit depth 1, grayscale, deflate, adaptive filtering, no interlace
+    ihdr[8..].copy_from_slice(&[1, 0, 0, 0, 0]);
+
+    w.write_all(b"\x89PNG\r\n\x1a\n")?;
+    write_png_chunk(w, b"IHDR", &ihdr)?;
+    write_png_chunk(w, b"IDAT", &zlib)?;
+    write_png_chunk(w, b"IEND", &[])
+}
+
+#[cfg(feature = "std")]
+fn write_png_chunk<W: std::io::Write>(
+    w: &mut W,
+    kind: &[u8; 4],
+    data: &[u8],
+) -> std::io::Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    w.write_all(&crc32(kind.iter().chain(data)).to_be_bytes())
+}
+
+#[cfg(feature = "std")]
+fn crc32<'a>(data: impl Iterator<Item = &'a u8>) -> u32 {
+    !data.fold(!0, |crc, &byte| {
+        (0..8).fold(crc ^ byte as u32, |crc, _| {
+            (crc >> 1) ^ (0xEDB8_8320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(feature = "std")]
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+    b << 16 | a
+}
+
+/// Write frame as text, each character holding two vertically adjacent pixels
+///
+/// Uses space, `▀`, `▄` and `█`, rows are terminated by a newline.
+pub fn write_half_blocks<W: fmt::Write>(
+    frame: FrameView<'_>,
+    scale: usize,
+    w: &mut W,
+) -> fmt::Result {
+    let scale = scale.max(1);
+    for y in (0..HEIGHT * scale).step_by(2) {
+        for x in 0..WIDTH * scale {
+            let upper = is_lit_scaled(frame, x, y, scale);
+            let lower = y + 1 < HEIGHT * scale && is_lit_scaled(frame, x, y + 1, scale);
+            w.write_char(match (upper, lower) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            })?;
+        }
+        w.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Write frame as text, each braille character holding 2x4 pixels
+///
+/// Empty cells are written as blank braille pattern (`U+2800`), rows are
+/// terminated by a newline.
+pub fn write_braille<W: fmt::Write>(frame: FrameView<'_>, scale: usize, w: &mut W) -> fmt::Result {
+    let scale = scale.max(1);
+    let (width, height) = (WIDTH * scale, HEIGHT * scale);
+    for y in (0..height).step_by(4) {
+        for x in (0..width).step_by(2) {
+            let dots = BRAILLE_DOTS
+                .iter()
+                .enumerate()
+                .flat_map(|(row, bits)| {
+                    bits.iter()
+                        .enumerate()
+                        .map(move |(col, bit)| (x + col, y + row, bit))
+                })
+                .filter(|&(x, y, _)| x < width && y < height && is_lit_scaled(frame, x, y, scale))
+                .fold(0, |acc, (_, _, bit)| acc | bit);
+            w.write_char(core::char::from_u32(BRAILLE_BASE + dots).ok_or(fmt::Error)?)?;
+        }
+        w.write_char('\n')?;
+    }
+    Ok(())
+}
+
+/// Parse text written by `write_half_blocks` into a frame
+///
+/// Lines may be shorter than the frame, missing characters are unlit, which
+/// keeps fixtures valid after trailing whitespace is trimmed.
+pub fn parse_half_blocks(text: &str) -> Result<Frame, &'static str> {
+    parse_text(text, |c| match c {
+        ' ' => Some([[false], [false]]),
+        '▀' => Some([[true], [false]]),
+        '▄' => Some([[false], [true]]),
+        '█' => Some([[true], [true]]),
+        _ => None,
+    })
+}
+
+/// Parse text written by `write_braille` into a frame
+///
+/// Lines may be shorter than the frame, missing characters are unlit.
+pub fn parse_braille(text: &str) -> Result<Frame, &'static str> {
+    parse_text(text, |c| {
+        let dots = (c as u32)
+            .checked_sub(BRAILLE_BASE)
+            .filter(|&d| d <= 0xFF)?;
+        let mut cell = [[false; 2]; 4];
+        for (row, bits) in BRAILLE_DOTS.iter().enumerate() {
+            for (col, bit) in bits.iter().enumerate() {
+                cell[row][col] = dots & bit != 0;
+            }
+        }
+        Some(cell)
+    })
+}
+
+/// Parse text made of characters holding `W` x `H` pixels each
+fn parse_text<const W: usize, const H: usize>(
+    text: &str,
+    cell: impl Fn(char) -> Option<[[bool; W]; H]>,
+) -> Result<Frame, &'static str> {
+    let lines = text.trim_end_matches('\n').split('\n');
+    let rows = lines.clone().count();
+    let scale = rows * H / HEIGHT;
+    if scale == 0 || rows * H != HEIGHT * scale {
+        return Err("Number of lines is not a multiple of frame height");
+    }
+    let columns = (WIDTH * scale).div_ceil(W);
+    let mut frame = Frame::new();
+    for (row, line) in lines.enumerate() {
+        let line = line.trim_end_matches('\r');
+        if line.chars().count() > columns {
+            return Err("Line is longer than the frame");
+        }
+        for (col, c) in line.chars().enumerate() {
+            let pixels = cell(c).ok_or("Unexpected character")?;
+            for (dy, bits) in pixels.iter().enumerate() {
+                for (dx, &bit) in bits.iter().enumerate() {
+                    let (x, y) = (col * W + dx, row * H + dy);
+                    if bit && x % scale == 0 && y % scale == 0 {
+                        frame.xor_bit(x / scale, y / scale, true)?;
+                    }
+                }
+            }
+        }
+    }
+    Ok(frame)
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+    use std::vec;
+
+    fn frame() -> Frame {
+        let mut frame = Frame::new();
+        [(0, 0), (1, 1), (10, 1), (5, 2), (6, 3), (63, 31)]
+            .iter()
+            .for_each(|&(x, y)| frame.xor_bit(x, y, true).unwrap());
+        frame
+    }
+
+    #[test]
+    fn pbm() {
+        let frame = frame();
+        let mut buf = [0; 9 + 256];
+        assert_eq!(pbm_len(1), buf.len());
+        assert_eq!(write_pbm(frame.view(), 1, &mut buf), Ok(buf.len()));
+        assert!(buf.starts_with(b"P4\n64 32\n"));
+        assert_eq!(&buf[9..], frame.view().as_raw());
+        assert_eq!(parse_pbm(&buf), Ok(frame.clone()));
+
+        let mut buf = vec![0; pbm_len(3)];
+        assert_eq!(write_pbm(frame.view(), 3, &mut buf), Ok(buf.len()));
+        assert!(buf.starts_with(b"P4\n192 96\n"));
+        assert_eq!(&buf[10..13], &[0b1110_0000, 0, 0]);
+        assert_eq!(parse_pbm(&buf), Ok(frame.clone()));
+
+        assert_eq!(
+            write_pbm(frame.view(), 1, &mut buf[..100]),
+            Err("Buffer too small for the image")
+        );
+    }
+
+    #[test]
+    fn parse_pbm_header() {
+        let mut image = b"P4 # comment\n64\t32 ".to_vec();
+        image.extend_from_slice(frame().view().as_raw());
+        assert_eq!(parse_pbm(&image), Ok(frame()));
+
+        assert_eq!(parse_pbm(b"P5\n64 32\n"), Err("Not a binary PBM image"));
+        assert_eq!(parse_pbm(b"P4\n64"), Err("Unexpected end of PBM header"));
+        assert_eq!(parse_pbm(b"P4\n64 x\n"), Err("Invalid PBM dimensions"));
+        assert_eq!(
+            parse_pbm(b"P4\n64 64\n"),
+            Err("Image dimensions are not a multiple of frame dimensions")
+        );
+        assert_eq!(parse_pbm(b"P4\n64 32\n\0"), Err("PBM image data too short"));
+    }
+
+    #[test]
+    fn pgm() {
+        let frame = frame();
+        let mut buf = vec![0; pgm_len(2)];
+        assert_eq!(buf.len(), 14 + 128 * 64);
+        assert_eq!(write_pgm(frame.view(), 2, &mut buf), Ok(buf.len()));
+        assert!(buf.starts_with(b"P5\n128 64\n255\n"));
+        assert_eq!(&buf[14..18], &[255, 255, 0, 0]);
+        assert_eq!(&buf[14 + 128..14 + 128 + 2], &[255, 255]);
+        assert_eq!(buf[buf.len() - 1], 255);
+        assert_eq!(buf[buf.len() - 3], 0);
+    }
+
+    #[test]
+    fn half_blocks() {
+        let frame = frame();
+        let mut text = String::new();
+        write_half_blocks(frame.view(), 1, &mut text).unwrap();
+        let lines: std::vec::Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 16);
+        assert!(lines[0].starts_with("▀▄        ▄ "));
+        assert!(lines[1].starts_with("     ▀▄ "));
+        assert!(lines[15].ends_with(" ▄"));
+        assert_eq!(parse_half_blocks(&text), Ok(frame.clone()));
+
+        // fixtures may have trailing whitespace trimmed
+        let trimmed: String = text
+            .lines()
+            .map(|l| l.trim_end())
+            .flat_map(|l| l.chars().chain(Some('\n')))
+            .collect();
+        assert_eq!(parse_half_blocks(&trimmed), Ok(frame.clone()));
+
+        text.clear();
+        write_half_blocks(frame.view(), 2, &mut text).unwrap();
+        assert_eq!(text.lines().count(), 32);
+        assert!(text.starts_with("██  "));
+        assert_eq!(parse_half_blocks(&text), Ok(frame));
+
+        assert_eq!(
+            parse_half_blocks("█\n"),
+            Err("Number of lines is not a multiple of frame height")
+        );
+        assert_eq!(
+            parse_half_blocks(&"x\n".repeat(16)),
+            Err("Unexpected character")
+        );
+    }
+
+    #[test]
+    fn braille() {
+        let frame = frame();
+        let mut text = String::new();
+        write_braille(frame.view(), 1, &mut text).unwrap();
+        let lines: std::vec::Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 8);
+        assert_eq!(lines[0].chars().count(), 32);
+        assert!(lines[0].starts_with("⠑⠀⠠⡀⠀⠂"));
+        assert!(lines[7].ends_with("⠀⢀"));
+        assert_eq!(parse_braille(&text), Ok(frame.clone()));
+
+        text.clear();
+        write_braille(frame.view(), 3, &mut text).unwrap();
+        assert_eq!(text.lines().count(), 24);
+        assert_eq!(parse_braille(&text), Ok(frame));
+
+        assert_eq!(
+            parse_braille("⠀\n"),
+            Err("Number of lines is not a multiple of frame height")
+        );
+        let long = std::format!("{}\n", "⠀".repeat(33)).repeat(8);
+        assert_eq!(parse_braille(&long), Err("Line is longer than the frame"));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn png() {
+        let frame = frame();
+        let mut png = vec![];
+        write_png(frame.view(), 2, &mut png).unwrap();
+
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n\0\0\0\x0dIHDR\0\0\0\x80\0\0\0\x40\x01\0\0\0\0"));
+        assert!(png.ends_with(b"\0\0\0\0IEND\xae\x42\x60\x82"));
+        assert_eq!(crc32(b"IEND".iter()), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        // IDAT holds single stored block of 64 rows, 1 + 16 bytes each
+        let idat = &png[8 + 25..];
+        assert_eq!(&idat[4..8], b"IDAT");
+        let data = &idat[8 + 2 + 5..][..64 * 17];
+        assert_eq!(&data[..3], &[0, 0b1100_0000, 0]);
+        assert_eq!(&data[17 + 1..17 + 2], &[0b1100_0000]);
+        assert_eq!(&data[2 * 17 + 1..2 * 17 + 2], &[0b0011_0000]);
+        assert_eq!(data[64 * 17 - 1], 0b0000_0011);
+    }
+}
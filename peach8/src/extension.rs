@@ -0,0 +1,154 @@
+//! Native extensions of the instruction set
+//!
+//! Programs written for COSMAC VIP may call machine code routines with `0NNN`,
+//! which `Peach8` can't execute. Instead, integrator can register a native
+//! handler emulating a routine placed at given address.
+//!
+//! Similarly, handlers can be registered for opcode patterns, that are not
+//! defined by `OpCode`. This allows to prototype new instructions, eg. reading
+//! a sensor attached to the board into VX.
+//!
+//! Handlers are registered with `Builder::with_routine` and
+//! `Builder::with_instruction`. Program counter is incremented by `Peach8`
+//! after successful execution of a handler.
+
+use heapless::{consts::U16, Vec};
+
+/// Mutable access to the state of `Peach8` given to handlers
+pub struct Registers<'a> {
+    /// General purpose registers V0 to VF
+    pub v: &'a mut [u8; 16],
+    /// Address register
+    pub i: &'a mut u16,
+    /// Whole address space, including fontset and program
+    pub memory: &'a mut [u8],
+}
+
+/// Handler of machine code subroutine called with `0NNN`
+pub type Routine<C> = fn(&mut C, Registers<'_>) -> Result<(), &'static str>;
+
+/// Handler of custom opcode. Receives raw instruction as its last argument
+pub type Instruction<C> = fn(&mut C, Registers<'_>, u16) -> Result<(), &'static str>;
+
+/// Table of registered handlers
+///
+/// Holds up to 16 routines and 16 instructions.
+pub struct Extensions<C> {
+    routines: Vec<(u16, Routine<C>), U16>,
+    instructions: Vec<(u16, u16, Instruction<C>), U16>,
+}
+
+impl<C> Extensions<C> {
+    pub fn new() -> Self {
+        Self {
+            routines: Vec::new(),
+            instructions: Vec::new(),
+        }
+    }
+
+    /// Register handler for `0NNN` called with `nnn == addr`
+    ///
+    /// Registering a routine for already used address replaces previous handler.
+    pub fn add_routine(&mut self, addr: u16, routine: Routine<C>) -> Result<(), &'static str> {
+        if let Some(entry) = self.routines.iter_mut().find(|(a, _)| *a == addr) {
+            entry.1 = routine;
+            Ok(())
+        } else {
+            self.routines
+                .push((addr, routine))
+                .or(Err("Cannot register routine, table is full"))
+        }
+    }
+
+    /// Register handler for opcodes matching `raw & mask == pattern`
+    ///
+    /// Handlers are consulted only for opcodes that are undefined by `OpCode`,
+    /// in order of registration.
+    pub fn add_instruction(
+        &mut self,
+        mask: u16,
+        pattern: u16,
+        instruction: Instruction<C>,
+    ) -> Result<(), &'static str> {
+        self.instructions
+            .push((mask, pattern & mask, instruction))
+            .or(Err("Cannot register instruction, table is full"))
+    }
+
+    /// Find routine registered at given address
+    pub fn routine(&self, addr: u16) -> Option<Routine<C>> {
+        self.routines
+            .iter()
+            .find(|(a, _)| *a == addr)
+            .map(|&(_, routine)| routine)
+    }
+
+    /// Find first instruction matching raw opcode
+    pub fn instruction(&self, raw: u16) -> Option<Instruction<C>> {
+        self.instructions
+            .iter()
+            .find(|(mask, pattern, _)| raw & mask == *pattern)
+            .map(|&(_, _, instruction)| instruction)
+    }
+}
+
+impl<C> Default for Extensions<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nop(_: &mut (), _: Registers<'_>) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    fn fail(_: &mut (), _: Registers<'_>) -> Result<(), &'static str> {
+        Err("fail")
+    }
+
+    fn nop_instruction(_: &mut (), _: Registers<'_>, _: u16) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    #[test]
+    fn routines() {
+        let mut ext = Extensions::<()>::new();
+        assert!(ext.routine(0x0ABC).is_none());
+
+        ext.add_routine(0x0ABC, nop).unwrap();
+        assert!(ext.routine(0x0ABC).is_some());
+        assert!(ext.routine(0x0ABD).is_none());
+
+        ext.add_routine(0x0ABC, fail).unwrap();
+        let (mut v, mut i, mut memory) = ([0; 16], 0, [0; 4]);
+        let regs = Registers {
+            v: &mut v,
+            i: &mut i,
+            memory: &mut memory,
+        };
+        assert_eq!(ext.routine(0x0ABC).unwrap()(&mut (), regs), Err("fail"));
+
+        for addr in 1..16 {
+            ext.add_routine(addr, nop).unwrap();
+        }
+        assert_eq!(
+            ext.add_routine(0x0FFF, nop),
+            Err("Cannot register routine, table is full")
+        );
+    }
+
+    #[test]
+    fn instructions() {
+        let mut ext = Extensions::<()>::new();
+        ext.add_instruction(0xF0FF, 0xF0A0, nop_instruction)
+            .unwrap();
+
+        assert!(ext.instruction(0xF3A0).is_some());
+        assert!(ext.instruction(0xF3A1).is_none());
+        assert!(ext.instruction(0xE3A0).is_none());
+    }
+}
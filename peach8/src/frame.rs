@@ -1,9 +1,17 @@
 use bitvec::prelude::*;
-use embedded_graphics::{image::ImageRaw, pixelcolor::BinaryColor};
+#[cfg(feature = "embedded-graphics")]
+use embedded_graphics::{
+    image::ImageRaw,
+    pixelcolor::{BinaryColor, PixelColor},
+};
+
+#[cfg(feature = "embedded-graphics")]
+use crate::drawable::FrameDrawable;
 
 pub const WIDTH: usize = 64;
 pub const HEIGHT: usize = 32;
 pub(crate) const MEM_LENGTH: usize = WIDTH * HEIGHT / 8;
+const ROW_LENGTH: usize = WIDTH / 8;
 
 /// An opaque struct holding frame of Peach8 display
 #[derive(Clone, PartialEq, Eq, Hash, Debug)]
@@ -26,7 +34,7 @@ pub struct FrameView<'a>(&'a [u8; MEM_LENGTH]);
 
 impl<'a> FrameView<'a> {
     /// View the raw memory of a frame
-    pub fn as_raw(&self) -> &[u8] {
+    pub fn as_raw(&self) -> &'a [u8] {
         self.0
     }
 
@@ -37,15 +45,20 @@ impl<'a> FrameView<'a> {
 
     /// Access frame's bits by indexes
     pub fn get_bit(&self, x: usize, y: usize) -> Option<&bool> {
-        self.iter_rows_as_bitslices()
-            .nth(y)
-            .map(|row| row.get(x))
-            .flatten()
+        self.0
+            .get(y * ROW_LENGTH..(y + 1) * ROW_LENGTH)
+            .and_then(|row| row.view_bits::<Msb0>().get(x))
+    }
+
+    /// Whether the pixel is lit, it must be inside of the frame
+    pub fn is_lit(&self, x: usize, y: usize) -> bool {
+        debug_assert!(x < WIDTH && y < HEIGHT, "Pixel outside of the frame");
+        self.0[y * ROW_LENGTH + x / 8] & 0x80 >> (x % 8) != 0
     }
 
     /// Get iterator over rows in a form of a `BitSlice`s
     pub fn iter_rows_as_bitslices(&self) -> impl Iterator<Item = &'a BitSlice<Msb0, u8>> {
-        self.0.chunks(WIDTH / 8).map(|row| row.view_bits::<_>())
+        self.0.chunks(ROW_LENGTH).map(|row| row.view_bits::<_>())
     }
 
     /// Iter frame pixelwise (each pixel in row for each row in frame) after scaling it
@@ -58,9 +71,9 @@ impl<'a> FrameView<'a> {
             .zip(core::iter::repeat(scale))
             .map(move |(row, scale)| {
                 row.iter()
-                    .flat_map(move |bit| core::iter::repeat(bit).take(scale))
+                    .flat_map(move |bit| core::iter::repeat_n(bit, scale))
             })
-            .flat_map(move |row| core::iter::repeat(row).take(scale))
+            .flat_map(move |row| core::iter::repeat_n(row, scale))
     }
 
     /// Get `ImageRaw` structure from frame's data
@@ -68,10 +81,16 @@ impl<'a> FrameView<'a> {
     pub fn as_raw_image(&self) -> ImageRaw<'_, BinaryColor> {
         ImageRaw::new(self.as_raw(), WIDTH as u32, HEIGHT as u32)
     }
+
+    /// Get `Drawable` drawing lit pixels with `on` colour and the rest with `off`
+    #[cfg(feature = "embedded-graphics")]
+    pub fn drawable<C: PixelColor>(self, on: C, off: C) -> FrameDrawable<'a, C> {
+        FrameDrawable::new(self, on, off)
+    }
 }
 
 impl Frame {
-    pub(crate) fn new() -> Self {
+    pub(crate) const fn new() -> Self {
         Self([0; MEM_LENGTH])
     }
 
@@ -80,20 +99,48 @@ impl Frame {
         FrameView(&self.0)
     }
 
+    pub(crate) fn as_raw_mut(&mut self) -> &mut [u8] {
+        &mut self.0
+    }
+
     pub(crate) fn xor_bit(&mut self, x: usize, y: usize, val: bool) -> Result<(), &'static str> {
-        self.iter_rows_as_bitslices_mut()
-            .nth(y)
-            .map(|row| row.get_mut(x).map(|mut bit| *bit ^= val))
-            .flatten()
+        self.0
+            .get_mut(y * ROW_LENGTH..(y + 1) * ROW_LENGTH)
+            .and_then(|row| row.view_bits_mut::<Msb0>().get_mut(x))
+            .map(|mut bit| *bit ^= val)
             .ok_or("Pixel index out of bounds")
     }
 
-    pub(crate) fn iter_rows_as_bitslices_mut(
+    /// XOR sprite into the frame with its top-left corner at (x, y), returns
+    /// whether any lit pixel was turned off
+    ///
+    /// Each row of the sprite is shifted into position and blitted into at
+    /// most two bytes of the frame. Parts of the sprite beyond the right or
+    /// bottom edge are clipped.
+    pub(crate) fn xor_sprite(
         &mut self,
-    ) -> impl Iterator<Item = &mut BitSlice<Msb0, u8>> {
-        self.0
-            .chunks_mut(WIDTH / 8)
-            .map(|row| row.view_bits_mut::<_>())
+        x: usize,
+        y: usize,
+        sprite: &[u8],
+    ) -> Result<bool, &'static str> {
+        if x >= WIDTH || y >= HEIGHT {
+            return Err("Pixel index out of bounds");
+        }
+        let (column, shift) = (x / 8, x % 8);
+        let mut collision = false;
+        for (row, &bits) in self.0.chunks_exact_mut(ROW_LENGTH).skip(y).zip(sprite) {
+            let mut blit = |byte: &mut u8, mask: u8| {
+                collision |= *byte & mask != 0;
+                *byte ^= mask;
+            };
+            blit(&mut row[column], bits >> shift);
+            if shift > 0 {
+                if let Some(byte) = row.get_mut(column + 1) {
+                    blit(byte, bits << (8 - shift));
+                }
+            }
+        }
+        Ok(collision)
     }
 }
 
@@ -104,13 +151,6 @@ impl<'a> FrameView<'a> {
     }
 }
 
-#[cfg(test)]
-impl Frame {
-    pub(crate) fn as_raw_mut(&mut self) -> &mut [u8] {
-        &mut self.0
-    }
-}
-
 #[cfg(test)]
 mod frame_test {
     use super::*;
@@ -125,6 +165,16 @@ mod frame_test {
         assert_eq!(frame.view().get_bit(0, 1), Some(&false));
     }
 
+    #[test]
+    fn is_lit() {
+        let mut frame = Frame::new();
+        frame.as_raw_mut()[ROW_LENGTH + 1] = 0b0100_0000;
+
+        assert!(frame.view().is_lit(9, 1));
+        assert!(!frame.view().is_lit(8, 1));
+        assert!(!frame.view().is_lit(9, 0));
+    }
+
     #[test]
     fn xor_bit() {
         let mut frame = Frame::new();
@@ -137,4 +187,25 @@ mod frame_test {
         frame.xor_bit(0, 0, true).unwrap();
         assert_eq!(frame.view().get_bit(0, 0), Some(&false));
     }
+
+    #[test]
+    fn xor_sprite() {
+        let mut frame = Frame::new();
+        assert_eq!(frame.xor_sprite(3, 0, &[0xFF, 0x81]), Ok(false));
+        assert_eq!(&frame.view().as_raw()[..2], &[0x1F, 0xE0]);
+        assert_eq!(&frame.view().as_raw()[8..10], &[0x10, 0x20]);
+
+        assert_eq!(frame.xor_sprite(8, 1, &[0x80]), Ok(false));
+        assert_eq!(frame.xor_sprite(10, 0, &[0x80]), Ok(true));
+        assert_eq!(&frame.view().as_raw()[..2], &[0x1F, 0xC0]);
+        assert_eq!(frame.view().get_bit(8, 1), Some(&true));
+
+        assert_eq!(frame.xor_sprite(60, 30, &[0xFF, 0xFF, 0xFF]), Ok(false));
+        assert_eq!(frame.view().as_raw()[8 * 30 + 7], 0x0F);
+        assert_eq!(frame.view().as_raw()[8 * 31 + 7], 0x0F);
+        assert_eq!(
+            frame.xor_sprite(64, 0, &[0xFF]),
+            Err("Pixel index out of bounds")
+        );
+    }
 }
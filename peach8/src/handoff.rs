@@ -0,0 +1,321 @@
+//! Handoff of frames between threads
+//!
+//! `TripleBuffer` passes frames from emulation to a render thread, or from an
+//! interrupt to the main loop, without locks. Its `Writer` publishes frames
+//! without ever blocking, and its `Reader` always gets the latest complete
+//! frame, with sequence number counting published frames. Frames published
+//! between two reads are skipped.
+//!
+//! Three slots are shared: the writer owns one to fill, the reader owns one
+//! to view, and the third one holds the latest published frame. Publishing
+//! and reading only swap indices of slots, so neither side waits for the other.
+//!
+//! `Handoff` wraps a `Context` and publishes each frame it receives.
+//!
+//! ```ignore
+//! static FRAMES: TripleBuffer = TripleBuffer::new();
+//!
+//! let (writer, mut reader) = FRAMES.split().unwrap();
+//! let chip = Builder::new()
+//!     .with_context(Handoff::new(ctx, writer))
+//!     .with_program(rom)
+//!     .build()?;
+//! std::thread::spawn(move || emulate(chip));
+//! loop {
+//!     let (sequence, frame) = reader.latest();
+//!     if sequence != drawn {
+//!         draw(frame);
+//!         drawn = sequence;
+//!     }
+//! }
+//! ```
+
+use core::cell::UnsafeCell;
+use core::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+
+use crate::context::Context;
+use crate::frame::{Frame, FrameView};
+use crate::peach::State;
+use crate::storage::Storage;
+
+/// Flag of the shared index, set when it holds a frame not read yet
+const FRESH: u8 = 0b100;
+const INDEX: u8 = 0b011;
+
+struct Slot {
+    sequence: u32,
+    frame: Frame,
+}
+
+/// Three slots of frames shared by a `Writer` and a `Reader`
+pub struct TripleBuffer {
+    slots: [UnsafeCell<Slot>; 3],
+    /// Index of the slot which is owned by neither side
+    shared: AtomicU8,
+    split: AtomicBool,
+}
+
+// Slots are accessed only by the side owning their index, and ownership of
+// a slot changes hands by swapping `shared`, which orders the accesses.
+unsafe impl Sync for TripleBuffer {}
+
+impl TripleBuffer {
+    pub const fn new() -> Self {
+        Self {
+            slots: [
+                UnsafeCell::new(Slot::new()),
+                UnsafeCell::new(Slot::new()),
+                UnsafeCell::new(Slot::new()),
+            ],
+            shared: AtomicU8::new(1),
+            split: AtomicBool::new(false),
+        }
+    }
+
+    /// Split into writing and reading side, `None` if it was already split
+    pub fn split(&self) -> Option<(Writer<'_>, Reader<'_>)> {
+        if self.split.swap(true, Ordering::AcqRel) {
+            return None;
+        }
+        let writer = Writer {
+            buffer: self,
+            index: 0,
+            sequence: 0,
+        };
+        let reader = Reader {
+            buffer: self,
+            index: 2,
+        };
+        Some((writer, reader))
+    }
+}
+
+impl Default for TripleBuffer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Slot {
+    const fn new() -> Self {
+        Self {
+            sequence: 0,
+            frame: Frame::new(),
+        }
+    }
+}
+
+/// Publishing side of a `TripleBuffer`
+pub struct Writer<'a> {
+    buffer: &'a TripleBuffer,
+    index: u8,
+    sequence: u32,
+}
+
+impl Writer<'_> {
+    /// Publish a copy of the frame, replacing one not read yet
+    pub fn publish(&mut self, frame: FrameView<'_>) {
+        self.sequence = self.sequence.wrapping_add(1);
+        // SAFETY: the slot at `index` is owned by the writer
+        let slot = unsafe { &mut *self.buffer.slots[self.index as usize].get() };
+        slot.sequence = self.sequence;
+        slot.frame.as_raw_mut().copy_from_slice(frame.as_raw());
+        let previous = self
+            .buffer
+            .shared
+            .swap(self.index | FRESH, Ordering::AcqRel);
+        self.index = previous & INDEX;
+    }
+
+    /// Number of frames published so far
+    pub fn sequence(&self) -> u32 {
+        self.sequence
+    }
+}
+
+/// Reading side of a `TripleBuffer`
+pub struct Reader<'a> {
+    buffer: &'a TripleBuffer,
+    index: u8,
+}
+
+impl Reader<'_> {
+    /// Whether a frame was published since the last `latest`
+    pub fn has_new(&self) -> bool {
+        self.buffer.shared.load(Ordering::Relaxed) & FRESH != 0
+    }
+
+    /// Latest published frame with its sequence number
+    ///
+    /// Sequence number is 0 with a blank frame until the first publish.
+    pub fn latest(&mut self) -> (u32, FrameView<'_>) {
+        if self.has_new() {
+            let previous = self.buffer.shared.swap(self.index, Ordering::AcqRel);
+            self.index = previous & INDEX;
+        }
+        // SAFETY: the slot at `index` is owned by the reader
+        let slot = unsafe { &*self.buffer.slots[self.index as usize].get() };
+        (slot.sequence, slot.frame.view())
+    }
+}
+
+/// `Context` adapter publishing each frame to a `TripleBuffer`
+pub struct Handoff<'a, C> {
+    pub inner: C,
+    writer: Writer<'a>,
+}
+
+impl<'a, C: Context> Handoff<'a, C> {
+    pub fn new(inner: C, writer: Writer<'a>) -> Self {
+        Self { inner, writer }
+    }
+
+    /// Number of frames published so far
+    pub fn sequence(&self) -> u32 {
+        self.writer.sequence()
+    }
+
+    /// Drop the adapter, releasing wrapped context and writer
+    pub fn release(self) -> (C, Writer<'a>) {
+        (self.inner, self.writer)
+    }
+}
+
+impl<C: Context> Context for Handoff<'_, C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.writer.publish(frame);
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+
+    /// Frame with every byte set to `byte`
+    fn filled(byte: u8) -> Frame {
+        let mut frame = Frame::new();
+        frame.as_raw_mut().iter_mut().for_each(|b| *b = byte);
+        frame
+    }
+
+    #[test]
+    fn splits_once() {
+        let buffer = TripleBuffer::new();
+        assert!(buffer.split().is_some());
+        assert!(buffer.split().is_none());
+    }
+
+    #[test]
+    fn reads_latest_frame() {
+        let buffer = TripleBuffer::new();
+        let (mut writer, mut reader) = buffer.split().unwrap();
+        assert!(!reader.has_new());
+        assert_eq!(reader.latest(), (0, Frame::new().view()));
+
+        writer.publish(filled(1).view());
+        writer.publish(filled(2).view());
+        assert!(reader.has_new());
+        assert_eq!(reader.latest(), (2, filled(2).view()));
+        // nothing new, the same frame again
+        assert!(!reader.has_new());
+        assert_eq!(reader.latest(), (2, filled(2).view()));
+
+        writer.publish(filled(3).view());
+        assert_eq!(reader.latest(), (3, filled(3).view()));
+        assert_eq!(writer.sequence(), 3);
+    }
+
+    #[test]
+    fn viewed_frame_is_not_overwritten() {
+        let buffer = TripleBuffer::new();
+        let (mut writer, mut reader) = buffer.split().unwrap();
+        writer.publish(filled(1).view());
+        let (sequence, frame) = reader.latest();
+        for byte in 2..10 {
+            writer.publish(filled(byte).view());
+        }
+        assert_eq!((sequence, frame), (1, filled(1).view()));
+        assert_eq!(reader.latest(), (9, filled(9).view()));
+    }
+
+    #[test]
+    fn frames_are_never_torn() {
+        let buffer = TripleBuffer::new();
+        let (mut writer, mut reader) = buffer.split().unwrap();
+        thread::scope(|scope| {
+            scope.spawn(move || {
+                for sequence in 1..=20_000u32 {
+                    writer.publish(filled(sequence as u8).view());
+                }
+            });
+            let mut last = 0;
+            while last < 20_000 {
+                let (sequence, frame) = reader.latest();
+                assert!(sequence >= last);
+                assert!(
+                    frame.as_raw().iter().all(|&b| b == sequence as u8),
+                    "torn frame {}",
+                    sequence
+                );
+                last = sequence;
+            }
+        });
+    }
+
+    #[test]
+    fn handoff_publishes_each_frame() {
+        let buffer = TripleBuffer::new();
+        let (writer, mut reader) = buffer.split().unwrap();
+        // I = digit 0, draw it at (0, 0), loop
+        let program = [0xF0, 0x29, 0xD0, 0x05, 0x12, 0x04];
+        let mut chip = Builder::new()
+            .with_context(Handoff::new(TestingContext::new(0), writer))
+            .with_program(&program)
+            .build()
+            .unwrap();
+        thread::scope(|scope| {
+            scope.spawn(|| {
+                for _ in 0..3 {
+                    chip.tick_chip().unwrap();
+                }
+            });
+        });
+        let (sequence, frame) = reader.latest();
+        assert_eq!(sequence, 3);
+        assert_eq!(frame.as_raw()[0], 0xF0);
+        assert_eq!(chip.ctx.sequence(), 3);
+    }
+}
@@ -30,6 +30,27 @@
 //! with 60Hz frequency, and `tick_chip` should be called with around 500Hz
 //! frequency.
 //!
+//! `runner::Runner` paces both by any monotonic `runner::Clock`, eg. a free
+//! running hardware timer or `runner::StdClock` on hosts. It catches up at
+//! most a bounded time after stalls, reporting the rest as dropped, and can
+//! run at a multiple of normal speed.
+//!
+//! With `async` feature, `Peach8::run` is an async loop for executors like
+//! `embassy`, awaiting deadlines of ticks and key events of an `asynch::Timer`.
+//!
+//! `schedule::Schedule` interleaves ticks of chip and timers at exact frequencies,
+//! chip first on ties. `Runner`, `Peach8::run` and `tas::Session` all follow it.
+//!
+//! `audio::Synth` renders the sound timer as a square wave into PCM buffers given
+//! by the caller, with band-limited edges and attack and release ramps against
+//! clicks. `audio::Beeper` context adapter feeds it with changes of sound,
+//! timestamped by cycles of the chip.
+//!
+//! SCHIP `FX75` and `FX85` save and load RPL user flags, eg. high scores, when
+//! `Context::storage` provides a `storage::Storage`. Flags are keyed by hash of the
+//! program and kept in RAM by `storage::MemoryStorage`, in files by
+//! `storage::FileStorage` or in two pages of flash by `storage::FlashStorage`.
+//!
 //! Emulation cycle (`tick_chip`) is as follows:
 //! - Get input (`Context::get_keys`),
 //! - Execute next instruction,
@@ -45,26 +66,111 @@
 //!
 //! `Peach8` can handle thread-safety on targets, where there is a support for
 //! atomic operations on `u8`. This is handled by `atomic` feature, enabled by
-//! default. On platforms where there is no such support, eg. `thumbv6em-none-eabi`,
-//! implementation should ensure that those methods won`t interrupt eachother.
+//! default. On platforms where there is no such support, eg. `thumbv6m-none-eabi`,
+//! `critical-section` feature guards timers with critical sections instead.
+//! Without any of them implementation should ensure that those methods won`t
+//! interrupt eachother.
+//!
+//! Timers placed in a static `timer::Timers` and passed to `Builder::with_timers`
+//! can be ticked straight from a 60Hz interrupt by `timer::TimerHandle`, while
+//! `tick_chip` runs in the main loop. The handle returns the state of sound timer
+//! instead of calling `Context`, and the main loop passes each state to
+//! `Peach8::vblank`, which switches sound and calls `Context::on_vblank` as
+//! `tick_timers` would.
+//!
+//! `Peach8` is `Sync` whenever implementation of `Context` is. Races of timers are
+//! checked with loom, by tests built with `--cfg loom`.
+//!
+//! `handoff::TripleBuffer` passes frames to a render thread without locks: the
+//! emulation side publishes them through `handoff::Handoff` context adapter
+//! without blocking, and the reader always gets the latest complete frame with
+//! its sequence number. It needs `atomic` feature, and can be placed in a static
+//! on `no_std` targets.
 //!
-//! Implementation of `Context` trait also have to be `Sync` for `Peach8` to be sync.
+//! # Debugging
+//! `Context::on_instruction` is called before each executed instruction.
+//! `trace::Tracer` wraps a context and uses it to emit an execution trace,
+//! which can be compared against traces of other emulators with `trace::diff`,
+//! eg. by `trace-diff` tool from `tools` crate.
+//!
+//! `profile::Profiler` gathers hot spots, costs of subroutines and drawing
+//! statistics of each frame, which are finished by `Context::on_vblank`.
+//! `access::AccessMap` records which bytes of memory were executed, read,
+//! written or drawn as sprites.
+//! `sprites::SpriteCatalogue` captures drawn sprites, which can be rendered
+//! or listed in Octo syntax.
+//!
+//! `export` writes frames as PBM, PGM or PNG images and as text made of
+//! half-block or braille characters. PBM and text can be parsed back into a
+//! `Frame`, eg. to serve as fixtures of tests.
+//! `scale::Upscaler` enlarges frames with edge-preserving pixel-art algorithms,
+//! Scale2x, Scale3x and Eagle.
+//! `palette::Palette` colours frames for RGB displays, filling RGB565, RGB888
+//! or ARGB8888 buffers, or adapting them into `embedded-graphics` images.
+//! `persistence::Persistence` emulates fading phosphor, reducing flicker of
+//! sprites redrawn with XOR.
+//! `FrameView::drawable` draws frames onto any `embedded-graphics` display in
+//! one call, with chosen scale, position and colours.
+//!
+//! `movie::Recorder` writes keys, random values and ends of frames into a compact
+//! movie with a header holding hash of the program, and `movie::Replay` feeds it
+//! back, reproducing the exact sequence of frames, eg. for bug reports or attract
+//! modes.
+//!
+//! `tas::Session` plays a program frame by frame from an editable list of keys of
+//! each frame, marks lag frames in which the program never read keys, and branches
+//! from save points. Its inputs are imported from and exported as movies. It
+//! requires `std` feature.
+//!
+//! `std` feature enables helpers that require standard library, eg. `trace::IoSink`
+//! or `export::write_png`.
+//!
+//! `testing` feature publishes `testing` module with contexts for tests, eg.
+//! with scripted keys or recording calls, ASCII art assertions of frames, and
+//! `VirtualClock` running chip and timers at exact frequencies in simulated time.
 //!
 //! # Examples:
 //! coming soon...
 
 #![no_std]
+#[cfg(any(test, feature = "std"))]
+extern crate std;
+
+pub mod access;
+#[cfg(feature = "async")]
+pub mod asynch;
+pub mod audio;
 pub mod builder;
 pub mod context;
+#[cfg(feature = "embedded-graphics")]
+pub mod drawable;
+pub mod export;
+pub mod extension;
 pub mod frame;
+#[cfg(feature = "atomic")]
+pub mod handoff;
+pub mod movie;
 pub mod opcode;
+pub mod palette;
 pub mod peach;
-pub(crate) mod timer;
-pub(crate) mod utils;
+pub mod persistence;
+pub mod profile;
+pub mod rng;
+pub mod runner;
+pub mod scale;
+pub mod schedule;
+pub mod sprites;
+pub mod storage;
+#[cfg(feature = "std")]
+pub mod tas;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod timer;
+pub mod trace;
 
 pub use builder::Builder;
 pub use context::Context;
 #[cfg(feature = "embedded-graphics")]
 pub use embedded_graphics;
 pub use frame::{Frame, FrameView};
-pub use peach::Peach8;
+pub use peach::{Peach8, Snapshot, State};
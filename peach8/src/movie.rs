@@ -0,0 +1,527 @@
+//! Recording and deterministic replay of input
+//!
+//! `Recorder` wraps a `Context` and writes a movie of everything that feeds
+//! emulation from outside: keys returned by `Context::get_keys`, values of
+//! `Context::gen_random`, and ends of frames at `Context::on_vblank`. `Replay`
+//! feeds a movie back instead of the wrapped context, which reproduces the
+//! exact sequence of frames, eg. for bug reports or attract modes.
+//!
+//! A movie starts with a header of 14 bytes: magic `P8MV`, version, `Sampling`
+//! and `RomHash` of the program in little endian. Records follow, each
+//! starting with a tag byte:
+//! - `0x00..=0x7F` - the same keys as before, for `tag + 1` more ticks,
+//! - `0x80` - keys as a little endian bitmask, key 0 in the lowest bit,
+//! - `0x81` - a random value,
+//! - `0x82` - end of a frame.
+//!
+//! Ticks of chip and timers have to interleave the same way during recording
+//! and replay, as with `runner::Runner` or `testing::VirtualClock` at the same
+//! frequencies. `Replay::error` reports any divergence.
+
+use crate::context::Context;
+use crate::frame::FrameView;
+use crate::peach::State;
+use crate::storage::{RomHash, Storage};
+use crate::trace::Sink;
+
+pub const MAGIC: [u8; 4] = *b"P8MV";
+pub const VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 14;
+
+const REPEAT_MAX: u8 = 0x7F;
+pub(crate) const TAG_KEYS: u8 = 0x80;
+pub(crate) const TAG_RANDOM: u8 = 0x81;
+pub(crate) const TAG_VBLANK: u8 = 0x82;
+
+/// How often keys are sampled
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Sampling {
+    /// Keys of each tick of chip
+    Tick,
+    /// Keys of the first tick of each frame, held for the whole frame. Keys
+    /// are written only when they change
+    Frame,
+}
+
+/// Header of a movie
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Header {
+    pub sampling: Sampling,
+    pub rom: RomHash,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> [u8; HEADER_SIZE] {
+        let mut bytes = [0; HEADER_SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = VERSION;
+        bytes[5] = match self.sampling {
+            Sampling::Tick => 0,
+            Sampling::Frame => 1,
+        };
+        bytes[6..].copy_from_slice(&self.rom.0.to_le_bytes());
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < HEADER_SIZE || bytes[..4] != MAGIC {
+            return Err("Not a movie");
+        }
+        if bytes[4] != VERSION {
+            return Err("Unsupported version of movie");
+        }
+        let sampling = match bytes[5] {
+            0 => Sampling::Tick,
+            1 => Sampling::Frame,
+            _ => return Err("Unknown sampling of movie"),
+        };
+        let mut rom = [0; 8];
+        rom.copy_from_slice(&bytes[6..HEADER_SIZE]);
+        Ok(Self {
+            sampling,
+            rom: RomHash(u64::from_le_bytes(rom)),
+        })
+    }
+}
+
+pub(crate) fn to_mask(keys: [bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .fold(0, |mask, (n, &key)| mask | (key as u16) << n)
+}
+
+pub(crate) fn to_keys(mask: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    keys.iter_mut()
+        .enumerate()
+        .for_each(|(n, key)| *key = mask & 1 << n != 0);
+    keys
+}
+
+/// `Context` adapter writing a movie of its input into a `Sink`
+pub struct Recorder<C, S: Sink> {
+    pub inner: C,
+    sink: S,
+    sampling: Sampling,
+    last: Option<u16>,
+    /// Samples of unchanged keys not written yet
+    repeats: u8,
+    /// Whether keys were sampled in the current frame
+    sampled: bool,
+}
+
+impl<C: Context, S: Sink> Recorder<C, S> {
+    /// Start a movie of a program, writing its header
+    pub fn new(inner: C, mut sink: S, rom: RomHash, sampling: Sampling) -> Self {
+        sink.write(&Header { sampling, rom }.to_bytes());
+        Self {
+            inner,
+            sink,
+            sampling,
+            last: None,
+            repeats: 0,
+            sampled: false,
+        }
+    }
+
+    /// Drop the recorder, finishing the movie and releasing wrapped context and sink
+    pub fn release(mut self) -> (C, S) {
+        self.flush();
+        (self.inner, self.sink)
+    }
+
+    fn flush(&mut self) {
+        if self.repeats > 0 {
+            self.sink.write(&[self.repeats - 1]);
+            self.repeats = 0;
+        }
+    }
+
+    fn record_keys(&mut self, mask: u16) {
+        if self.last == Some(mask) {
+            self.repeats += 1;
+            if self.repeats == REPEAT_MAX + 1 {
+                self.flush();
+            }
+        } else {
+            self.flush();
+            let [low, high] = mask.to_le_bytes();
+            self.sink.write(&[TAG_KEYS, low, high]);
+            self.last = Some(mask);
+        }
+    }
+}
+
+impl<C: Context, S: Sink> Context for Recorder<C, S> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        let keys = self.inner.get_keys();
+        match (self.sampling, self.sampled, self.last) {
+            (Sampling::Frame, true, Some(last)) => to_keys(last),
+            (Sampling::Frame, _, last) => {
+                self.sampled = true;
+                let mask = to_mask(keys);
+                if last != Some(mask) {
+                    let [low, high] = mask.to_le_bytes();
+                    self.sink.write(&[TAG_KEYS, low, high]);
+                    self.last = Some(mask);
+                }
+                keys
+            }
+            (Sampling::Tick, _, _) => {
+                self.record_keys(to_mask(keys));
+                keys
+            }
+        }
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        let value = self.inner.gen_random();
+        self.flush();
+        self.sink.write(&[TAG_RANDOM, value]);
+        value
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.flush();
+        self.sink.write(&[TAG_VBLANK]);
+        self.sampled = false;
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+/// `Context` adapter feeding input from a movie instead of wrapped context
+///
+/// Input of the wrapped context is ignored, other calls are forwarded. After
+/// the end of the movie, or after an error, no keys are pressed and random
+/// values are 0.
+pub struct Replay<'a, C> {
+    pub inner: C,
+    header: Header,
+    movie: &'a [u8],
+    /// Offset of the next record
+    position: usize,
+    keys: u16,
+    repeats: u8,
+    sampled: bool,
+    error: Option<&'static str>,
+}
+
+impl<'a, C: Context> Replay<'a, C> {
+    /// Replay a movie, which must have been recorded for the program `rom`
+    pub fn new(inner: C, movie: &'a [u8], rom: RomHash) -> Result<Self, &'static str> {
+        let header = Header::parse(movie)?;
+        if header.rom != rom {
+            return Err("Movie was recorded for another program");
+        }
+        Ok(Self {
+            inner,
+            header,
+            movie,
+            position: HEADER_SIZE,
+            keys: 0,
+            repeats: 0,
+            sampled: false,
+            error: None,
+        })
+    }
+
+    pub fn header(&self) -> Header {
+        self.header
+    }
+
+    /// Whether all records were replayed
+    pub fn is_finished(&self) -> bool {
+        self.repeats == 0 && self.position >= self.movie.len()
+    }
+
+    /// First divergence of emulation from the movie, or a malformed record
+    pub fn error(&self) -> Option<&'static str> {
+        self.error
+    }
+
+    /// Replay the movie again from its start
+    pub fn rewind(&mut self) {
+        self.position = HEADER_SIZE;
+        self.keys = 0;
+        self.repeats = 0;
+        self.sampled = false;
+        self.error = None;
+    }
+
+    /// Drop the adapter, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    /// Take the next record if it has `tag`, returning its payload
+    fn take(&mut self, tag: u8, len: usize) -> Option<&'a [u8]> {
+        if self.error.is_some() {
+            return None;
+        }
+        let movie = self.movie;
+        let found = *movie.get(self.position)?;
+        if found != tag {
+            self.error = Some(match found {
+                0x83..=0xFF => "Unknown record of movie",
+                _ => "Emulation diverged from movie",
+            });
+            return None;
+        }
+        match movie.get(self.position + 1..self.position + 1 + len) {
+            Some(payload) => {
+                self.position += 1 + len;
+                Some(payload)
+            }
+            None => {
+                self.error = Some("Movie ends inside a record");
+                None
+            }
+        }
+    }
+
+    /// Check that all repeated keys were replayed before another record
+    fn check_repeats(&mut self) {
+        if self.repeats > 0 {
+            self.error.get_or_insert("Emulation diverged from movie");
+        }
+    }
+
+    fn next_tag(&self) -> Option<u8> {
+        match self.error {
+            Some(_) => None,
+            None => self.movie.get(self.position).copied(),
+        }
+    }
+
+    fn replay_keys(&mut self) {
+        if self.repeats > 0 {
+            self.repeats -= 1;
+            return;
+        }
+        match self.next_tag() {
+            Some(tag) if tag <= REPEAT_MAX => {
+                self.position += 1;
+                self.repeats = tag;
+            }
+            _ => {
+                let payload = self.take(TAG_KEYS, 2);
+                self.keys = payload.map_or(0, |p| u16::from_le_bytes([p[0], p[1]]));
+            }
+        }
+    }
+}
+
+impl<C: Context> Context for Replay<'_, C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        match self.header.sampling {
+            Sampling::Tick => self.replay_keys(),
+            Sampling::Frame if !self.sampled => {
+                self.sampled = true;
+                if self.next_tag() == Some(TAG_KEYS) {
+                    self.replay_keys();
+                }
+            }
+            Sampling::Frame => (),
+        }
+        if self.error.is_some() {
+            self.keys = 0;
+        }
+        to_keys(self.keys)
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.check_repeats();
+        self.take(TAG_RANDOM, 1).map_or(0, |payload| payload[0])
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.check_repeats();
+        self.take(TAG_VBLANK, 0);
+        self.sampled = false;
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    use crate::testing::TestingContext;
+    use crate::trace::IoSink;
+
+    const ROM: RomHash = RomHash(0x0123_4567_89AB_CDEF);
+
+    fn keys(mask: u16) -> [bool; 16] {
+        to_keys(mask)
+    }
+
+    fn recorder(sampling: Sampling) -> Recorder<TestingContext, IoSink<Vec<u8>>> {
+        Recorder::new(TestingContext::new(0), IoSink(Vec::new()), ROM, sampling)
+    }
+
+    #[test]
+    fn header() {
+        let header = Header {
+            sampling: Sampling::Frame,
+            rom: ROM,
+        };
+        let bytes = header.to_bytes();
+        assert_eq!(
+            bytes,
+            [b'P', b'8', b'M', b'V', 1, 1, 0xEF, 0xCD, 0xAB, 0x89, 0x67, 0x45, 0x23, 0x01]
+        );
+        assert_eq!(Header::parse(&bytes), Ok(header));
+        assert_eq!(Header::parse(&bytes[..13]), Err("Not a movie"));
+
+        let mut newer = bytes;
+        newer[4] = 2;
+        assert_eq!(Header::parse(&newer), Err("Unsupported version of movie"));
+    }
+
+    #[test]
+    fn keys_are_run_length_encoded() {
+        let mut recorder = recorder(Sampling::Tick);
+        recorder.inner.set_key(3);
+        for _ in 0..200 {
+            recorder.get_keys();
+        }
+        recorder.gen_random();
+        recorder.get_keys();
+        recorder.on_vblank();
+        let (_, IoSink(movie)) = recorder.release();
+        let random = movie[HEADER_SIZE + 6];
+        assert_eq!(
+            movie[HEADER_SIZE..],
+            [TAG_KEYS, 0x08, 0x00, 0x7F, 70, TAG_RANDOM, random, 0, TAG_VBLANK]
+        );
+
+        let mut replay = Replay::new(TestingContext::new(1), &movie, ROM).unwrap();
+        for _ in 0..200 {
+            assert_eq!(replay.get_keys(), keys(0x0008));
+        }
+        assert_eq!(replay.gen_random(), random);
+        assert_eq!(replay.get_keys(), keys(0x0008));
+        replay.on_vblank();
+        assert!(replay.is_finished());
+        assert_eq!(replay.error(), None);
+    }
+
+    #[test]
+    fn frame_sampling_holds_keys() {
+        let mut recorder = recorder(Sampling::Frame);
+        for frame in 0..3 {
+            recorder.inner.set_key(frame);
+            assert_eq!(recorder.get_keys(), keys(1 << frame));
+            recorder.inner.set_key(0xF);
+            // held until the end of the frame
+            assert_eq!(recorder.get_keys(), keys(1 << frame));
+            recorder.inner.reset_key(0xF);
+            recorder.inner.reset_key(frame);
+            recorder.on_vblank();
+        }
+        // unchanged keys aren't written
+        recorder.get_keys();
+        recorder.on_vblank();
+        recorder.get_keys();
+        recorder.on_vblank();
+        let (_, IoSink(movie)) = recorder.release();
+        assert_eq!(movie.len(), HEADER_SIZE + 3 * 4 + 4 + 1);
+
+        let mut replay = Replay::new(TestingContext::new(0), &movie, ROM).unwrap();
+        for frame in 0..3 {
+            assert_eq!(replay.get_keys(), keys(1 << frame));
+            assert_eq!(replay.get_keys(), keys(1 << frame));
+            replay.on_vblank();
+        }
+        for _ in 0..2 {
+            assert_eq!(replay.get_keys(), keys(0));
+            replay.on_vblank();
+        }
+        assert_eq!(replay.error(), None);
+    }
+
+    #[test]
+    fn divergence_is_reported() {
+        let mut recorder = recorder(Sampling::Tick);
+        recorder.get_keys();
+        recorder.gen_random();
+        recorder.on_vblank();
+        let (_, IoSink(movie)) = recorder.release();
+
+        // vblank came before the random value
+        let mut replay = Replay::new(TestingContext::new(0), &movie, ROM).unwrap();
+        replay.get_keys();
+        replay.on_vblank();
+        assert_eq!(replay.error(), Some("Emulation diverged from movie"));
+        assert_eq!(replay.gen_random(), 0);
+
+        replay.rewind();
+        replay.get_keys();
+        replay.gen_random();
+        replay.on_vblank();
+        assert_eq!(replay.error(), None);
+
+        let mut malformed = movie.clone();
+        malformed.push(0xF0);
+        let mut replay = Replay::new(TestingContext::new(0), &malformed, ROM).unwrap();
+        replay.get_keys();
+        replay.gen_random();
+        replay.on_vblank();
+        replay.on_vblank();
+        assert_eq!(replay.error(), Some("Unknown record of movie"));
+
+        let truncated = &movie[..HEADER_SIZE + 2];
+        let mut replay = Replay::new(TestingContext::new(0), truncated, ROM).unwrap();
+        assert_eq!(replay.get_keys(), keys(0));
+        assert_eq!(replay.error(), Some("Movie ends inside a record"));
+        assert_eq!(
+            Replay::new(TestingContext::new(0), &[0; 4], ROM).err(),
+            Some("Not a movie")
+        );
+        assert_eq!(
+            Replay::new(TestingContext::new(0), &movie, RomHash(0)).err(),
+            Some("Movie was recorded for another program")
+        );
+    }
+}
@@ -1,4 +1,5 @@
 use core::convert::TryFrom;
+use core::fmt;
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
@@ -24,7 +25,7 @@ use log::{debug, error, info, trace, warn};
 pub enum OpCode {
     /// Execute machine language subroutine at address NNN
     ///
-    /// This opcode is not implemented!
+    /// Executes only routines registered with `Builder::with_routine`!
     _0NNN { nnn: u16 },
     /// Clear the screen
     _00E0,
@@ -94,6 +95,10 @@ pub enum OpCode {
     _FX55 { x: u8 },
     /// Fill registers V0 to VX inclusive with the values stored in memory starting at address I, I is set to I + X + 1 after operation
     _FX65 { x: u8 },
+    /// Store the values of registers V0 to VX inclusive in RPL user flags, X must be below 8 (SUPER-CHIP)
+    _FX75 { x: u8 },
+    /// Fill registers V0 to VX inclusive with the values stored in RPL user flags, X must be below 8 (SUPER-CHIP)
+    _FX85 { x: u8 },
 }
 
 impl OpCode {
@@ -122,6 +127,54 @@ impl OpCode {
     }
 }
 
+/// Disassembly in commonly used mnemonics
+///
+/// Based on [Cowgod's Chip-8 technical reference](http://devernay.free.fr/hacks/chip8/C8TECH10.HTM)
+impl fmt::Display for OpCode {
+    #[rustfmt::skip]
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            OpCode::_0NNN { nnn }     => write!(f, "SYS 0x{:03X}", nnn),
+            OpCode::_00E0             => write!(f, "CLS"),
+            OpCode::_00EE             => write!(f, "RET"),
+            OpCode::_1NNN { nnn }     => write!(f, "JP 0x{:03X}", nnn),
+            OpCode::_2NNN { nnn }     => write!(f, "CALL 0x{:03X}", nnn),
+            OpCode::_3XNN { x, nn }   => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            OpCode::_4XNN { x, nn }   => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            OpCode::_5XY0 { x, y }    => write!(f, "SE V{:X}, V{:X}", x, y),
+            OpCode::_6XNN { x, nn }   => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            OpCode::_7XNN { x, nn }   => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            OpCode::_8XY0 { x, y }    => write!(f, "LD V{:X}, V{:X}", x, y),
+            OpCode::_8XY1 { x, y }    => write!(f, "OR V{:X}, V{:X}", x, y),
+            OpCode::_8XY2 { x, y }    => write!(f, "AND V{:X}, V{:X}", x, y),
+            OpCode::_8XY3 { x, y }    => write!(f, "XOR V{:X}, V{:X}", x, y),
+            OpCode::_8XY4 { x, y }    => write!(f, "ADD V{:X}, V{:X}", x, y),
+            OpCode::_8XY5 { x, y }    => write!(f, "SUB V{:X}, V{:X}", x, y),
+            OpCode::_8XY6 { x, y }    => write!(f, "SHR V{:X}, V{:X}", x, y),
+            OpCode::_8XY7 { x, y }    => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            OpCode::_8XYE { x, y }    => write!(f, "SHL V{:X}, V{:X}", x, y),
+            OpCode::_9XY0 { x, y }    => write!(f, "SNE V{:X}, V{:X}", x, y),
+            OpCode::_ANNN { nnn }     => write!(f, "LD I, 0x{:03X}", nnn),
+            OpCode::_BNNN { nnn }     => write!(f, "JP V0, 0x{:03X}", nnn),
+            OpCode::_CXNN { x, nn }   => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            OpCode::_DXYN { x, y, n } => write!(f, "DRW V{:X}, V{:X}, {}", x, y, n),
+            OpCode::_EX9E { x }       => write!(f, "SKP V{:X}", x),
+            OpCode::_EXA1 { x }       => write!(f, "SKNP V{:X}", x),
+            OpCode::_FX07 { x }       => write!(f, "LD V{:X}, DT", x),
+            OpCode::_FX0A { x }       => write!(f, "LD V{:X}, K", x),
+            OpCode::_FX15 { x }       => write!(f, "LD DT, V{:X}", x),
+            OpCode::_FX18 { x }       => write!(f, "LD ST, V{:X}", x),
+            OpCode::_FX1E { x }       => write!(f, "ADD I, V{:X}", x),
+            OpCode::_FX29 { x }       => write!(f, "LD F, V{:X}", x),
+            OpCode::_FX33 { x }       => write!(f, "LD B, V{:X}", x),
+            OpCode::_FX55 { x }       => write!(f, "LD [I], V{:X}", x),
+            OpCode::_FX65 { x }       => write!(f, "LD V{:X}, [I]", x),
+            OpCode::_FX75 { x }       => write!(f, "LD R, V{:X}", x),
+            OpCode::_FX85 { x }       => write!(f, "LD V{:X}, R", x),
+        }
+    }
+}
+
 impl TryFrom<u16> for OpCode {
     type Error = &'static str;
 
@@ -225,6 +278,8 @@ impl TryFrom<u16> for OpCode {
                     0x33u8 => OpCode::_FX33 { x },
                     0x55u8 => OpCode::_FX55 { x },
                     0x65u8 => OpCode::_FX65 { x },
+                    0x75u8 => OpCode::_FX75 { x },
+                    0x85u8 => OpCode::_FX85 { x },
                     _ => return Err("Unknown operation code"),
                 }
             }
@@ -336,6 +391,8 @@ mod tests {
             (0xFA33u16, OpCode::_FX33 { x: 0xAu8 }),
             (0xFA55u16, OpCode::_FX55 { x: 0xAu8 }),
             (0xFA65u16, OpCode::_FX65 { x: 0xAu8 }),
+            (0xF775u16, OpCode::_FX75 { x: 0x7u8 }),
+            (0xF385u16, OpCode::_FX85 { x: 0x3u8 }),
         ];
 
         for &(raw, expected) in &labeled_data {
@@ -345,4 +402,27 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn should_disassemble() {
+        extern crate std;
+        use std::string::ToString;
+
+        let labeled_data = [
+            (0x0ABCu16, "SYS 0xABC"),
+            (0x00EEu16, "RET"),
+            (0x3A0Cu16, "SE VA, 0x0C"),
+            (0x8AB6u16, "SHR VA, VB"),
+            (0xBABCu16, "JP V0, 0xABC"),
+            (0xDAB5u16, "DRW VA, VB, 5"),
+            (0xF155u16, "LD [I], V1"),
+            (0xF165u16, "LD V1, [I]"),
+            (0xF275u16, "LD R, V2"),
+            (0xF285u16, "LD V2, R"),
+        ];
+
+        for &(raw, expected) in &labeled_data {
+            assert_eq!(expected, OpCode::try_from(raw).unwrap().to_string());
+        }
+    }
 }
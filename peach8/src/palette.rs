@@ -0,0 +1,289 @@
+//! Colour palettes
+//!
+//! `Palette` maps unlit and lit pixels of a frame to RGB colours and fills
+//! buffers in pixel formats commonly used by colour displays:
+//! - RGB565, one `u16` per pixel,
+//! - RGB888, three bytes per pixel in order red, green, blue,
+//! - ARGB8888, one `u32` per pixel with opaque alpha.
+//!
+//! Buffers are filled row by row, each pixel of the frame becomes a `scale` x
+//! `scale` square. Factor of 0 is treated as 1.
+//!
+//! With `embedded-graphics` feature on, `Palette::image` adapts a frame into
+//! an image of any RGB colour type, which can be drawn with `Image`.
+
+#[cfg(feature = "embedded-graphics")]
+use embedded_graphics::{
+    drawable::Pixel,
+    geometry::Point,
+    image::{ImageDimensions, IntoPixelIter},
+    pixelcolor::{PixelColor, Rgb888},
+};
+
+use crate::frame::{FrameView, HEIGHT, WIDTH};
+
+/// Colours of unlit and lit pixels
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Palette {
+    /// Colour of unlit pixels, as red, green and blue
+    pub off: [u8; 3],
+    /// Colour of lit pixels, as red, green and blue
+    pub on: [u8; 3],
+}
+
+impl Palette {
+    /// White pixels on black background
+    pub const CLASSIC: Self = Self::new([0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]);
+    /// Default colours of Octo
+    pub const OCTO: Self = Self::new([0x99, 0x66, 0x00], [0xFF, 0xCC, 0x00]);
+    /// Dark green pixels on pale green background of handheld LCDs
+    pub const LCD_GREEN: Self = Self::new([0x9B, 0xBC, 0x0F], [0x0F, 0x38, 0x0F]);
+    /// Amber monochrome monitor
+    pub const AMBER: Self = Self::new([0x1A, 0x0F, 0x00], [0xFF, 0xB0, 0x00]);
+
+    pub const fn new(off: [u8; 3], on: [u8; 3]) -> Self {
+        Self { off, on }
+    }
+
+    /// Colour of a pixel, as red, green and blue
+    pub fn rgb888(&self, lit: bool) -> [u8; 3] {
+        if lit {
+            self.on
+        } else {
+            self.off
+        }
+    }
+
+    /// Colour of a pixel in RGB565 format
+    pub fn rgb565(&self, lit: bool) -> u16 {
+        let [r, g, b] = self.rgb888(lit);
+        (r as u16 >> 3) << 11 | (g as u16 >> 2) << 5 | b as u16 >> 3
+    }
+
+    /// Colour of a pixel in ARGB8888 format, fully opaque
+    pub fn argb8888(&self, lit: bool) -> u32 {
+        let [r, g, b] = self.rgb888(lit);
+        0xFF00_0000 | (r as u32) << 16 | (g as u32) << 8 | b as u32
+    }
+
+    /// Fill the buffer with frame in RGB565 format, returns number of written pixels
+    pub fn fill_rgb565(
+        &self,
+        frame: FrameView<'_>,
+        scale: usize,
+        buf: &mut [u16],
+    ) -> Result<usize, &'static str> {
+        let colours = [self.rgb565(false), self.rgb565(true)];
+        fill(frame, scale, buf, 1, |pixel, lit| {
+            pixel[0] = colours[lit as usize]
+        })
+    }
+
+    /// Fill the buffer with frame in RGB888 format, returns number of written bytes
+    pub fn fill_rgb888(
+        &self,
+        frame: FrameView<'_>,
+        scale: usize,
+        buf: &mut [u8],
+    ) -> Result<usize, &'static str> {
+        fill(frame, scale, buf, 3, |pixel, lit| {
+            pixel.copy_from_slice(&self.rgb888(lit))
+        })
+    }
+
+    /// Fill the buffer with frame in ARGB8888 format, returns number of written pixels
+    pub fn fill_argb8888(
+        &self,
+        frame: FrameView<'_>,
+        scale: usize,
+        buf: &mut [u32],
+    ) -> Result<usize, &'static str> {
+        let colours = [self.argb8888(false), self.argb8888(true)];
+        fill(frame, scale, buf, 1, |pixel, lit| {
+            pixel[0] = colours[lit as usize]
+        })
+    }
+
+    /// Adapt frame into an image of given colour type
+    #[cfg(feature = "embedded-graphics")]
+    pub fn image<'a, C>(&self, frame: FrameView<'a>) -> PaletteImage<'a, C>
+    where
+        C: PixelColor + From<Rgb888>,
+    {
+        let [r, g, b] = self.off;
+        let off = Rgb888::new(r, g, b).into();
+        let [r, g, b] = self.on;
+        let on = Rgb888::new(r, g, b).into();
+        PaletteImage { frame, off, on }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::CLASSIC
+    }
+}
+
+/// Fill `buf` with `scale`d frame, `len` elements per pixel
+fn fill<T>(
+    frame: FrameView<'_>,
+    scale: usize,
+    buf: &mut [T],
+    len: usize,
+    mut set: impl FnMut(&mut [T], bool),
+) -> Result<usize, &'static str> {
+    let scale = scale.max(1);
+    let width = WIDTH * scale;
+    let total = width * HEIGHT * scale * len;
+    if buf.len() < total {
+        return Err("Buffer too small for the image");
+    }
+    buf[..total]
+        .chunks_exact_mut(len)
+        .enumerate()
+        .for_each(|(n, pixel)| {
+            let (x, y) = (n % width / scale, n / width / scale);
+            set(pixel, frame.is_lit(x, y))
+        });
+    Ok(total)
+}
+
+/// Frame coloured with a `Palette`, created by `Palette::image`
+#[cfg(feature = "embedded-graphics")]
+#[derive(Copy, Clone, Debug)]
+pub struct PaletteImage<'a, C> {
+    frame: FrameView<'a>,
+    off: C,
+    on: C,
+}
+
+#[cfg(feature = "embedded-graphics")]
+impl<C> ImageDimensions for PaletteImage<'_, C> {
+    fn width(&self) -> u32 {
+        WIDTH as u32
+    }
+
+    fn height(&self) -> u32 {
+        HEIGHT as u32
+    }
+}
+
+#[cfg(feature = "embedded-graphics")]
+impl<'a, 'b, C> IntoPixelIter<C> for &'a PaletteImage<'b, C>
+where
+    C: PixelColor + From<<C as PixelColor>::Raw>,
+{
+    type PixelIterator = PaletteImageIterator<'a, 'b, C>;
+
+    fn pixel_iter(self) -> Self::PixelIterator {
+        PaletteImageIterator {
+            image: self,
+            index: 0,
+        }
+    }
+}
+
+/// Iterator over pixels of `PaletteImage`
+#[cfg(feature = "embedded-graphics")]
+#[derive(Debug)]
+pub struct PaletteImageIterator<'a, 'b, C> {
+    image: &'a PaletteImage<'b, C>,
+    index: usize,
+}
+
+#[cfg(feature = "embedded-graphics")]
+impl<C: PixelColor> Iterator for PaletteImageIterator<'_, '_, C> {
+    type Item = Pixel<C>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.index >= WIDTH * HEIGHT {
+            return None;
+        }
+        let n = self.index;
+        self.index += 1;
+        let (x, y) = (n % WIDTH, n / WIDTH);
+        let colour = if self.image.frame.is_lit(x, y) {
+            self.image.on
+        } else {
+            self.image.off
+        };
+        Some(Pixel(Point::new(x as i32, y as i32), colour))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+
+    fn frame() -> Frame {
+        let mut frame = Frame::new();
+        frame.xor_bit(1, 0, true).unwrap();
+        frame.xor_bit(63, 31, true).unwrap();
+        frame
+    }
+
+    #[test]
+    fn colours() {
+        assert_eq!(Palette::default(), Palette::CLASSIC);
+        assert_eq!(Palette::OCTO.rgb565(true), 0xFE60);
+        assert_eq!(Palette::CLASSIC.rgb565(true), 0xFFFF);
+        assert_eq!(Palette::CLASSIC.rgb565(false), 0x0000);
+        assert_eq!(Palette::AMBER.argb8888(true), 0xFFFF_B000);
+        assert_eq!(Palette::LCD_GREEN.rgb888(false), [0x9B, 0xBC, 0x0F]);
+    }
+
+    #[test]
+    fn fill_buffers() {
+        let frame = frame();
+        let palette = Palette::OCTO;
+        let (off, on) = (palette.rgb565(false), palette.rgb565(true));
+
+        let mut rgb565 = [0; 128 * 64];
+        assert_eq!(
+            palette.fill_rgb565(frame.view(), 2, &mut rgb565),
+            Ok(128 * 64)
+        );
+        assert_eq!(&rgb565[..5], &[off, off, on, on, off]);
+        assert_eq!(&rgb565[128..133], &[off, off, on, on, off]);
+        assert_eq!(&rgb565[256..261], &[off; 5]);
+        assert_eq!(rgb565[128 * 64 - 1], on);
+
+        let mut rgb888 = [0; 64 * 32 * 3];
+        assert_eq!(
+            palette.fill_rgb888(frame.view(), 1, &mut rgb888),
+            Ok(64 * 32 * 3)
+        );
+        assert_eq!(&rgb888[..6], &[0x99, 0x66, 0x00, 0xFF, 0xCC, 0x00]);
+
+        let mut argb = [0; 64 * 32];
+        assert_eq!(
+            palette.fill_argb8888(frame.view(), 0, &mut argb),
+            Ok(64 * 32)
+        );
+        assert_eq!(&argb[..2], &[0xFF99_6600, 0xFFFF_CC00]);
+
+        assert_eq!(
+            palette.fill_argb8888(frame.view(), 2, &mut argb),
+            Err("Buffer too small for the image")
+        );
+    }
+
+    #[cfg(feature = "embedded-graphics")]
+    #[test]
+    fn image() {
+        use embedded_graphics::pixelcolor::Rgb565;
+
+        let frame = frame();
+        let image = Palette::OCTO.image::<Rgb565>(frame.view());
+        assert_eq!((image.width(), image.height()), (64, 32));
+
+        let off = Rgb565::from(Rgb888::new(0x99, 0x66, 0x00));
+        let on = Rgb565::from(Rgb888::new(0xFF, 0xCC, 0x00));
+        let mut pixels = image.pixel_iter();
+        assert_eq!(pixels.next(), Some(Pixel(Point::new(0, 0), off)));
+        assert_eq!(pixels.next(), Some(Pixel(Point::new(1, 0), on)));
+        assert_eq!(pixels.next(), Some(Pixel(Point::new(2, 0), off)));
+        assert_eq!(pixels.last(), Some(Pixel(Point::new(63, 31), on)));
+    }
+}
@@ -6,22 +6,25 @@
 
 use core::convert::TryInto;
 
-use bitvec::prelude::*;
 use heapless::{consts::U64, Vec};
 
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 
 use crate::context::Context;
-use crate::frame::{Frame, HEIGHT, WIDTH};
+use crate::extension::{Extensions, Registers};
+use crate::frame::{Frame, FrameView, HEIGHT, WIDTH};
 use crate::opcode::OpCode;
-#[cfg(feature = "atomic")]
-use crate::timer::atomic::Timer;
-#[cfg(not(feature = "atomic"))]
-use crate::timer::racy::Timer;
-use crate::timer::TimerState;
-
-const MEM_LENGTH: usize = 4096;
+use crate::storage::{RomHash, Storage, FLAGS};
+#[cfg(any(feature = "atomic", feature = "critical-section"))]
+use crate::timer::TimerHandle;
+use crate::timer::{Slot, TimerState, Timers};
+
+pub(crate) const MEM_LENGTH: usize = 4096;
+const STACK_LENGTH: usize = 64;
+/// Size of a `Snapshot` as bytes, see `Snapshot::to_bytes`
+pub const SNAPSHOT_SIZE: usize =
+    16 + 2 + 2 + 1 + 1 + 8 + 8 + 16 + 1 + 2 * STACK_LENGTH + MEM_LENGTH + 256;
 const START_ADDR: u16 = 0x200;
 const FONTSET_ADDR: u16 = 0x050;
 
@@ -50,6 +53,144 @@ impl KeyState {
     }
 }
 
+/// Read-only view over the state of `Peach8`
+///
+/// Passed to `Context::on_instruction` before each instruction is executed.
+#[derive(Copy, Clone, Debug)]
+pub struct State<'a> {
+    /// Number of instructions executed so far
+    pub cycle: u64,
+    pub pc: u16,
+    pub v: &'a [u8; 16],
+    pub i: u16,
+    pub stack: &'a [u16],
+    pub delay: u8,
+    pub sound: u8,
+    pub memory: &'a [u8],
+}
+
+/// Build `State` borrowing only the fields it needs, so that `ctx`
+/// can be borrowed mutably at the same time
+macro_rules! state {
+    ($chip:expr) => {
+        State {
+            cycle: $chip.cycles,
+            pc: $chip.pc,
+            v: &$chip.v,
+            i: $chip.i,
+            stack: &$chip.stack,
+            delay: $chip.timers.delay(),
+            sound: $chip.timers.sound(),
+            memory: &$chip.memory,
+        }
+    };
+}
+
+/// Copy of the whole state of `Peach8`, see `Peach8::snapshot`
+#[derive(Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    v: [u8; 16],
+    i: u16,
+    pc: u16,
+    frame: Frame,
+    keys: [KeyState; 16],
+    stack: Vec<u16, U64>,
+    memory: [u8; MEM_LENGTH],
+    delay: u8,
+    sound: u8,
+    cycles: u64,
+    rom: RomHash,
+}
+
+impl Snapshot {
+    /// Encode the snapshot as V0 to VF, I, PC, delay and sound timers, number
+    /// of cycles, hash of the program, states of keys, depth of the stack and
+    /// 64 of its slots, memory and frame. Numbers are little endian
+    pub fn to_bytes(&self) -> [u8; SNAPSHOT_SIZE] {
+        let mut bytes = [0; SNAPSHOT_SIZE];
+        let mut writer = bytes.iter_mut();
+        let mut write = |data: &[u8]| {
+            writer
+                .by_ref()
+                .zip(data)
+                .for_each(|(byte, &value)| *byte = value)
+        };
+        write(&self.v);
+        write(&self.i.to_le_bytes());
+        write(&self.pc.to_le_bytes());
+        write(&[self.delay, self.sound]);
+        write(&self.cycles.to_le_bytes());
+        write(&self.rom.0.to_le_bytes());
+        for key in self.keys.iter() {
+            write(&[*key as u8]);
+        }
+        write(&[self.stack.len() as u8]);
+        for slot in 0..STACK_LENGTH {
+            write(&self.stack.get(slot).copied().unwrap_or(0).to_le_bytes());
+        }
+        write(&self.memory);
+        write(self.frame.view().as_raw());
+        bytes
+    }
+
+    /// Decode a snapshot encoded by `to_bytes`
+    pub fn parse(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() != SNAPSHOT_SIZE {
+            return Err("Snapshot has wrong size");
+        }
+        let (v, rest) = bytes.split_at(16);
+        let (registers, rest) = rest.split_at(22);
+        let (keys, rest) = rest.split_at(16);
+        let (stack, rest) = rest.split_at(1 + 2 * STACK_LENGTH);
+        let (memory, raw_frame) = rest.split_at(MEM_LENGTH);
+
+        let word = |at: usize| u16::from_le_bytes([registers[at], registers[at + 1]]);
+        let long = |at: usize| u64::from_le_bytes(registers[at..at + 8].try_into().unwrap());
+        let mut key_states = [KeyState::Up; 16];
+        for (state, &key) in key_states.iter_mut().zip(keys) {
+            *state = match key {
+                0 => KeyState::Pressed,
+                1 => KeyState::Down,
+                2 => KeyState::Released,
+                3 => KeyState::Up,
+                _ => return Err("Invalid state of a key in snapshot"),
+            };
+        }
+        let depth = stack[0] as usize;
+        if depth > STACK_LENGTH {
+            return Err("Stack of snapshot is too deep");
+        }
+        let mut frame = Frame::new();
+        frame.as_raw_mut().copy_from_slice(raw_frame);
+        Ok(Self {
+            v: v.try_into().unwrap(),
+            i: word(0),
+            pc: word(2),
+            delay: registers[4],
+            sound: registers[5],
+            cycles: long(6),
+            rom: RomHash(long(14)),
+            frame,
+            keys: key_states,
+            stack: stack[1..1 + 2 * depth]
+                .chunks(2)
+                .map(|slot| u16::from_le_bytes([slot[0], slot[1]]))
+                .collect(),
+            memory: memory.try_into().unwrap(),
+        })
+    }
+
+    /// Number of instructions executed before the snapshot
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Frame as it was at the snapshot
+    pub fn frame(&self) -> FrameView<'_> {
+        self.frame.view()
+    }
+}
+
 /// Chip-8 virtual machine
 pub struct Peach8<C: Context + Sized> {
     pub ctx: C,
@@ -60,8 +201,10 @@ pub struct Peach8<C: Context + Sized> {
     keys: [KeyState; 16],
     stack: Vec<u16, U64>,
     memory: [u8; MEM_LENGTH],
-    delay_timer: Timer,
-    sound_timer: Timer,
+    pub(crate) timers: Slot,
+    pub(crate) extensions: Extensions<C>,
+    cycles: u64,
+    rom: RomHash,
 }
 
 impl<C: Context + Sized> Peach8<C> {
@@ -75,8 +218,10 @@ impl<C: Context + Sized> Peach8<C> {
             keys: [KeyState::Up; 16],
             stack: Vec::new(),
             memory: [0; MEM_LENGTH],
-            delay_timer: Timer::new(),
-            sound_timer: Timer::new(),
+            timers: Slot::Owned(Timers::new()),
+            extensions: Extensions::new(),
+            cycles: 0,
+            rom: RomHash::of(&[]),
         }
     }
 
@@ -108,6 +253,7 @@ impl<C: Context + Sized> Peach8<C> {
             .iter_mut()
             .zip(prog)
             .for_each(|(mem, &data)| *mem = data);
+        self.rom = RomHash::of(prog);
     }
 
     fn pc_increment(&mut self) -> Result<(), &'static str> {
@@ -129,28 +275,70 @@ impl<C: Context + Sized> Peach8<C> {
             });
     }
 
-    fn read_opcode(&self) -> Result<OpCode, &'static str> {
+    fn fetch(&self) -> Result<u16, &'static str> {
         if self.pc <= (MEM_LENGTH - 2) as u16 {
             let mut opcode: u16 = 0;
             opcode |= (self.memory[self.pc as usize] as u16) << 8;
             opcode |= self.memory[(self.pc + 1) as usize] as u16;
-            opcode.try_into()
+            Ok(opcode)
         } else {
             Err("Attempted to read memory out of address space")
         }
     }
 
+    #[cfg(test)]
+    fn read_opcode(&self) -> Result<OpCode, &'static str> {
+        self.fetch().and_then(TryInto::try_into)
+    }
+
+    fn registers(&mut self) -> (&mut C, Registers<'_>) {
+        (
+            &mut self.ctx,
+            Registers {
+                v: &mut self.v,
+                i: &mut self.i,
+                memory: &mut self.memory,
+            },
+        )
+    }
+
+    /// Decode raw instruction and execute it. Falls back to registered
+    /// instructions, when opcode is undefined
+    fn decode_and_execute(&mut self, raw: u16) -> Result<(), &'static str> {
+        match raw.try_into() {
+            Ok(opcode) => self.execute(opcode),
+            Err(e) => match self.extensions.instruction(raw) {
+                Some(instruction) => {
+                    let (ctx, regs) = self.registers();
+                    instruction(ctx, regs, raw).and_then(|_| self.pc_increment())
+                }
+                None => Err(e),
+            },
+        }
+    }
+
     /// Decrement delay and sound timers. Handles sound on/off events.
     ///
     /// # Note
     /// Should be called with 60Hz frequency
     pub fn tick_timers(&mut self) {
-        self.delay_timer.decrement();
-        match self.sound_timer.decrement() {
+        let state = self.timers.tick();
+        self.vblank(state);
+    }
+
+    /// Finish a frame whose timers were ticked by an interrupt through
+    /// `TimerHandle::tick`. Handles sound on/off events of the returned state.
+    ///
+    /// # Note
+    /// Should be called from the main loop once per tick of the interrupt,
+    /// so that `Context::on_vblank` is called as by `tick_timers`
+    pub fn vblank(&mut self, state: TimerState) {
+        match state {
             TimerState::On => self.ctx.sound_on(),
             TimerState::Off => self.ctx.sound_off(),
             TimerState::Finished => (),
         }
+        self.ctx.on_vblank();
     }
 
     /// Progress emulation by one cycle. Handles user input and drawing to the screen
@@ -159,10 +347,78 @@ impl<C: Context + Sized> Peach8<C> {
     /// Should be called with around 500Hz frequency
     pub fn tick_chip(&mut self) -> Result<(), &'static str> {
         self.update_keys();
-        self.read_opcode().and_then(|op| self.execute(op)).and({
-            self.ctx.on_frame(self.frame.view());
-            Ok(())
-        })
+        self.fetch()
+            .and_then(|raw| {
+                self.observe(raw);
+                self.decode_and_execute(raw)
+            })
+            .and({
+                self.ctx.on_frame(self.frame.view());
+                Ok(())
+            })
+    }
+
+    /// Get read-only view over current state
+    pub fn state(&self) -> State<'_> {
+        state!(self)
+    }
+
+    /// Number of instructions executed so far
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// Save the state of the machine, except for `Context` and extensions
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            v: self.v,
+            i: self.i,
+            pc: self.pc,
+            frame: self.frame.clone(),
+            keys: self.keys,
+            stack: self.stack.clone(),
+            memory: self.memory,
+            delay: self.timers.delay(),
+            sound: self.timers.sound(),
+            cycles: self.cycles,
+            rom: self.rom,
+        }
+    }
+
+    /// Bring the machine back to a saved state, including shared timers
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.v = snapshot.v;
+        self.i = snapshot.i;
+        self.pc = snapshot.pc;
+        self.frame = snapshot.frame.clone();
+        self.keys = snapshot.keys;
+        self.stack = snapshot.stack.clone();
+        self.memory = snapshot.memory;
+        self.timers.set_delay(snapshot.delay);
+        self.timers.set_sound(snapshot.sound);
+        self.cycles = snapshot.cycles;
+        self.rom = snapshot.rom;
+    }
+
+    /// Hash of the loaded program, keying its RPL user flags
+    pub fn rom_hash(&self) -> RomHash {
+        self.rom
+    }
+
+    /// Handle ticking timers from an interrupt, when they were shared by
+    /// `Builder::with_timers`
+    #[cfg(any(feature = "atomic", feature = "critical-section"))]
+    pub fn timer_handle(&self) -> Option<TimerHandle<'static>> {
+        match self.timers {
+            Slot::Owned(_) => None,
+            Slot::Shared(timers) => Some(timers.handle()),
+        }
+    }
+
+    fn observe(&mut self, raw: u16) {
+        let state = state!(self);
+        self.ctx.on_instruction(&state, raw);
+        self.cycles += 1;
     }
 
     /// Drop and release held `Context`
@@ -171,13 +427,13 @@ impl<C: Context + Sized> Peach8<C> {
     }
 }
 
-#[cfg(feature = "atomic")]
-unsafe impl<C: Context + Sized + Sync> core::marker::Sync for Peach8<C> {}
+#[cfg(test)]
+mod single_step;
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::context::testing::TestingContext;
+    use crate::testing::TestingContext;
 
     #[test]
     fn pc_incrementation() {
@@ -194,6 +450,103 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "atomic")]
+    #[test]
+    fn peach8_is_sync() {
+        fn assert_sync<T: Sync>() {}
+        assert_sync::<Peach8<TestingContext>>();
+    }
+
+    #[cfg(all(feature = "atomic", not(loom)))]
+    #[test]
+    fn timers_ticked_by_handle() {
+        static TIMERS: Timers = Timers::new();
+        // V0 = 3, delay = V0, sound = V0, V1 = delay
+        let program = [0x60, 0x03, 0xF0, 0x15, 0xF0, 0x18, 0xF1, 0x07];
+        let mut chip = crate::builder::Builder::new()
+            .with_context(TestingContext::new(0))
+            .with_program(&program)
+            .with_timers(&TIMERS)
+            .build()
+            .unwrap();
+        let handle = chip.timer_handle().unwrap();
+        for _ in 0..3 {
+            chip.tick_chip().unwrap();
+        }
+        let isr = std::thread::spawn(move || {
+            assert_eq!(handle.tick(), TimerState::On);
+            assert_eq!(handle.tick(), TimerState::On);
+        });
+        isr.join().unwrap();
+        chip.tick_chip().unwrap();
+        assert_eq!(chip.state().v[1], 1);
+        assert_eq!(handle.tick(), TimerState::Finished);
+        assert_eq!((handle.delay(), handle.sound()), (0, 0));
+
+        chip.vblank(TimerState::On);
+        assert!(chip.ctx.is_sound_on());
+        chip.vblank(TimerState::Off);
+        assert!(!chip.ctx.is_sound_on());
+
+        let chip = Peach8::new(TestingContext::new(0));
+        assert!(chip.timer_handle().is_none());
+    }
+
+    #[test]
+    fn snapshot_restores_state() {
+        // V0 = random, I = V0 sprite, draw, call 0x200
+        let program = [0xC0, 0xFF, 0xF0, 0x29, 0xD0, 0x05, 0x22, 0x00];
+        let mut chip = crate::builder::Builder::new()
+            .with_context(TestingContext::new(7))
+            .with_program(&program)
+            .build()
+            .unwrap();
+        for _ in 0..5 {
+            chip.tick_chip().unwrap();
+        }
+        chip.tick_timers();
+        let snapshot = chip.snapshot();
+        assert_eq!(snapshot.cycles(), 5);
+        for _ in 0..9 {
+            chip.tick_chip().unwrap();
+        }
+        assert!(chip.snapshot() != snapshot);
+
+        chip.restore(&snapshot);
+        assert!(chip.snapshot() == snapshot);
+        assert_eq!(chip.state().stack, &[0x206]);
+        assert_eq!(chip.cycles(), 5);
+    }
+
+    #[test]
+    fn snapshot_bytes_round_trip() {
+        let mut chip = Peach8::new(TestingContext::new(0));
+        chip.load(&[0x22, 0x00]);
+        chip.tick_chip().unwrap();
+        chip.ctx.set_key(3);
+        chip.tick_chip().unwrap();
+        let snapshot = chip.snapshot();
+        let bytes = snapshot.to_bytes();
+        assert!(Snapshot::parse(&bytes) == Ok(snapshot));
+
+        assert_eq!(
+            Snapshot::parse(&bytes[1..]).err(),
+            Some("Snapshot has wrong size")
+        );
+        let mut invalid = bytes;
+        invalid[16 + 22 + 3] = 4;
+        assert_eq!(
+            Snapshot::parse(&invalid).err(),
+            Some("Invalid state of a key in snapshot")
+        );
+        let mut invalid = bytes;
+        invalid[16 + 22 + 16] = 65;
+        assert_eq!(
+            Snapshot::parse(&invalid).err(),
+            Some("Stack of snapshot is too deep")
+        );
+    }
+
     #[test]
     fn key_state_update() {
         let mut state = KeyState::Pressed;
@@ -258,8 +611,8 @@ mod tests {
             chip.tick_timers();
         }
         assert!(chip.ctx.is_sound_on());
-        assert_eq!(chip.delay_timer.load(), 0);
-        assert_eq!(chip.sound_timer.load(), 0);
+        assert_eq!(chip.timers.delay(), 0);
+        assert_eq!(chip.timers.sound(), 0);
 
         chip.tick_timers();
         assert!(!chip.ctx.is_sound_on());
@@ -323,14 +676,22 @@ impl<C: Context + Sized> Peach8<C> {
             OpCode::_FX33 { x }       => self.assign_mem_at_i_bcd_of_vx(x),
             OpCode::_FX55 { x }       => self.assign_mem_at_i_v0_to_vx(x),
             OpCode::_FX65 { x }       => self.assign_v0_to_vx_mem_at_i(x),
+            OpCode::_FX75 { x }       => self.assign_flags_v0_to_vx(x),
+            OpCode::_FX85 { x }       => self.assign_v0_to_vx_flags(x),
         }
         .and(self.pc_increment())
     }
 
     /// Execute machine language subroutine at address NNN
     /// 0NNN { nnn: u16 },
-    fn exec_ml_subroutine_at(&mut self, _nnn: u16) -> Result<(), &'static str> {
-        Err("Machine code subroutines not supported")
+    fn exec_ml_subroutine_at(&mut self, nnn: u16) -> Result<(), &'static str> {
+        match self.extensions.routine(nnn) {
+            Some(routine) => {
+                let (ctx, regs) = self.registers();
+                routine(ctx, regs).and_then(|_| self.pc_increment())
+            }
+            None => Err("Machine code subroutines not supported"),
+        }
     }
 
     /// Clear the screen
@@ -514,11 +875,11 @@ impl<C: Context + Sized> Peach8<C> {
     /// Jump to address NNN + V0
     /// BNNN { nnn: u16 },
     fn jump_to_nnn_add_v0(&mut self, nnn: u16) -> Result<(), &'static str> {
-        let addr = nnn + self.v[0] as u16;
-        if addr < START_ADDR {
+        let addr = nnn as usize + self.v[0] as usize;
+        if addr < START_ADDR as usize {
             Err("Attempted to jump out of program's address space")
-        } else if addr < MEM_LENGTH as u16 {
-            self.pc = addr;
+        } else if addr < MEM_LENGTH {
+            self.pc = addr as u16;
             Ok(())
         } else {
             Err("Attempted to set pc out of address space")
@@ -536,28 +897,16 @@ impl<C: Context + Sized> Peach8<C> {
     /// Draw a sprite at position VX, VY with N bytes of sprite data starting at the address stored in I, Set VF to 01 if any set pixels are changed to unset, and 00 otherwise
     /// DXYN { x: u8, y: u8, n: u8 },
     fn draw_n_at_vx_vy(&mut self, x: u8, y: u8, n: u8) -> Result<(), &'static str> {
-        if self.i + n as u16 >= MEM_LENGTH as u16 {
+        if self.i as usize + n as usize > MEM_LENGTH {
             return Err("Attempted to read memory out of address space");
         }
 
         let x = self.v[x as usize] as usize % WIDTH;
         let y = self.v[y as usize] as usize % HEIGHT;
-        let x_stop = core::cmp::min(x + 8 as usize, WIDTH);
-        let y_stop = core::cmp::min(y + n as usize, HEIGHT);
-
-        let mut collision = false;
-        for x_idx in x..x_stop {
-            for y_idx in y..y_stop {
-                let row =
-                    BitSlice::<Msb0, _>::from_element(&self.memory[self.i as usize + y_idx - y]);
-                let to_draw = *row.get(x_idx - x).unwrap();
-                let curr_bit = *self.frame.view().get_bit(x_idx, y_idx).unwrap();
-                if to_draw && to_draw == curr_bit {
-                    collision = true;
-                }
-                self.frame.xor_bit(x_idx, y_idx, to_draw)?;
-            }
-        }
+        let start = self.i as usize;
+        let collision = self
+            .frame
+            .xor_sprite(x, y, &self.memory[start..start + n as usize])?;
 
         self.v[15] = if collision { 0x01u8 } else { 0x00u8 };
         Ok(())
@@ -586,7 +935,7 @@ impl<C: Context + Sized> Peach8<C> {
     /// Store the current value of the delay timer in register VX
     /// FX07 { x: u8 },
     fn assign_vx_delay_t(&mut self, x: u8) -> Result<(), &'static str> {
-        self.v[x as usize] = self.delay_timer.load();
+        self.v[x as usize] = self.timers.delay();
         Ok(())
     }
 
@@ -612,23 +961,23 @@ impl<C: Context + Sized> Peach8<C> {
     /// Set the delay timer to the value of register VX
     /// FX15 { x: u8 },
     fn assign_delay_t_vx(&mut self, x: u8) -> Result<(), &'static str> {
-        self.delay_timer.store(self.v[x as usize]);
+        self.timers.set_delay(self.v[x as usize]);
         Ok(())
     }
 
     /// Set the sound timer to the value of register VX
     /// FX18 { x: u8 },
     fn assign_sound_t_vx(&mut self, x: u8) -> Result<(), &'static str> {
-        self.sound_timer.store(self.v[x as usize]);
+        self.timers.set_sound(self.v[x as usize]);
         Ok(())
     }
 
     /// Add the value stored in register VX to register I
     /// FX1E { x: u8 },
     fn assign_add_i_vx(&mut self, x: u8) -> Result<(), &'static str> {
-        let addr = self.i + self.v[x as usize] as u16;
-        if addr < MEM_LENGTH as u16 {
-            self.i = addr;
+        let addr = self.i as usize + self.v[x as usize] as usize;
+        if addr < MEM_LENGTH {
+            self.i = addr as u16;
             Ok(())
         } else {
             Err("Attempted to set i out of address space")
@@ -646,7 +995,7 @@ impl<C: Context + Sized> Peach8<C> {
     /// Store the binary-coded decimal equivalent of the value stored in register VX at addresses I, I+1, and I+2
     /// FX33 { x: u8 },
     fn assign_mem_at_i_bcd_of_vx(&mut self, x: u8) -> Result<(), &'static str> {
-        if ((self.i + 2) as usize) < self.memory.len() {
+        if (self.i as usize + 2) < self.memory.len() {
             let value = self.v[x as usize];
             self.memory[self.i as usize] = value / 100u8;
             self.memory[(self.i + 1) as usize] = (value % 100) / 10u8;
@@ -660,7 +1009,7 @@ impl<C: Context + Sized> Peach8<C> {
     /// Store the values of registers V0 to VX inclusive in memory starting at address I, I is set to I + X + 1 after operation
     /// FX55 { x: u8 },
     fn assign_mem_at_i_v0_to_vx(&mut self, x: u8) -> Result<(), &'static str> {
-        if ((self.i + x as u16) as usize) < self.memory.len() - 1 {
+        if self.i as usize + (x as usize) < self.memory.len() {
             for idx in 0..=x {
                 self.memory[self.i as usize] = self.v[idx as usize];
                 self.i += 1
@@ -674,7 +1023,7 @@ impl<C: Context + Sized> Peach8<C> {
     /// Fill registers V0 to VX inclusive with the values stored in memory starting at address I, I is set to I + X + 1 after operation
     /// FX65 { x: u8 },
     fn assign_v0_to_vx_mem_at_i(&mut self, x: u8) -> Result<(), &'static str> {
-        if ((self.i + x as u16) as usize) < self.memory.len() - 1 {
+        if self.i as usize + (x as usize) < self.memory.len() {
             for idx in 0..=x {
                 self.v[idx as usize] = self.memory[self.i as usize];
                 self.i += 1
@@ -684,6 +1033,37 @@ impl<C: Context + Sized> Peach8<C> {
             Err("Attempted to load memory out of address space")
         }
     }
+
+    /// Store the values of registers V0 to VX inclusive in RPL user flags
+    /// FX75 { x: u8 },
+    fn assign_flags_v0_to_vx(&mut self, x: u8) -> Result<(), &'static str> {
+        let (rom, x) = (self.rom, x as usize);
+        let storage = flags_storage(&mut self.ctx, x)?;
+        let mut flags = [0; FLAGS];
+        storage.load(rom, &mut flags)?;
+        flags[..=x].copy_from_slice(&self.v[..=x]);
+        storage.save(rom, &flags)
+    }
+
+    /// Fill registers V0 to VX inclusive with the values stored in RPL user flags
+    /// FX85 { x: u8 },
+    fn assign_v0_to_vx_flags(&mut self, x: u8) -> Result<(), &'static str> {
+        let (rom, x) = (self.rom, x as usize);
+        let storage = flags_storage(&mut self.ctx, x)?;
+        let mut flags = [0; FLAGS];
+        storage.load(rom, &mut flags)?;
+        self.v[..=x].copy_from_slice(&flags[..=x]);
+        Ok(())
+    }
+}
+
+/// `Storage` of RPL user flags, if they can hold registers V0 to VX
+fn flags_storage<C: Context>(ctx: &mut C, x: usize) -> Result<&mut dyn Storage, &'static str> {
+    if x >= FLAGS {
+        return Err("RPL user flags hold only V0 to V7");
+    }
+    ctx.storage()
+        .ok_or("Context has no storage for RPL user flags")
 }
 
 #[cfg(test)]
@@ -693,8 +1073,8 @@ mod opcodes_execution_tests {
     use core::convert::{TryFrom, TryInto};
 
     use crate::assert_eq_2d;
-    use crate::context::testing::TestingContext;
-    use crate::utils::testing::ToMask;
+    use crate::testing::TestingContext;
+    use crate::testing::ToMask;
 
     #[test]
     fn pc_manipulation_test() -> Result<(), &'static str> {
@@ -742,7 +1122,7 @@ mod opcodes_execution_tests {
                                             // Wait for a keypress should stop execution, hovewer to not block the whole routine
                                             // it just doesn't increment pc until wait condition is met
         let wait_opcode = 0xFB0Au16; // FX0A assign_vx_wait_for_key(x)
-                                     // This always returns Err
+                                     // Returns Err unless routine is registered
         let _ommited = 0x0AAAu16; // 0NNN exec_ml_subroutine_at(nnn)
 
         let mut chip = Peach8::new(TestingContext::new(0));
@@ -794,6 +1174,89 @@ mod opcodes_execution_tests {
             chip.execute(opcode),
             Err("Machine code subroutines not supported"),
         );
+        assert_eq!(chip.pc, START_ADDR);
+
+        chip.extensions
+            .add_routine(0x0DEF, |_, _| Err("Routine failed"))?;
+        assert_eq!(
+            chip.execute(OpCode::_0NNN { nnn: 0x0DEF }),
+            Err("Routine failed"),
+        );
+        assert_eq!(chip.pc, START_ADDR);
+
+        chip.extensions.add_routine(0x0ABC, |ctx, regs| {
+            regs.v[0] = ctx.gen_random() | 0x01;
+            *regs.i = 0x0300;
+            regs.memory[0x0300] = 0xAB;
+            Ok(())
+        })?;
+        let pc = chip.pc;
+        chip.execute(OpCode::_0NNN { nnn: 0x0ABC })?;
+        assert_ne!(chip.v[0], 0x00u8);
+        assert_eq!(chip.i, 0x0300u16);
+        assert_eq!(chip.memory[0x0300], 0xABu8);
+        assert_eq!(chip.pc, pc + 2);
+        Ok(())
+    }
+
+    /// Execute registered handler for undefined opcode
+    #[test]
+    fn execute_custom_instruction() -> Result<(), &'static str> {
+        let mut chip = Peach8::new(TestingContext::new(0));
+        // 0x5XY1 reads 0x42 into VX
+        chip.load(&[0x53, 0x41, 0x53, 0x42]);
+        chip.extensions
+            .add_instruction(0xF00F, 0x5001, |_, regs, raw| {
+                regs.v[(raw >> 8 & 0x0F) as usize] = 0x42;
+                Ok(())
+            })?;
+
+        chip.tick_chip()?;
+        assert_eq!(chip.v[3], 0x42u8);
+        assert_eq!(chip.pc, START_ADDR + 2);
+
+        assert_eq!(chip.tick_chip(), Err("Unknown operation code"));
+        assert_eq!(chip.pc, START_ADDR + 2);
+        Ok(())
+    }
+
+    /// Save registers into RPL user flags, which survive restart
+    #[test]
+    fn execute_fx75_fx85_rpl_flags() -> Result<(), &'static str> {
+        use crate::storage::{MemoryStorage, WithStorage};
+
+        // V0 = 1, V1 = 2, V2 = 3, save V0..V2, V1 = 0, read V0..V1, read V0..V8
+        let program = [
+            0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x75, 0x61, 0x00, 0xF1, 0x85, 0xF8, 0x85,
+        ];
+        let ctx = WithStorage::new(TestingContext::new(0), MemoryStorage::new());
+        let mut chip = Peach8::new(ctx);
+        chip.load(&program);
+        for _ in 0..6 {
+            chip.tick_chip()?;
+        }
+        assert_eq!(chip.v[..3], [1, 2, 3]);
+        assert_eq!(chip.tick_chip(), Err("RPL user flags hold only V0 to V7"));
+
+        // flags are not shared with other programs
+        let (_, storage) = chip.release().release();
+        let mut chip = Peach8::new(WithStorage::new(TestingContext::new(0), storage));
+        chip.load(&program[10..]);
+        chip.tick_chip()?;
+        assert_eq!(chip.v[..3], [0, 0, 0]);
+        // restarted program reads flags saved by the previous run
+        let (_, storage) = chip.release().release();
+        let mut chip = Peach8::new(WithStorage::new(TestingContext::new(0), storage));
+        chip.load(&program);
+        chip.pc = START_ADDR + 10;
+        chip.tick_chip()?;
+        assert_eq!(chip.v[..3], [1, 2, 0]);
+
+        let mut chip = Peach8::new(TestingContext::new(0));
+        assert_eq!(
+            chip.execute(OpCode::_FX75 { x: 0 }),
+            Err("Context has no storage for RPL user flags")
+        );
         Ok(())
     }
 
@@ -890,7 +1353,7 @@ mod opcodes_execution_tests {
             Err("Cannot enter subroutine, stack is full"),
         );
 
-        chip.stack.clear();
+        while chip.stack.pop().is_some() {}
         assert_eq!(
             chip.execute(OpCode::_2NNN { nnn: 0x100u16 }),
             Err("Attempted to jump out of program's address space"),
@@ -1234,7 +1697,6 @@ mod opcodes_execution_tests {
     #[test]
     fn execute_cxnn_assign_vx_random_and_nn() -> Result<(), &'static str> {
         // Not testing this opcode currently, tested in test roms
-        assert!(true);
         Ok(())
     }
 
@@ -1359,10 +1821,10 @@ mod opcodes_execution_tests {
     fn execute_fx07_assign_vx_delay_t() -> Result<(), &'static str> {
         let mut chip = Peach8::new(TestingContext::new(0));
         let opcode = OpCode::_FX07 { x: 0 };
-        chip.delay_timer.store(0xFFu8);
+        chip.timers.set_delay(0xFFu8);
 
         chip.execute(opcode)?;
-        assert_eq!(chip.delay_timer.load(), chip.v[0]);
+        assert_eq!(chip.timers.delay(), chip.v[0]);
         Ok(())
     }
 
@@ -1407,7 +1869,7 @@ mod opcodes_execution_tests {
         chip.assign_vx_nn(0, 0xFFu8)?;
 
         chip.execute(opcode)?;
-        assert_eq!(chip.delay_timer.load(), chip.v[0]);
+        assert_eq!(chip.timers.delay(), chip.v[0]);
         Ok(())
     }
 
@@ -1419,7 +1881,7 @@ mod opcodes_execution_tests {
         chip.assign_vx_nn(0, 0xFFu8)?;
 
         chip.execute(opcode)?;
-        assert_eq!(chip.sound_timer.load(), chip.v[0]);
+        assert_eq!(chip.timers.sound(), chip.v[0]);
         Ok(())
     }
 
@@ -1553,4 +2015,68 @@ mod opcodes_execution_tests {
         );
         Ok(())
     }
+
+    /// Extensions can set I to any value, operations using it must not overflow
+    #[test]
+    fn execute_with_i_out_of_address_space() {
+        let mut chip = Peach8::new(TestingContext::new(0));
+        chip.v[0] = 0xFF;
+        chip.v[1] = 0xFF;
+
+        let cases = [
+            (
+                OpCode::_DXYN { x: 0, y: 1, n: 1 },
+                "Attempted to read memory out of address space",
+            ),
+            (
+                OpCode::_FX1E { x: 0 },
+                "Attempted to set i out of address space",
+            ),
+            (
+                OpCode::_FX33 { x: 0 },
+                "Attempted to set memory out of address space",
+            ),
+            (
+                OpCode::_FX55 { x: 1 },
+                "Attempted to store data out of address space",
+            ),
+            (
+                OpCode::_FX65 { x: 1 },
+                "Attempted to load memory out of address space",
+            ),
+        ];
+        for &(opcode, error) in cases.iter() {
+            chip.i = 0xFFFF;
+            assert_eq!(chip.execute(opcode), Err(error), "{}", opcode);
+        }
+
+        assert_eq!(
+            chip.execute(OpCode::_BNNN { nnn: 0xFFF }),
+            Err("Attempted to set pc out of address space"),
+        );
+    }
+
+    /// Memory can be used up to its last byte
+    #[test]
+    fn execute_at_end_of_memory() -> Result<(), &'static str> {
+        let mut chip = Peach8::new(TestingContext::new(0));
+        chip.memory[MEM_LENGTH - 1] = 0x80;
+
+        chip.i = (MEM_LENGTH - 1) as u16;
+        chip.execute(OpCode::_DXYN { x: 0, y: 0, n: 1 })?;
+        assert_eq!(chip.frame.view().get_bit(0, 0), Some(&true));
+
+        chip.i = (MEM_LENGTH - 2) as u16;
+        chip.v[0] = 0x12;
+        chip.v[1] = 0xAB;
+        chip.execute(OpCode::_FX55 { x: 1 })?;
+        assert_eq!(chip.memory[MEM_LENGTH - 1], 0xAB);
+        assert_eq!(chip.i as usize, MEM_LENGTH);
+
+        chip.i = (MEM_LENGTH - 2) as u16;
+        chip.v = [0; 16];
+        chip.execute(OpCode::_FX65 { x: 1 })?;
+        assert_eq!(chip.v[..2], [0x12, 0xAB]);
+        Ok(())
+    }
 }
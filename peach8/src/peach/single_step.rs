@@ -0,0 +1,248 @@
+//! Single-step test vectors, generated by `gen-vectors` tool from `tools` crate
+//!
+//! Each gzipped JSON file in `test-data/vectors` holds cases of one opcode
+//! family. A case seeds the state of `Peach8`, decodes its opcode with
+//! `OpCode::try_from`, runs it with `Peach8::execute` and compares the
+//! resulting state with the expected one. Fetching in `tick_chip`, sampling of
+//! keys and instructions registered as extensions are bypassed, keys of a case
+//! are seeded directly.
+
+use core::convert::TryFrom;
+
+use std::fs::File;
+use std::path::Path;
+use std::string::String;
+use std::vec::Vec as StdVec;
+use std::{format, vec};
+
+use flate2::read::GzDecoder;
+use serde::Deserialize;
+
+use super::*;
+use crate::frame::{FrameView, MEM_LENGTH as FRAME_LENGTH};
+use crate::storage::{Flags, MemoryStorage};
+
+#[derive(Deserialize)]
+struct VectorState {
+    pc: u16,
+    i: u16,
+    v: [u8; 16],
+    stack: StdVec<u16>,
+    delay: u8,
+    sound: u8,
+    #[serde(default)]
+    flags: Flags,
+    ram: StdVec<(u16, u8)>,
+    frame: StdVec<(u8, u8)>,
+}
+
+#[derive(Deserialize)]
+struct Vector {
+    name: String,
+    opcode: u16,
+    keys: u16,
+    previous_keys: u16,
+    random: u8,
+    initial: VectorState,
+    #[serde(rename = "final")]
+    result: Option<VectorState>,
+}
+
+/// `Context` returning the random number of a case, with its RPL user flags
+struct VectorContext {
+    random: u8,
+    storage: MemoryStorage,
+}
+
+impl Context for VectorContext {
+    fn on_frame(&mut self, _: FrameView<'_>) {}
+
+    fn sound_on(&mut self) {}
+
+    fn sound_off(&mut self) {}
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        [false; 16]
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.random
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        Some(&mut self.storage)
+    }
+}
+
+/// Memory of `state`, with its bytes written over `memory`
+fn memory(mut memory: [u8; MEM_LENGTH], state: &VectorState) -> [u8; MEM_LENGTH] {
+    for &(address, byte) in &state.ram {
+        memory[address as usize] = byte;
+    }
+    memory
+}
+
+fn frame(state: &VectorState) -> [u8; FRAME_LENGTH] {
+    let mut frame = [0; FRAME_LENGTH];
+    for &(n, byte) in &state.frame {
+        frame[n as usize] = byte;
+    }
+    frame
+}
+
+fn key_state(previous: bool, held: bool) -> KeyState {
+    match (previous, held) {
+        (false, true) => KeyState::Pressed,
+        (true, true) => KeyState::Down,
+        (true, false) => KeyState::Released,
+        (false, false) => KeyState::Up,
+    }
+}
+
+fn seed(vector: &Vector) -> Peach8<VectorContext> {
+    let state = &vector.initial;
+    let mut chip = Peach8::new(VectorContext {
+        random: vector.random,
+        storage: MemoryStorage::new(),
+    });
+    let rom = chip.rom_hash();
+    chip.ctx.storage.save(rom, &state.flags).unwrap();
+    chip.pc = state.pc;
+    chip.i = state.i;
+    chip.v = state.v;
+    chip.stack = state.stack.iter().copied().collect();
+    chip.timers.set_delay(state.delay);
+    chip.timers.set_sound(state.sound);
+    chip.memory = memory([0; MEM_LENGTH], state);
+    chip.frame.as_raw_mut().copy_from_slice(&frame(state));
+    for (n, key) in chip.keys.iter_mut().enumerate() {
+        *key = key_state(
+            vector.previous_keys & 1 << n != 0,
+            vector.keys & 1 << n != 0,
+        );
+    }
+    chip
+}
+
+/// Describe the first difference between the state of `chip` and `expected`
+///
+/// Memory of `expected` lists only bytes changed since the `initial` state.
+fn diff(
+    chip: &mut Peach8<VectorContext>,
+    initial: &VectorState,
+    expected: &VectorState,
+) -> Option<String> {
+    let mut flags = Flags::default();
+    let rom = chip.rom_hash();
+    chip.ctx.storage.load(rom, &mut flags).unwrap();
+    let state = chip.state();
+    if state.pc != expected.pc {
+        return Some(format!("PC {:#05X} != {:#05X}", state.pc, expected.pc));
+    }
+    if state.i != expected.i {
+        return Some(format!("I {:#05X} != {:#05X}", state.i, expected.i));
+    }
+    if state.v != &expected.v {
+        return Some(format!("V {:02X?} != {:02X?}", state.v, expected.v));
+    }
+    if state.stack != &expected.stack[..] {
+        return Some(format!(
+            "stack {:03X?} != {:03X?}",
+            state.stack, expected.stack
+        ));
+    }
+    if (state.delay, state.sound) != (expected.delay, expected.sound) {
+        return Some(format!(
+            "timers (delay, sound) {:?} != {:?}",
+            (state.delay, state.sound),
+            (expected.delay, expected.sound)
+        ));
+    }
+    if flags != expected.flags {
+        return Some(format!(
+            "RPL user flags {:02X?} != {:02X?}",
+            flags, expected.flags
+        ));
+    }
+    let memory = memory(memory([0; MEM_LENGTH], initial), expected);
+    if let Some(at) = (0..MEM_LENGTH).find(|&a| state.memory[a] != memory[a]) {
+        return Some(format!(
+            "memory at {:#05X} {:#04X} != {:#04X}",
+            at, state.memory[at], memory[at]
+        ));
+    }
+    let frame = frame(expected);
+    let raw = chip.frame.view().as_raw();
+    if let Some(at) = (0..FRAME_LENGTH).find(|&n| raw[n] != frame[n]) {
+        return Some(format!(
+            "frame at row {} byte {} {:08b} != {:08b}",
+            at / 8,
+            at % 8,
+            raw[at],
+            frame[at]
+        ));
+    }
+    None
+}
+
+/// Run all cases of a file, returning descriptions of failed ones
+fn run(file: &str) -> StdVec<String> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test-data/vectors")
+        .join(file);
+    let json = GzDecoder::new(File::open(&path).unwrap());
+    let vectors: StdVec<Vector> = serde_json::from_reader(json).unwrap();
+    assert!(!vectors.is_empty(), "no cases in {}", file);
+
+    let mut failures = vec![];
+    for vector in &vectors {
+        let mut chip = seed(vector);
+        let result = OpCode::try_from(vector.opcode).and_then(|opcode| chip.execute(opcode));
+        let failure = match (&result, &vector.result) {
+            (Ok(()), Some(expected)) => diff(&mut chip, &vector.initial, expected),
+            (Err(_), None) => None,
+            (Ok(()), None) => Some(String::from("expected an error")),
+            (Err(e), Some(_)) => Some(format!("unexpected error: {}", e)),
+        };
+        if let Some(failure) = failure {
+            failures.push(format!("{}: {}", vector.name, failure));
+        }
+    }
+    failures
+}
+
+macro_rules! vectors {
+    ($($test:ident => $file:expr,)*) => {
+        $(
+            #[test]
+            fn $test() {
+                let failures = run($file);
+                assert!(
+                    failures.is_empty(),
+                    "{} cases failed, first ones:\n{}",
+                    failures.len(),
+                    failures[..failures.len().min(10)].join("\n")
+                );
+            }
+        )*
+    };
+}
+
+vectors! {
+    vectors_0nnn => "0nnn.json.gz",
+    vectors_1nnn => "1nnn.json.gz",
+    vectors_2nnn => "2nnn.json.gz",
+    vectors_3xnn => "3xnn.json.gz",
+    vectors_4xnn => "4xnn.json.gz",
+    vectors_5xy0 => "5xy0.json.gz",
+    vectors_6xnn => "6xnn.json.gz",
+    vectors_7xnn => "7xnn.json.gz",
+    vectors_8xyn => "8xyn.json.gz",
+    vectors_9xy0 => "9xy0.json.gz",
+    vectors_annn => "annn.json.gz",
+    vectors_bnnn => "bnnn.json.gz",
+    vectors_cxnn => "cxnn.json.gz",
+    vectors_dxyn => "dxyn.json.gz",
+    vectors_exnn => "exnn.json.gz",
+    vectors_fxnn => "fxnn.json.gz",
+}
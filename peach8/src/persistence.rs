@@ -0,0 +1,268 @@
+//! Phosphor persistence
+//!
+//! CHIP-8 programs move sprites by erasing them with XOR and drawing them
+//! again, so moving objects are missing from some of the frames and flicker.
+//! `Persistence` wraps a `Context` and emulates slowly fading phosphor of old
+//! screens: a pixel stays visible for several frames after it was turned off.
+//!
+//! Frames passed to `Context::on_frame` during each period between
+//! `Context::on_vblank` calls are merged together. At each vblank the wrapped
+//! context receives a mask of pixels lit in any of the last
+//! `Config::frames` periods, so it's updated once per vblank instead of after
+//! each instruction. Multi-level intensity of pixels, decaying by
+//! `Config::decay` each period, is available with `Persistence::intensity`
+//! and `Persistence::fill_intensity`, eg. for grayscale displays.
+
+use crate::context::Context;
+use crate::frame::{Frame, FrameView, HEIGHT, MEM_LENGTH, WIDTH};
+use crate::peach::State;
+use crate::storage::Storage;
+
+/// Configuration of `Persistence`
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Config {
+    /// Number of vblank periods a pixel stays lit in the mask, 1 disables persistence
+    pub frames: u8,
+    /// Intensity lost by a pixel in each vblank period after it was turned off
+    pub decay: u8,
+}
+
+impl Default for Config {
+    /// Keep pixels for 3 periods, fading in 4 levels
+    fn default() -> Self {
+        Self {
+            frames: 3,
+            decay: 64,
+        }
+    }
+}
+
+/// `Context` adapter keeping turned off pixels visible for a few frames
+pub struct Persistence<C> {
+    pub inner: C,
+    config: Config,
+    /// Pixels lit in the current vblank period
+    seen: Frame,
+    /// Number of finished vblank periods since each pixel was last lit
+    ages: [u8; WIDTH * HEIGHT],
+    mask: Frame,
+}
+
+impl<C: Context> Persistence<C> {
+    pub fn new(inner: C, config: Config) -> Self {
+        Self {
+            inner,
+            config,
+            seen: Frame::new(),
+            ages: [u8::MAX; WIDTH * HEIGHT],
+            mask: Frame::new(),
+        }
+    }
+
+    /// Drop the filter, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// Mask of pixels lit in any of the last `Config::frames` periods, as
+    /// passed to the wrapped context at the last vblank
+    pub fn mask(&self) -> FrameView<'_> {
+        self.mask.view()
+    }
+
+    /// Intensity of a pixel at the last vblank, 255 if it was lit in the last
+    /// period, 0 if it faded out completely
+    pub fn intensity(&self, x: usize, y: usize) -> Option<u8> {
+        if x >= WIDTH || y >= HEIGHT {
+            return None;
+        }
+        Some(self.level(self.ages[y * WIDTH + x]))
+    }
+
+    /// Fill the buffer with intensity of each pixel, row by row, returns
+    /// number of written bytes
+    pub fn fill_intensity(&self, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let len = self.ages.len();
+        if buf.len() < len {
+            return Err("Buffer too small for the image");
+        }
+        buf.iter_mut()
+            .zip(self.ages.iter())
+            .for_each(|(pixel, &age)| *pixel = self.level(age));
+        Ok(len)
+    }
+
+    fn level(&self, age: u8) -> u8 {
+        if age == u8::MAX {
+            return 0;
+        }
+        u8::MAX.saturating_sub(age.saturating_mul(self.config.decay))
+    }
+
+    fn merge(&mut self, frame: FrameView<'_>) {
+        self.seen
+            .as_raw_mut()
+            .iter_mut()
+            .zip(frame.as_raw())
+            .for_each(|(seen, &lit)| *seen |= lit);
+    }
+
+    fn age(&mut self) {
+        let frames = self.config.frames.max(1);
+        let seen = self.seen.view().as_raw();
+        let mask = self.mask.as_raw_mut();
+        for n in 0..MEM_LENGTH {
+            let (ages, mut byte) = (&mut self.ages[n * 8..n * 8 + 8], 0);
+            for (bit, age) in ages.iter_mut().enumerate() {
+                let lit = seen[n] & 0x80 >> bit != 0;
+                *age = if lit { 0 } else { age.saturating_add(1) };
+                if *age < frames {
+                    byte |= 0x80 >> bit;
+                }
+            }
+            mask[n] = byte;
+        }
+        self.seen = Frame::new();
+    }
+}
+
+impl<C: Context> Context for Persistence<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.merge(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.age();
+        self.inner.on_frame(self.mask.view());
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::assert_eq_2d;
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+    use crate::testing::ToMask;
+
+    fn frame(pixels: &[(usize, usize)]) -> Frame {
+        let mut frame = Frame::new();
+        pixels
+            .iter()
+            .for_each(|&(x, y)| frame.xor_bit(x, y, true).unwrap());
+        frame
+    }
+
+    #[test]
+    fn decay() {
+        let mut filter = Persistence::new(TestingContext::new(0), Config::default());
+        filter.on_frame(frame(&[(0, 0)]).view());
+        assert!(filter.inner.get_frame().is_none());
+
+        let mut levels = [0; 5];
+        let mut masks = [false; 5];
+        for (level, lit) in levels.iter_mut().zip(masks.iter_mut()) {
+            filter.on_vblank();
+            *level = filter.intensity(0, 0).unwrap();
+            *lit = *filter.mask().get_bit(0, 0).unwrap();
+            assert_eq!(filter.inner.get_frame(), Some(&filter.mask().to_mask()));
+            filter.on_frame(Frame::new().view());
+        }
+        assert_eq!(levels, [255, 191, 127, 63, 0]);
+        assert_eq!(masks, [true, true, true, false, false]);
+        assert_eq!(filter.intensity(1, 0), Some(0));
+        assert_eq!(filter.intensity(64, 0), None);
+
+        let mut buf = [0xAA; 64 * 32];
+        filter.on_frame(frame(&[(1, 0)]).view());
+        filter.on_vblank();
+        assert_eq!(filter.fill_intensity(&mut buf), Ok(buf.len()));
+        assert_eq!(&buf[..3], &[0, 255, 0]);
+        assert_eq!(
+            filter.fill_intensity(&mut buf[..10]),
+            Err("Buffer too small for the image")
+        );
+    }
+
+    #[test]
+    fn disabled() {
+        let config = Config {
+            frames: 1,
+            decay: 255,
+        };
+        let mut filter = Persistence::new(TestingContext::new(0), config);
+        filter.on_frame(frame(&[(5, 5)]).view());
+        filter.on_vblank();
+        assert_eq!(filter.mask().get_bit(5, 5), Some(&true));
+        filter.on_vblank();
+        assert_eq!(filter.mask().get_bit(5, 5), Some(&false));
+        assert_eq!(filter.intensity(5, 5), Some(0));
+    }
+
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0xA0, 0x50, // 0x200 LD I, 0x050
+        0xD0, 0x05, // 0x202 DRW V0, V0, 5
+        0xD0, 0x05, // 0x204 DRW V0, V0, 5
+        0x12, 0x02, // 0x206 JP 0x202
+    ];
+
+    #[rustfmt::skip]
+    #[test]
+    fn flicker() {
+        let mut chip = Builder::new()
+            .with_context(Persistence::new(TestingContext::new(0), Config::default()))
+            .with_program(PROGRAM)
+            .build()
+            .unwrap();
+        // each period ends with the sprite erased by the second DRW
+        for _ in 0..8 {
+            for _ in 0..6 {
+                chip.tick_chip().unwrap();
+            }
+            chip.tick_timers();
+            assert_eq_2d!(
+                x_range: .., y_range: ..;
+                chip.ctx.inner.get_frame().unwrap().clone(), "####
+                                                               #..#
+                                                               #..#
+                                                               #..#
+                                                               ####".to_mask()
+            );
+        }
+    }
+}
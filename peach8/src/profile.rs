@@ -0,0 +1,448 @@
+//! Execution profiling
+//!
+//! `Profiler` wraps a `Context` and gathers statistics about executed program:
+//! - number of executions of each address,
+//! - inclusive and exclusive instruction counts of subroutines, based on
+//!   `2NNN`/`00EE` pairs,
+//! - number of draw calls and pixels touched by them in each 60Hz frame.
+//!
+//! The statistics help to find out, whether a program spends its budget in
+//! busy-wait loops or in `DXYN`, and so to tune the frequency of `tick_chip`.
+//!
+//! # Note
+//! Counters of executions take 16KiB of memory.
+//! ```ignore
+//! let ctx = Profiler::new(ctx);
+//! // ...run the program
+//! println!("{}", chip.ctx.report(chip.state().memory, 10));
+//! ```
+
+use core::cmp::{max, min};
+use core::convert::TryFrom;
+use core::fmt;
+
+use heapless::{consts::U64, Vec};
+
+use crate::context::Context;
+use crate::frame::{FrameView, HEIGHT, WIDTH};
+use crate::opcode::OpCode;
+use crate::peach::{State, MEM_LENGTH};
+use crate::storage::Storage;
+
+/// Statistics of a subroutine
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Subroutine {
+    /// Address of the first instruction
+    pub addr: u16,
+    pub calls: u32,
+    /// Instructions executed from entering the subroutine to returning from it
+    pub inclusive: u64,
+    /// Instructions executed by the subroutine itself, excluding called subroutines
+    pub exclusive: u64,
+}
+
+/// Statistics gathered during 60Hz frame
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct FrameStats {
+    pub instructions: u32,
+    pub draw_calls: u32,
+    /// Pixels flipped by `DXYN`, after clipping at screen edges
+    pub pixels: u32,
+    /// Executions of `FX07`, which are usual in busy-wait loops
+    pub timer_polls: u32,
+}
+
+/// Number of executions of an instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct HotSpot {
+    pub addr: u16,
+    pub count: u32,
+    /// Raw opcode currently stored at `addr`
+    pub opcode: u16,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Call {
+    addr: u16,
+    entered_at: u64,
+    children: u64,
+}
+
+/// `Context` adapter profiling executed program
+pub struct Profiler<C> {
+    pub inner: C,
+    executions: [u32; MEM_LENGTH],
+    total: u64,
+    calls: Vec<Call, U64>,
+    subroutines: Vec<Subroutine, U64>,
+    frame: FrameStats,
+    last_frame: FrameStats,
+    peak_frame: FrameStats,
+    frames: u32,
+}
+
+impl<C: Context> Profiler<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            executions: [0; MEM_LENGTH],
+            total: 0,
+            calls: Vec::new(),
+            subroutines: Vec::new(),
+            frame: FrameStats::default(),
+            last_frame: FrameStats::default(),
+            peak_frame: FrameStats::default(),
+            frames: 0,
+        }
+    }
+
+    /// Drop the profiler, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    /// Number of executions of instruction at given address
+    pub fn executions(&self, addr: u16) -> u32 {
+        self.executions.get(addr as usize).copied().unwrap_or(0)
+    }
+
+    /// Number of all executed instructions
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Statistics of called subroutines, in order of first call
+    ///
+    /// Only first 64 distinct subroutines are tracked. Recursive calls are
+    /// counted into inclusive count of each level of recursion.
+    pub fn subroutines(&self) -> &[Subroutine] {
+        &self.subroutines
+    }
+
+    /// Statistics of the last finished frame
+    pub fn last_frame(&self) -> FrameStats {
+        self.last_frame
+    }
+
+    /// Maximum of each statistic over finished frames
+    pub fn peak_frame(&self) -> FrameStats {
+        self.peak_frame
+    }
+
+    /// Number of finished frames
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Iterate over executed addresses, from the most often executed
+    ///
+    /// `memory` is used to read opcodes, eg. `Peach8::state().memory`.
+    pub fn hot_spots<'a>(&'a self, memory: &'a [u8]) -> impl Iterator<Item = HotSpot> + 'a {
+        let mut previous: Option<(u32, usize)> = None;
+        core::iter::from_fn(move || {
+            // select the next (count, addr) pair lower than previous one, so
+            // no buffer for sorting is needed
+            let (count, addr) = self
+                .executions
+                .iter()
+                .enumerate()
+                .filter(|&(_, &count)| count > 0)
+                .map(|(addr, &count)| (count, MEM_LENGTH - addr))
+                .filter(|&key| previous.is_none_or(|prev| key < prev))
+                .max()?;
+            previous = Some((count, addr));
+            let addr = MEM_LENGTH - addr;
+            let opcode = match memory.get(addr..addr + 2) {
+                Some(bytes) => u16::from_be_bytes([bytes[0], bytes[1]]),
+                None => 0,
+            };
+            Some(HotSpot {
+                addr: addr as u16,
+                count,
+                opcode,
+            })
+        })
+    }
+
+    /// Human readable report with `top` hot spots
+    pub fn report<'a>(&'a self, memory: &'a [u8], top: usize) -> Report<'a, C> {
+        Report {
+            profiler: self,
+            memory,
+            top,
+        }
+    }
+
+    fn enter(&mut self, addr: u16) {
+        let call = Call {
+            addr,
+            entered_at: self.total,
+            children: 0,
+        };
+        // deeper calls than the stack of `Peach8` fail anyway
+        let _ = self.calls.push(call);
+    }
+
+    fn leave(&mut self) {
+        if let Some(call) = self.calls.pop() {
+            let inclusive = self.total - call.entered_at;
+            if let Some(parent) = self.calls.last_mut() {
+                parent.children += inclusive;
+            }
+            let stats = match self.subroutines.iter_mut().find(|s| s.addr == call.addr) {
+                Some(stats) => stats,
+                None => {
+                    let stats = Subroutine {
+                        addr: call.addr,
+                        ..Subroutine::default()
+                    };
+                    if self.subroutines.push(stats).is_err() {
+                        return;
+                    }
+                    self.subroutines.last_mut().unwrap()
+                }
+            };
+            stats.calls += 1;
+            stats.inclusive += inclusive;
+            stats.exclusive += inclusive - call.children;
+        }
+    }
+
+    fn draw(&mut self, state: &State<'_>, x: u8, y: u8, n: u8) {
+        let x = state.v[x as usize] as usize % WIDTH;
+        let y = state.v[y as usize] as usize % HEIGHT;
+        let rows = min(n as usize, HEIGHT - y);
+        let visible = 0xFFu8 << (8 - min(8, WIDTH - x));
+        let pixels: u32 = (0..rows)
+            .filter_map(|row| state.memory.get(state.i as usize + row))
+            .map(|byte| (byte & visible).count_ones())
+            .sum();
+        self.frame.draw_calls += 1;
+        self.frame.pixels += pixels;
+    }
+}
+
+impl<C: Context> Context for Profiler<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        if let Some(count) = self.executions.get_mut(state.pc as usize) {
+            *count = count.saturating_add(1);
+        }
+        self.total += 1;
+        self.frame.instructions += 1;
+        match OpCode::try_from(opcode) {
+            Ok(OpCode::_2NNN { nnn }) => self.enter(nnn),
+            Ok(OpCode::_00EE) => self.leave(),
+            Ok(OpCode::_DXYN { x, y, n }) => self.draw(state, x, y, n),
+            Ok(OpCode::_FX07 { .. }) => self.frame.timer_polls += 1,
+            _ => (),
+        }
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        let frame = core::mem::take(&mut self.frame);
+        self.peak_frame = FrameStats {
+            instructions: max(self.peak_frame.instructions, frame.instructions),
+            draw_calls: max(self.peak_frame.draw_calls, frame.draw_calls),
+            pixels: max(self.peak_frame.pixels, frame.pixels),
+            timer_polls: max(self.peak_frame.timer_polls, frame.timer_polls),
+        };
+        self.last_frame = frame;
+        self.frames += 1;
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+impl fmt::Display for HotSpot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "0x{:03X} {:>10} {:04X}",
+            self.addr, self.count, self.opcode
+        )?;
+        match OpCode::try_from(self.opcode) {
+            Ok(opcode) => write!(f, " {}", opcode),
+            Err(_) => write!(f, " DW 0x{:04X}", self.opcode),
+        }
+    }
+}
+
+/// Report of the `Profiler`, see `Profiler::report`
+pub struct Report<'a, C> {
+    profiler: &'a Profiler<C>,
+    memory: &'a [u8],
+    top: usize,
+}
+
+impl<'a, C: Context> fmt::Display for Report<'a, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let profiler = self.profiler;
+        writeln!(f, "instructions: {}", profiler.total)?;
+        writeln!(f, "hot spots:")?;
+        for spot in profiler.hot_spots(self.memory).take(self.top) {
+            writeln!(f, "  {}", spot)?;
+        }
+        writeln!(f, "subroutines:")?;
+        for s in profiler.subroutines() {
+            writeln!(
+                f,
+                "  0x{:03X} calls: {} inclusive: {} exclusive: {}",
+                s.addr, s.calls, s.inclusive, s.exclusive
+            )?;
+        }
+        let (last, peak) = (profiler.last_frame, profiler.peak_frame);
+        writeln!(f, "frames: {}", profiler.frames)?;
+        writeln!(
+            f,
+            "  last: instructions: {} draw calls: {} pixels: {} timer polls: {}",
+            last.instructions, last.draw_calls, last.pixels, last.timer_polls
+        )?;
+        writeln!(
+            f,
+            "  peak: instructions: {} draw calls: {} pixels: {} timer polls: {}",
+            peak.instructions, peak.draw_calls, peak.pixels, peak.timer_polls
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::ToString;
+    use std::vec::Vec;
+
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0x22, 0x06, // 0x200 CALL 0x206
+        0x12, 0x00, // 0x202 JP 0x200
+        0x00, 0x00, // 0x204
+        0x22, 0x0C, // 0x206 CALL 0x20C
+        0xD0, 0x03, // 0x208 DRW V0, V0, 3
+        0x00, 0xEE, // 0x20A RET
+        0xF0, 0x07, // 0x20C LD V0, DT
+        0x00, 0xEE, // 0x20E RET
+    ];
+
+    #[test]
+    fn profile() {
+        let mut chip = Builder::new()
+            .with_context(Profiler::new(TestingContext::new(0)))
+            .with_program(PROGRAM)
+            .build()
+            .unwrap();
+        // I points at empty memory, so drawing flips no pixels
+        for _ in 0..7 {
+            chip.tick_chip().unwrap();
+        }
+        chip.tick_timers();
+        for _ in 0..7 {
+            chip.tick_chip().unwrap();
+        }
+        let profiler = &chip.ctx;
+
+        assert_eq!(profiler.total(), 14);
+        assert_eq!(profiler.executions(0x200), 2);
+        assert_eq!(profiler.executions(0x20C), 2);
+        assert_eq!(profiler.executions(0x204), 0);
+        assert_eq!(
+            profiler.subroutines(),
+            &[
+                Subroutine {
+                    addr: 0x20C,
+                    calls: 2,
+                    inclusive: 4,
+                    exclusive: 4
+                },
+                Subroutine {
+                    addr: 0x206,
+                    calls: 2,
+                    inclusive: 10,
+                    exclusive: 6
+                },
+            ]
+        );
+        assert_eq!(profiler.frames(), 1);
+        assert_eq!(
+            profiler.last_frame(),
+            FrameStats {
+                instructions: 7,
+                draw_calls: 1,
+                pixels: 0,
+                timer_polls: 1
+            }
+        );
+
+        let memory = chip.state().memory;
+        let spots: Vec<_> = profiler.hot_spots(memory).collect();
+        assert_eq!(spots.len(), 7);
+        assert_eq!(
+            spots[0],
+            HotSpot {
+                addr: 0x200,
+                count: 2,
+                opcode: 0x2206
+            }
+        );
+        assert_eq!(spots[1].addr, 0x202);
+        assert_eq!(
+            spots[6],
+            HotSpot {
+                addr: 0x20E,
+                count: 2,
+                opcode: 0x00EE
+            }
+        );
+        assert_eq!(spots[3].to_string(), "0x208          2 D003 DRW V0, V0, 3");
+
+        let report = profiler.report(memory, 2).to_string();
+        assert!(report.contains("instructions: 14\n"));
+        assert!(report.contains("  0x202          2 1200 JP 0x200\n"));
+        assert!(!report.contains("0x206          2"));
+    }
+
+    #[test]
+    fn pixels_touched() {
+        let mut chip = Builder::new()
+            .with_context(Profiler::new(TestingContext::new(0)))
+            // LD V0, 0x3E; LD V1, 0x1F; LD I, 0x050; DRW V0, V1, 5
+            .with_program(&[0x60, 0x3E, 0x61, 0x1F, 0xA0, 0x50, 0xD0, 0x15])
+            .build()
+            .unwrap();
+        for _ in 0..4 {
+            chip.tick_chip().unwrap();
+        }
+        chip.tick_timers();
+        // only two left columns of the first row of "0" are visible
+        assert_eq!(chip.ctx.last_frame().pixels, 2);
+        assert_eq!(chip.ctx.last_frame().draw_calls, 1);
+    }
+}
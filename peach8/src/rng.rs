@@ -0,0 +1,50 @@
+//! Seeded pseudo-random numbers
+//!
+//! `Rng` draws random values of `tas::Session` and of contexts of tests,
+//! the same on every platform for the same seed.
+
+/// Seeded pseudo-random generator (PCG-XSH-RR), reproducible across platforms
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rng(u64);
+
+impl Rng {
+    const MULTIPLIER: u64 = 6_364_136_223_846_793_005;
+    const INCREMENT: u64 = 1_442_695_040_888_963_407;
+
+    pub fn new(seed: u64) -> Self {
+        let mut rng = Self(seed.wrapping_add(Self::INCREMENT));
+        rng.next_u32();
+        rng
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let state = self.0;
+        self.0 = state
+            .wrapping_mul(Self::MULTIPLIER)
+            .wrapping_add(Self::INCREMENT);
+        let xorshifted = (((state >> 18) ^ state) >> 27) as u32;
+        xorshifted.rotate_right((state >> 59) as u32)
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        (self.next_u32() >> 24) as u8
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn rng() {
+        let mut a = Rng::new(7);
+        let mut b = Rng::new(7);
+        let mut c = Rng::new(8);
+        let a: Vec<_> = (0..16).map(|_| a.next_u8()).collect();
+        let b: Vec<_> = (0..16).map(|_| b.next_u8()).collect();
+        let c: Vec<_> = (0..16).map(|_| c.next_u8()).collect();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+}
@@ -0,0 +1,339 @@
+//! Pacing of emulation by a monotonic clock
+//!
+//! `Runner` reads a `Clock` on each `poll` and runs every chip and timer tick
+//! that became due since the previous one, so the loop calling it doesn't
+//! need to keep time. After a stall only a bounded amount of time is caught
+//! up, the rest is dropped and reported.
+//!
+//! ```ignore
+//! let mut runner = Runner::new(StdClock::new());
+//! loop {
+//!     let report = runner.poll(&mut chip)?;
+//!     if report.is_behind() {
+//!         // host is too slow, or was suspended
+//!     }
+//! }
+//! ```
+
+use core::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
+
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::context::Context;
+use crate::peach::Peach8;
+use crate::schedule::{Schedule, Tick, NANOS};
+
+/// Monotonic counter of time, eg. a free running hardware timer
+pub trait Clock {
+    /// Frequency of the counter in Hz
+    fn frequency(&self) -> u32;
+
+    /// Current value of the counter, which may wrap around
+    ///
+    /// # Note
+    /// `Runner` has to be polled at least once per period of wrapping.
+    fn now(&mut self) -> u32;
+}
+
+/// `Clock` counting microseconds of `std::time::Instant`
+#[cfg(feature = "std")]
+#[derive(Copy, Clone, Debug)]
+pub struct StdClock(Instant);
+
+#[cfg(feature = "std")]
+impl StdClock {
+    pub fn new() -> Self {
+        Self(Instant::now())
+    }
+}
+
+#[cfg(feature = "std")]
+impl Default for StdClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for StdClock {
+    fn frequency(&self) -> u32 {
+        1_000_000
+    }
+
+    fn now(&mut self) -> u32 {
+        self.0.elapsed().as_micros() as u32
+    }
+}
+
+/// Ticks run by a single `Runner::poll`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Report {
+    pub chip_ticks: u32,
+    pub timers_ticks: u32,
+    /// Time not emulated, because more than the limit of catch-up was due
+    pub dropped: Duration,
+}
+
+impl Report {
+    /// Whether emulation fell behind real time
+    pub fn is_behind(&self) -> bool {
+        self.dropped > Duration::ZERO
+    }
+}
+
+/// Runs chip and timers of `Peach8` at their frequencies in time of a `Clock`
+///
+/// Ticks follow a `schedule::Schedule` in emulated time, which follows the
+/// clock, scaled by the speed.
+pub struct Runner<K: Clock> {
+    pub clock: K,
+    schedule: Schedule,
+    /// Speed as numerator and denominator
+    speed: (u32, u32),
+    max_catch_up: Duration,
+    /// Reading of the clock at the previous poll
+    last: Option<u32>,
+    /// Emulated time in nanoseconds since the start of the current second of ticks
+    time: u64,
+    /// Remainder of conversion of clock ticks into nanoseconds
+    rest: u128,
+    dropped: Duration,
+}
+
+impl<K: Clock> Runner<K> {
+    /// Runner of chip at 500Hz and timers at 60Hz, at normal speed, catching up at most 100ms
+    pub fn new(clock: K) -> Self {
+        assert!(clock.frequency() > 0, "Frequency must be positive");
+        Self {
+            clock,
+            schedule: Schedule::default(),
+            speed: (1, 1),
+            max_catch_up: Duration::from_millis(100),
+            last: None,
+            time: 0,
+            rest: 0,
+            dropped: Duration::ZERO,
+        }
+    }
+
+    /// Set frequencies of chip and timers in Hz
+    ///
+    /// Panics when any frequency is 0.
+    pub fn with_frequencies(mut self, chip_freq: u32, timers_freq: u32) -> Self {
+        self.schedule = Schedule::new(chip_freq, timers_freq);
+        self
+    }
+
+    /// Set speed of emulation, see `set_speed`
+    pub fn with_speed(mut self, numerator: u32, denominator: u32) -> Self {
+        self.set_speed(numerator, denominator);
+        self
+    }
+
+    /// Set the longest time caught up by a single poll
+    pub fn with_max_catch_up(mut self, max_catch_up: Duration) -> Self {
+        self.max_catch_up = max_catch_up;
+        self
+    }
+
+    /// Run emulated time `numerator / denominator` times as fast as the clock
+    ///
+    /// Panics when any part of the speed is 0.
+    pub fn set_speed(&mut self, numerator: u32, denominator: u32) {
+        assert!(numerator > 0 && denominator > 0, "Speed must be positive");
+        self.speed = (numerator, denominator);
+        self.rest = 0;
+    }
+
+    /// Speed as numerator and denominator
+    pub fn speed(&self) -> (u32, u32) {
+        self.speed
+    }
+
+    /// Total time dropped since the start, because emulation fell behind
+    pub fn dropped(&self) -> Duration {
+        self.dropped
+    }
+
+    /// Read the clock and run ticks that became due since the previous poll
+    ///
+    /// The first poll only starts measuring time. Stops at the first error of
+    /// `Peach8::tick_chip`.
+    pub fn poll<C: Context>(&mut self, chip: &mut Peach8<C>) -> Result<Report, &'static str> {
+        let now = self.clock.now();
+        if let Some(last) = self.last {
+            self.advance(now.wrapping_sub(last));
+        }
+        self.last = Some(now);
+
+        let mut report = Report::default();
+        let max_catch_up = self.max_catch_up.as_nanos().min(u64::MAX as u128) as u64;
+        let last_tick = self.schedule.last_tick().as_nanos() as u64;
+        let limit = last_tick.saturating_add(max_catch_up);
+        if self.time > limit {
+            report.dropped = Duration::from_nanos(self.time - limit);
+            self.dropped += report.dropped;
+            self.time = limit;
+            warn!("Emulation fell behind, dropped {:?}", report.dropped);
+        }
+
+        while self.schedule.is_due(Duration::from_nanos(self.time)) {
+            match self.schedule.step(chip)? {
+                Tick::Chip => report.chip_ticks += 1,
+                Tick::Timers => report.timers_ticks += 1,
+            }
+        }
+        // time is counted from the second the schedule is rebased to
+        self.time -= self.schedule.rebase() * NANOS;
+        Ok(report)
+    }
+
+    /// Drop the runner and release held clock
+    pub fn release(self) -> K {
+        self.clock
+    }
+
+    /// Advance emulated time by `ticks` of the clock
+    fn advance(&mut self, ticks: u32) {
+        let (numerator, denominator) = self.speed;
+        let scaled = ticks as u128 * NANOS as u128 * numerator as u128 + self.rest;
+        let divisor = self.clock.frequency() as u128 * denominator as u128;
+        self.time += (scaled / divisor) as u64;
+        self.rest = scaled % divisor;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+
+    /// `Clock` moved by hand
+    struct ManualClock {
+        frequency: u32,
+        now: u32,
+    }
+
+    impl Clock for ManualClock {
+        fn frequency(&self) -> u32 {
+            self.frequency
+        }
+
+        fn now(&mut self) -> u32 {
+            self.now
+        }
+    }
+
+    fn chip() -> Peach8<TestingContext> {
+        // jump to itself
+        Builder::new()
+            .with_context(TestingContext::new(0))
+            .with_program(&[0x12, 0x00])
+            .build()
+            .unwrap()
+    }
+
+    fn runner(frequency: u32, now: u32) -> Runner<ManualClock> {
+        Runner::new(ManualClock { frequency, now })
+    }
+
+    /// Poll `polls` times, moving the clock by `step` before each, and sum the ticks
+    fn run(
+        runner: &mut Runner<ManualClock>,
+        chip: &mut Peach8<TestingContext>,
+        polls: u32,
+        step: u32,
+    ) -> Report {
+        let mut total = Report::default();
+        for _ in 0..polls {
+            runner.clock.now = runner.clock.now.wrapping_add(step);
+            let report = runner.poll(chip).unwrap();
+            total.chip_ticks += report.chip_ticks;
+            total.timers_ticks += report.timers_ticks;
+            total.dropped += report.dropped;
+        }
+        total
+    }
+
+    #[test]
+    fn runs_ticks_due() {
+        let mut chip = chip();
+        let mut runner = runner(1000, 0);
+        assert_eq!(runner.poll(&mut chip), Ok(Report::default()));
+
+        let report = run(&mut runner, &mut chip, 1000, 1);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (500, 60));
+        assert!(!report.is_behind());
+        assert_eq!(chip.cycles(), 500);
+
+        // 2ms is the period of chip, 50ms of 3 frames
+        let report = run(&mut runner, &mut chip, 1, 50);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (25, 3));
+    }
+
+    #[test]
+    fn clock_wraps_around() {
+        let mut chip = chip();
+        let mut runner = runner(1_000_000, u32::MAX - 1_500_000);
+        runner.poll(&mut chip).unwrap();
+
+        let report = run(&mut runner, &mut chip, 30, 100_000);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (1500, 180));
+    }
+
+    #[test]
+    fn no_drift_at_odd_frequency() {
+        let mut chip = chip();
+        let mut runner = runner(32768, 0);
+        runner.poll(&mut chip).unwrap();
+
+        let report = run(&mut runner, &mut chip, 10 * 32768 / 7, 7);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (4999, 599));
+        let report = run(&mut runner, &mut chip, 1, 3);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (1, 1));
+    }
+
+    #[test]
+    fn catch_up_is_bounded() {
+        let mut chip = chip();
+        let mut runner = runner(1000, 0);
+        runner.poll(&mut chip).unwrap();
+
+        let report = run(&mut runner, &mut chip, 1, 1000);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (50, 6));
+        assert_eq!(report.dropped, Duration::from_millis(900));
+        assert!(report.is_behind());
+
+        let report = run(&mut runner, &mut chip, 1000, 1);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (500, 60));
+        assert!(!report.is_behind());
+        assert_eq!(runner.dropped(), Duration::from_millis(900));
+    }
+
+    #[test]
+    fn speed_multiplier() {
+        let mut chip = chip();
+        let mut runner = runner(1000, 0).with_speed(2, 1);
+        runner.poll(&mut chip).unwrap();
+        let report = run(&mut runner, &mut chip, 1000, 1);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (1000, 120));
+
+        runner.set_speed(1, 3);
+        let report = run(&mut runner, &mut chip, 3000, 1);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (500, 60));
+    }
+
+    #[test]
+    fn frequencies() {
+        let mut chip = chip();
+        let mut runner = runner(1000, 0).with_frequencies(1000, 50);
+        runner.poll(&mut chip).unwrap();
+        let report = run(&mut runner, &mut chip, 100, 10);
+        assert_eq!((report.chip_ticks, report.timers_ticks), (1000, 50));
+    }
+}
@@ -0,0 +1,276 @@
+//! Pixel-art upscalers
+//!
+//! `FrameView::iter_pixelwise_scaled` enlarges each pixel into a square, which
+//! makes diagonal lines look jagged. `Upscaler` implements edge-preserving
+//! algorithms, which fill corners of enlarged pixels depending on neighbours:
+//! - `Scale2x` (also known as EPX) and `Scale3x` by Andrea Mazzoleni,
+//! - `Eagle`, which rounds corners more eagerly.
+//!
+//! Pixels are computed on demand, so upscaling doesn't allocate. Pixels beyond
+//! edges of the frame are assumed to be equal to the nearest edge pixel.
+
+use crate::frame::{FrameView, HEIGHT, WIDTH};
+
+/// 3x3 neighbourhood of a pixel, indexed by row and column
+type Neighbours = [[bool; 3]; 3];
+
+/// Edge-preserving upscaling algorithm
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Upscaler {
+    /// Scale2x, equivalent to EPX, enlarges by factor of 2
+    Scale2x,
+    /// Scale3x, enlarges by factor of 3
+    Scale3x,
+    /// Eagle, enlarges by factor of 2
+    Eagle,
+}
+
+impl Upscaler {
+    /// Factor by which frames are enlarged
+    pub fn factor(self) -> usize {
+        match self {
+            Self::Scale2x | Self::Eagle => 2,
+            Self::Scale3x => 3,
+        }
+    }
+
+    /// Width of upscaled frame
+    pub fn width(self) -> usize {
+        WIDTH * self.factor()
+    }
+
+    /// Height of upscaled frame
+    pub fn height(self) -> usize {
+        HEIGHT * self.factor()
+    }
+
+    /// Number of bytes needed by `fill`
+    pub fn buffer_len(self) -> usize {
+        self.width() * self.height() / 8
+    }
+
+    /// Compute pixel of upscaled frame
+    pub fn get_pixel(self, frame: FrameView<'_>, x: usize, y: usize) -> Option<bool> {
+        if x >= self.width() || y >= self.height() {
+            return None;
+        }
+        let factor = self.factor();
+        let n = neighbours(frame, x / factor, y / factor);
+        let (col, row) = (x % factor, y % factor);
+        Some(match self {
+            Self::Scale2x => scale2x(&n, row, col),
+            Self::Scale3x => scale3x(&n, row, col),
+            Self::Eagle => eagle(&n, row, col),
+        })
+    }
+
+    /// Iter upscaled frame pixelwise (each pixel in row for each row in frame)
+    pub fn iter_pixelwise<'a>(
+        self,
+        frame: FrameView<'a>,
+    ) -> impl Iterator<Item = impl Iterator<Item = bool> + 'a> + 'a {
+        (0..self.height()).map(move |y| {
+            (0..self.width()).map(move |x| self.get_pixel(frame, x, y).unwrap_or(false))
+        })
+    }
+
+    /// Fill the buffer with upscaled frame, returns number of written bytes
+    ///
+    /// Layout follows `FrameView::as_raw`: rows from top to bottom, pixels
+    /// from left to right as bits from the most significant one. Buffer can be
+    /// used eg. to create `ImageRaw` of `width` x `height` pixels.
+    pub fn fill(self, frame: FrameView<'_>, buf: &mut [u8]) -> Result<usize, &'static str> {
+        let len = self.buffer_len();
+        if buf.len() < len {
+            return Err("Buffer too small for the image");
+        }
+        let stride = self.width() / 8;
+        buf[..len]
+            .chunks_mut(stride)
+            .enumerate()
+            .for_each(|(y, row)| {
+                row.iter_mut().enumerate().for_each(|(n, byte)| {
+                    *byte = (0..8)
+                        .filter(|bit| self.get_pixel(frame, n * 8 + bit, y) == Some(true))
+                        .fold(0, |acc, bit| acc | 0x80 >> bit);
+                })
+            });
+        Ok(len)
+    }
+}
+
+fn neighbours(frame: FrameView<'_>, x: usize, y: usize) -> Neighbours {
+    let mut n = [[false; 3]; 3];
+    for (row, dy) in n.iter_mut().zip(&[-1, 0, 1]) {
+        let y = (y as isize + dy).clamp(0, HEIGHT as isize - 1) as usize;
+        for (pixel, dx) in row.iter_mut().zip(&[-1, 0, 1]) {
+            let x = (x as isize + dx).clamp(0, WIDTH as isize - 1) as usize;
+            *pixel = frame.is_lit(x, y);
+        }
+    }
+    n
+}
+
+fn scale2x(n: &Neighbours, row: usize, col: usize) -> bool {
+    let (a, c, p, b, d) = (n[0][1], n[1][0], n[1][1], n[1][2], n[2][1]);
+    match (row, col) {
+        (0, 0) if c == a && c != d && a != b => a,
+        (0, 1) if a == b && a != c && b != d => b,
+        (1, 0) if d == c && d != b && c != a => c,
+        (1, 1) if b == d && b != a && d != c => d,
+        _ => p,
+    }
+}
+
+fn scale3x(n: &Neighbours, row: usize, col: usize) -> bool {
+    let [[a, b, c], [d, e, f], [g, h, i]] = *n;
+    let db = d == b && b != f && d != h;
+    let bf = b == f && b != d && f != h;
+    let dh = d == h && d != b && h != f;
+    let hf = h == f && d != h && b != f;
+    match (row, col) {
+        (0, 0) if db => d,
+        (0, 1) if (db && e != c) || (bf && e != a) => b,
+        (0, 2) if bf => f,
+        (1, 0) if (db && e != g) || (dh && e != a) => d,
+        (1, 2) if (bf && e != i) || (hf && e != c) => f,
+        (2, 0) if dh => d,
+        (2, 1) if (hf && e != g) || (dh && e != i) => h,
+        (2, 2) if hf => f,
+        _ => e,
+    }
+}
+
+fn eagle(n: &Neighbours, row: usize, col: usize) -> bool {
+    let [[s, t, u], [v, c, w], [x, y, z]] = *n;
+    match (row, col) {
+        (0, 0) if v == s && s == t => s,
+        (0, 1) if t == u && u == w => u,
+        (1, 0) if v == x && x == y => x,
+        (1, 1) if w == z && z == y => z,
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+    use std::vec;
+
+    use crate::frame::Frame;
+
+    /// Diagonal line, a square and a corner of a slope
+    const INPUT: [&str; 5] = ["#.......", ".#..##..", "..#.##..", "...#...#", "......##"];
+
+    fn frame() -> Frame {
+        let mut frame = Frame::new();
+        for (y, row) in INPUT.iter().enumerate() {
+            row.chars()
+                .enumerate()
+                .filter(|&(_, c)| c == '#')
+                .for_each(|(x, _)| frame.xor_bit(x, y, true).unwrap());
+        }
+        frame
+    }
+
+    /// Render upscaled area covering `INPUT`
+    fn render(upscaler: Upscaler) -> String {
+        let factor = upscaler.factor();
+        let mut text = String::new();
+        for row in upscaler.iter_pixelwise(frame().view()).take(5 * factor) {
+            text.extend(row.take(8 * factor).map(|p| if p { '#' } else { '.' }));
+            text.push('\n');
+        }
+        text
+    }
+
+    fn golden(image: &str) -> String {
+        image
+            .split_whitespace()
+            .flat_map(|row| row.chars().chain(Some('\n')))
+            .collect()
+    }
+
+    #[test]
+    fn scale2x() {
+        assert_eq!(
+            render(Upscaler::Scale2x),
+            golden(
+                "##..............
+                 #.#.............
+                 .###.....##.....
+                 ..###...####....
+                 ...###..####....
+                 ....##...##.....
+                 .....####.....##
+                 ......##.....###
+                 ............####
+                 ............###."
+            )
+        );
+    }
+
+    #[test]
+    fn scale3x() {
+        assert_eq!(
+            render(Upscaler::Scale3x),
+            golden(
+                "###.....................
+                 ##.#....................
+                 #..#....................
+                 .#####........##........
+                 ...###.......####.......
+                 ...####.....######......
+                 .....####...######......
+                 ......###....####.......
+                 ......###.....##........
+                 ........######.......###
+                 .........###.........###
+                 .........###........####
+                 ..................######
+                 ..................#####.
+                 ..................####.."
+            )
+        );
+    }
+
+    #[test]
+    fn eagle() {
+        assert_eq!(
+            render(Upscaler::Eagle),
+            golden(
+                "##..............
+                 ##..............
+                 ..#......##.....
+                 ...#....####....
+                 ....#...####....
+                 .....#..###.....
+                 ......##........
+                 .............###
+                 .............###
+                 .............##."
+            )
+        );
+    }
+
+    #[test]
+    fn fill() {
+        let frame = frame();
+        let upscaler = Upscaler::Scale2x;
+        let mut buf = vec![0; upscaler.buffer_len() + 1];
+        assert_eq!(upscaler.fill(frame.view(), &mut buf), Ok(128 * 64 / 8));
+        assert_eq!(&buf[..3], &[0b1100_0000, 0, 0]);
+        assert_eq!(&buf[16..18], &[0b1010_0000, 0]);
+        assert_eq!(&buf[2 * 16..2 * 16 + 2], &[0b0111_0000, 0b0110_0000]);
+        assert!(buf[10 * 16..].iter().all(|&b| b == 0));
+
+        assert_eq!(
+            Upscaler::Scale3x.fill(frame.view(), &mut buf),
+            Err("Buffer too small for the image")
+        );
+        assert_eq!(upscaler.get_pixel(frame.view(), 128, 0), None);
+    }
+}
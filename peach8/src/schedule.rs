@@ -0,0 +1,205 @@
+//! Exact interleaving of chip and timer ticks
+//!
+//! `Schedule` orders ticks of chip and timers at their frequencies, comparing
+//! their times as fractions, so that no drift accumulates. It is driven by
+//! `runner::Runner`, by `Peach8::run` with `async` feature, and in simulated
+//! time by `testing::VirtualClock` and `tas::Session`.
+
+use core::time::Duration;
+
+use crate::context::Context;
+use crate::peach::Peach8;
+
+/// Nanoseconds in a second
+pub(crate) const NANOS: u64 = 1_000_000_000;
+
+/// Tick of chip or of timers
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Tick {
+    Chip,
+    Timers,
+}
+
+/// Order of chip and timer ticks since a start
+///
+/// The n-th tick of chip happens at `n / chip_freq` seconds and the n-th tick
+/// of timers at `n / timers_freq` seconds, counting from 1. Ticks happening
+/// at the same time run chip first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Schedule {
+    chip_freq: u32,
+    timers_freq: u32,
+    chip_ticks: u64,
+    timers_ticks: u64,
+}
+
+impl Schedule {
+    /// Schedule of chip and timers with given frequencies in Hz
+    ///
+    /// Panics when any frequency is 0.
+    pub fn new(chip_freq: u32, timers_freq: u32) -> Self {
+        assert!(
+            chip_freq > 0 && timers_freq > 0,
+            "Frequency must be positive"
+        );
+        Self {
+            chip_freq,
+            timers_freq,
+            chip_ticks: 0,
+            timers_ticks: 0,
+        }
+    }
+
+    /// Number of chip ticks so far
+    pub fn chip_ticks(&self) -> u64 {
+        self.chip_ticks
+    }
+
+    /// Number of timer ticks so far
+    pub fn timers_ticks(&self) -> u64 {
+        self.timers_ticks
+    }
+
+    /// The next tick, without advancing the schedule
+    pub fn peek(&self) -> Tick {
+        let chip = (self.chip_ticks + 1) as u128 * self.timers_freq as u128;
+        let timers = (self.timers_ticks + 1) as u128 * self.chip_freq as u128;
+        if chip <= timers {
+            Tick::Chip
+        } else {
+            Tick::Timers
+        }
+    }
+
+    /// Advance the schedule past the next tick and return it
+    pub fn advance(&mut self) -> Tick {
+        let tick = self.peek();
+        match tick {
+            Tick::Chip => self.chip_ticks += 1,
+            Tick::Timers => self.timers_ticks += 1,
+        }
+        tick
+    }
+
+    /// Time of the next tick of chip or of timers, rounded down to nanoseconds
+    pub fn next(&self, tick: Tick) -> Duration {
+        let (ticks, freq) = self.counter(tick);
+        Duration::from_nanos(((ticks + 1) as u128 * NANOS as u128 / freq as u128) as u64)
+    }
+
+    /// Whether the next tick happens no later than `time`, as given by `next`
+    pub fn is_due(&self, time: Duration) -> bool {
+        self.next(self.peek()) <= time
+    }
+
+    /// Time of the last tick, rounded down
+    pub fn last_tick(&self) -> Duration {
+        let chip = self.chip_ticks as u128 * NANOS as u128 / self.chip_freq as u128;
+        let timers = self.timers_ticks as u128 * NANOS as u128 / self.timers_freq as u128;
+        Duration::from_nanos(chip.max(timers) as u64)
+    }
+
+    /// Skip chip ticks due no later than `time`, eg. while it sleeps
+    pub fn skip_chip(&mut self, time: Duration) {
+        // the last tick whose time rounded down isn't later
+        let due = ((time.as_nanos() + 1) * self.chip_freq as u128 - 1) / NANOS as u128;
+        self.chip_ticks = self.chip_ticks.max(due as u64);
+    }
+
+    /// Advance the schedule and run the next tick on `chip`
+    ///
+    /// Returns the tick, or the error of `Peach8::tick_chip`.
+    pub fn step<C: Context>(&mut self, chip: &mut Peach8<C>) -> Result<Tick, &'static str> {
+        let tick = self.advance();
+        match tick {
+            Tick::Chip => chip.tick_chip()?,
+            Tick::Timers => chip.tick_timers(),
+        }
+        Ok(tick)
+    }
+
+    /// Run ticks up to and including the next tick of timers, ie. a frame
+    ///
+    /// Stops at the first error of `Peach8::tick_chip`.
+    pub fn run_frame<C: Context>(&mut self, chip: &mut Peach8<C>) -> Result<(), &'static str> {
+        while self.step(chip)? == Tick::Chip {}
+        Ok(())
+    }
+
+    /// Count ticks from the next second, once ticks of both reached it
+    ///
+    /// Returns the number of seconds dropped from the start.
+    pub(crate) fn rebase(&mut self) -> u64 {
+        let mut seconds = 0;
+        while self.chip_ticks >= self.chip_freq as u64
+            && self.timers_ticks >= self.timers_freq as u64
+        {
+            self.chip_ticks -= self.chip_freq as u64;
+            self.timers_ticks -= self.timers_freq as u64;
+            seconds += 1;
+        }
+        seconds
+    }
+
+    fn counter(&self, tick: Tick) -> (u64, u32) {
+        match tick {
+            Tick::Chip => (self.chip_ticks, self.chip_freq),
+            Tick::Timers => (self.timers_ticks, self.timers_freq),
+        }
+    }
+}
+
+impl Default for Schedule {
+    /// Chip at 500Hz and timers at 60Hz
+    fn default() -> Self {
+        Self::new(500, 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip_goes_first_on_ties() {
+        let mut schedule = Schedule::new(120, 60);
+        let ticks = [Tick::Chip, Tick::Chip, Tick::Timers, Tick::Chip];
+        for &tick in ticks.iter() {
+            assert_eq!(schedule.advance(), tick);
+        }
+    }
+
+    #[test]
+    fn times_of_ticks() {
+        let mut schedule = Schedule::default();
+        assert_eq!(schedule.next(Tick::Chip), Duration::from_millis(2));
+        assert_eq!(schedule.next(Tick::Timers), Duration::new(0, 16_666_666));
+        assert!(!schedule.is_due(Duration::new(0, 1_999_999)));
+        assert!(schedule.is_due(Duration::from_millis(2)));
+
+        for _ in 0..8 {
+            schedule.advance();
+        }
+        // due at the deadline of `next`, rounded down
+        assert_eq!(schedule.peek(), Tick::Timers);
+        assert!(schedule.is_due(Duration::new(0, 16_666_666)));
+        schedule.advance();
+        assert_eq!((schedule.chip_ticks(), schedule.timers_ticks()), (8, 1));
+        assert_eq!(schedule.last_tick(), Duration::new(0, 16_666_666));
+
+        // 1s of sleep skips 500 ticks of chip, but no timers
+        schedule.skip_chip(Duration::from_secs(1));
+        assert_eq!((schedule.chip_ticks(), schedule.timers_ticks()), (500, 1));
+        assert_eq!(schedule.peek(), Tick::Timers);
+    }
+
+    #[test]
+    fn rebase_keeps_order() {
+        let mut schedule = Schedule::new(7, 3);
+        let mut rebased = schedule;
+        for n in 0..30 {
+            assert_eq!(rebased.advance(), schedule.advance(), "tick {}", n);
+            rebased.rebase();
+        }
+    }
+}
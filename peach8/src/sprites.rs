@@ -0,0 +1,304 @@
+//! Sprite gallery
+//!
+//! `SpriteCatalogue` wraps a `Context` and captures sprite data drawn by `DXYN`,
+//! deduplicated by address and contents, with the number of draws of each
+//! sprite. Captured sprites can be rendered into a `Frame`, or listed in
+//! [Octo](https://github.com/JohnEarnest/Octo) syntax, eg. to pull graphical
+//! assets out of games.
+//!
+//! Pixels of a sprite follow conventions of `FrameView`: each row is a byte,
+//! pixels from left to right are bits from the most significant one.
+
+use core::convert::TryFrom;
+use core::fmt;
+
+use bitvec::prelude::*;
+#[cfg(feature = "embedded-graphics")]
+use embedded_graphics::{image::ImageRaw, pixelcolor::BinaryColor};
+use heapless::{consts::U128, Vec};
+
+use crate::context::Context;
+use crate::frame::{Frame, FrameView, HEIGHT, WIDTH};
+use crate::opcode::OpCode;
+use crate::peach::State;
+use crate::storage::Storage;
+
+/// Maximal height of a sprite
+pub const MAX_HEIGHT: usize = 15;
+
+/// Width of a cell holding a sprite in rendered pages, including 1px gap
+const CELL_WIDTH: usize = 9;
+/// Height of a cell holding a sprite in rendered pages, including 1px gap
+const CELL_HEIGHT: usize = MAX_HEIGHT + 1;
+
+/// Number of sprites rendered in one page by `SpriteCatalogue::render`
+pub const PER_PAGE: usize = (WIDTH / CELL_WIDTH) * (HEIGHT / CELL_HEIGHT);
+
+/// Sprite captured from memory
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Sprite {
+    /// Value of I at the time of drawing
+    pub addr: u16,
+    /// Number of times the sprite was drawn
+    pub draws: u32,
+    len: u8,
+    data: [u8; MAX_HEIGHT],
+}
+
+impl Sprite {
+    /// Rows of the sprite
+    pub fn as_raw(&self) -> &[u8] {
+        &self.data[..self.len as usize]
+    }
+
+    /// Height of the sprite
+    pub fn height(&self) -> usize {
+        self.len as usize
+    }
+
+    /// Get iterator over rows in a form of a `BitSlice`s
+    pub fn iter_rows_as_bitslices(&self) -> impl Iterator<Item = &BitSlice<Msb0, u8>> {
+        self.as_raw().iter().map(|row| row.view_bits::<_>())
+    }
+
+    /// Get `ImageRaw` structure from sprite's data
+    #[cfg(feature = "embedded-graphics")]
+    pub fn as_raw_image(&self) -> ImageRaw<'_, BinaryColor> {
+        ImageRaw::new(self.as_raw(), 8, self.len as u32)
+    }
+}
+
+/// `Context` adapter capturing drawn sprites
+///
+/// Holds up to 128 distinct sprites, further ones are counted by `dropped`.
+pub struct SpriteCatalogue<C> {
+    pub inner: C,
+    sprites: Vec<Sprite, U128>,
+    dropped: u32,
+}
+
+impl<C: Context> SpriteCatalogue<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            sprites: Vec::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Drop the catalogue, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    /// Captured sprites, in order of the first draw
+    pub fn sprites(&self) -> &[Sprite] {
+        &self.sprites
+    }
+
+    /// Number of draws of sprites not captured, because catalogue was full
+    pub fn dropped(&self) -> u32 {
+        self.dropped
+    }
+
+    /// Number of pages needed to render all of captured sprites
+    pub fn pages(&self) -> usize {
+        self.sprites.len().div_ceil(PER_PAGE)
+    }
+
+    /// Render a page of sprites into a frame, `PER_PAGE` sprites in a grid
+    /// of cells separated by 1px gaps
+    pub fn render(&self, page: usize) -> Frame {
+        let mut frame = Frame::new();
+        let columns = WIDTH / CELL_WIDTH;
+        self.sprites
+            .iter()
+            .skip(page * PER_PAGE)
+            .take(PER_PAGE)
+            .enumerate()
+            .for_each(|(n, sprite)| {
+                let x = n % columns * CELL_WIDTH;
+                let y = n / columns * CELL_HEIGHT;
+                sprite
+                    .iter_rows_as_bitslices()
+                    .enumerate()
+                    .for_each(|(row, bits)| {
+                        bits.iter().enumerate().for_each(|(col, &bit)| {
+                            // cells lay within the frame
+                            let _ = frame.xor_bit(x + col, y + row, bit);
+                        })
+                    });
+            });
+        frame
+    }
+
+    /// Write listing of captured sprites in Octo syntax
+    ///
+    /// Each sprite is labelled with its address, sprites drawn from the same
+    /// address with different contents are suffixed with consecutive numbers.
+    pub fn write_octo<W: fmt::Write>(&self, w: &mut W) -> fmt::Result {
+        for (n, sprite) in self.sprites.iter().enumerate() {
+            let variant = self.sprites[..n]
+                .iter()
+                .filter(|s| s.addr == sprite.addr)
+                .count();
+            write!(w, ": sprite-{:03X}", sprite.addr)?;
+            if variant > 0 {
+                write!(w, "-{}", variant)?;
+            }
+            writeln!(w, " # drawn {} times", sprite.draws)?;
+            for row in sprite.as_raw() {
+                writeln!(w, "\t0b{:08b}", row)?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+
+    fn capture(&mut self, addr: u16, data: &[u8]) {
+        if let Some(sprite) = self
+            .sprites
+            .iter_mut()
+            .find(|s| s.addr == addr && s.as_raw() == data)
+        {
+            sprite.draws += 1;
+            return;
+        }
+        let mut sprite = Sprite {
+            addr,
+            draws: 1,
+            len: data.len() as u8,
+            data: [0; MAX_HEIGHT],
+        };
+        sprite.data[..data.len()].copy_from_slice(data);
+        if self.sprites.push(sprite).is_err() {
+            self.dropped += 1;
+        }
+    }
+}
+
+impl<C: Context> Context for SpriteCatalogue<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        if let Ok(OpCode::_DXYN { n, .. }) = OpCode::try_from(opcode) {
+            let start = state.i as usize;
+            if let Some(data) = state.memory.get(start..start + n as usize) {
+                if !data.is_empty() {
+                    self.capture(state.i, data);
+                }
+            }
+        }
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::string::String;
+
+    use crate::assert_eq_2d;
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+    use crate::testing::ToMask;
+
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0xA2, 0x10, // 0x200 LD I, 0x210
+        0xD0, 0x02, // 0x202 DRW V0, V0, 2
+        0xD0, 0x02, // 0x204 DRW V0, V0, 2
+        0xD0, 0x03, // 0x206 DRW V0, V0, 3
+        0xF0, 0x29, // 0x208 LD F, V0
+        0xD0, 0x05, // 0x20A DRW V0, V0, 5
+        0x12, 0x0C, // 0x20C JP 0x20C
+        0x00, 0x00, // 0x20E
+        0x81, 0x42, // 0x210 sprite data
+        0x3C,       // 0x212
+    ];
+
+    #[test]
+    fn catalogue() {
+        let mut chip = Builder::new()
+            .with_context(SpriteCatalogue::new(TestingContext::new(0)))
+            .with_program(PROGRAM)
+            .build()
+            .unwrap();
+        for _ in 0..7 {
+            chip.tick_chip().unwrap();
+        }
+        let catalogue = &chip.ctx;
+        let sprites = catalogue.sprites();
+
+        assert_eq!(sprites.len(), 3);
+        assert_eq!((sprites[0].addr, sprites[0].draws), (0x210, 2));
+        assert_eq!(sprites[0].as_raw(), &[0x81, 0x42]);
+        assert_eq!((sprites[1].addr, sprites[1].height()), (0x210, 3));
+        assert_eq!(sprites[2].as_raw(), &[0xF0, 0x90, 0x90, 0x90, 0xF0]);
+        assert_eq!(catalogue.dropped(), 0);
+        assert_eq!(catalogue.pages(), 1);
+
+        let mut listing = String::new();
+        catalogue.write_octo(&mut listing).unwrap();
+        assert!(listing.starts_with(
+            ": sprite-210 # drawn 2 times\n\t0b10000001\n\t0b01000010\n\n\
+             : sprite-210-1 # drawn 1 times\n"
+        ));
+        assert!(listing.ends_with(
+            ": sprite-050 # drawn 1 times\n\t0b11110000\n\t0b10010000\n\
+             \t0b10010000\n\t0b10010000\n\t0b11110000\n\n"
+        ));
+    }
+
+    #[rustfmt::skip]
+    #[test]
+    fn render() {
+        let mut catalogue = SpriteCatalogue::new(TestingContext::new(0));
+        catalogue.capture(0x210, &[0x81, 0x42]);
+        catalogue.capture(0x050, &[0xF0, 0x90, 0xF0]);
+        for n in 0..PER_PAGE as u16 {
+            catalogue.capture(0x300 + n, &[0xFF]);
+        }
+        assert_eq!(catalogue.pages(), 2);
+
+        assert_eq_2d!(
+            x_range: 0..18, y_range: 0..4;
+            catalogue.render(0).view().to_mask(), "#......#.####.....
+                                                   .#....#..#..#.....
+                                                   .........####.....
+                                                   ..................".to_mask()
+        );
+        assert_eq_2d!(
+            x_range: .., y_range: ..;
+            catalogue.render(1).view().to_mask(), "########.########".to_mask()
+        );
+    }
+}
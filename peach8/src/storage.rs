@@ -0,0 +1,579 @@
+//! Persistent storage of SCHIP RPL user flags
+//!
+//! On HP48 calculators `FX75` saves V0 to VX into RPL user flags, and `FX85`
+//! reads them back, even after the program is restarted. Some games keep
+//! high scores there. `Peach8` executes these opcodes when `Context::storage`
+//! returns a `Storage`, which keeps a block of flags for each program, keyed
+//! by `RomHash` of the program. Without it they fail.
+//!
+//! Flags can be kept in RAM by `MemoryStorage`, in files by `FileStorage` with
+//! `std` feature, or in two pages of flash memory by `FlashStorage` over an
+//! implementation of `Flash`. `WithStorage` adds any of them to a `Context`.
+
+use core::fmt;
+
+use heapless::{consts::U16, LinearMap};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+
+use crate::context::Context;
+use crate::frame::FrameView;
+use crate::peach::State;
+
+/// Number of RPL user flags
+pub const FLAGS: usize = 8;
+
+/// Block of RPL user flags of a program
+pub type Flags = [u8; FLAGS];
+
+/// FNV-1a hash identifying a program
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RomHash(pub u64);
+
+impl RomHash {
+    pub fn of(rom: &[u8]) -> Self {
+        Self(rom.iter().fold(0xCBF2_9CE4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01B3)
+        }))
+    }
+}
+
+impl fmt::Display for RomHash {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
+
+/// Storage of flags surviving restarts of programs
+pub trait Storage {
+    /// Read flags saved for a program into `flags`, returns whether any were
+    /// saved. `flags` are left untouched otherwise
+    fn load(&mut self, rom: RomHash, flags: &mut Flags) -> Result<bool, &'static str>;
+    /// Save flags of a program, replacing previous ones
+    fn save(&mut self, rom: RomHash, flags: &Flags) -> Result<(), &'static str>;
+}
+
+/// `Storage` in RAM, holding flags of up to 16 programs
+#[derive(Default)]
+pub struct MemoryStorage {
+    flags: LinearMap<RomHash, Flags, U16>,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self {
+            flags: LinearMap::new(),
+        }
+    }
+}
+
+impl Storage for MemoryStorage {
+    fn load(&mut self, rom: RomHash, flags: &mut Flags) -> Result<bool, &'static str> {
+        match self.flags.get(&rom) {
+            Some(saved) => {
+                *flags = *saved;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn save(&mut self, rom: RomHash, flags: &Flags) -> Result<(), &'static str> {
+        self.flags
+            .insert(rom, *flags)
+            .map(|_| ())
+            .or(Err("Storage is full"))
+    }
+}
+
+/// `Storage` keeping flags of each program in a file named by its hash
+#[cfg(feature = "std")]
+pub struct FileStorage {
+    dir: std::path::PathBuf,
+}
+
+#[cfg(feature = "std")]
+impl FileStorage {
+    /// Keep files in `dir`, which is created on first save
+    pub fn new(dir: impl Into<std::path::PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    fn path(&self, rom: RomHash) -> std::path::PathBuf {
+        self.dir.join(std::format!("{}.rpl", rom))
+    }
+}
+
+#[cfg(feature = "std")]
+impl Storage for FileStorage {
+    fn load(&mut self, rom: RomHash, flags: &mut Flags) -> Result<bool, &'static str> {
+        let path = self.path(rom);
+        match std::fs::read(&path) {
+            Ok(bytes) if bytes.len() == FLAGS => {
+                flags.copy_from_slice(&bytes);
+                Ok(true)
+            }
+            Ok(_) => Err("Corrupted file of flags"),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => {
+                warn!("Reading {}: {}", path.display(), e);
+                Err("Failed to read flags")
+            }
+        }
+    }
+
+    fn save(&mut self, rom: RomHash, flags: &Flags) -> Result<(), &'static str> {
+        let path = self.path(rom);
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&path, flags))
+            .map_err(|e| {
+                warn!("Writing {}: {}", path.display(), e);
+                "Failed to write flags"
+            })
+    }
+}
+
+/// Page of flash memory, eg. one of the last pages of STM32 flash
+///
+/// Erased bytes read as `0xFF`. Each byte is written at most once between
+/// erases, in chunks of 8 bytes aligned to 8.
+pub trait Flash {
+    /// Size of the page in bytes, a multiple of 16
+    fn capacity(&self) -> usize;
+    fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), &'static str>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), &'static str>;
+    /// Erase the whole page
+    fn erase(&mut self) -> Result<(), &'static str>;
+}
+
+/// Size of a record of `FlashStorage`, hash followed by flags
+const RECORD: usize = 16;
+
+/// `Storage` appending records of flags to one of two pages of `Flash`
+///
+/// A page in use starts with a header holding its sequence number, followed
+/// by records of 16 bytes. Each save writes a record after the previous ones.
+/// Flags are written before the hash, so a record torn by losing power is
+/// skipped.
+///
+/// When the page is full, the latest flags of up to 16 programs are written
+/// to the other page, and its header last. Only then the full page is marked
+/// obsolete and erased. If power is lost or a write fails before, the full
+/// page stays in use, so no flags are lost.
+pub struct FlashStorage<F: Flash> {
+    pub pages: [F; 2],
+}
+
+impl<F: Flash> FlashStorage<F> {
+    /// Storage over two pages of equal capacity
+    pub fn new(pages: [F; 2]) -> Self {
+        Self { pages }
+    }
+
+    /// Drop the storage, releasing flash
+    pub fn release(self) -> [F; 2] {
+        self.pages
+    }
+
+    /// Page in use and its sequence number, the highest one of pages with a
+    /// header which aren't obsolete
+    fn active(&mut self) -> Result<Option<(usize, u64)>, &'static str> {
+        let mut active = None;
+        for (page, flash) in self.pages.iter_mut().enumerate() {
+            let mut header = [0; RECORD];
+            flash.read(0, &mut header)?;
+            let (sequence, obsolete) = words(&header);
+            // both words are erased until written
+            if sequence != u64::MAX
+                && obsolete == u64::MAX
+                && active.is_none_or(|(_, latest)| sequence > latest)
+            {
+                active = Some((page, sequence));
+            }
+        }
+        Ok(active)
+    }
+
+    /// Visit valid records of a page in order of writing, returns offset of free space
+    fn scan(
+        &mut self,
+        page: usize,
+        mut visit: impl FnMut(RomHash, Flags) -> Result<(), &'static str>,
+    ) -> Result<usize, &'static str> {
+        let flash = &mut self.pages[page];
+        let mut offset = RECORD;
+        while offset + RECORD <= flash.capacity() {
+            let mut raw = [0; RECORD];
+            flash.read(offset, &mut raw)?;
+            if raw.iter().all(|&b| b == 0xFF) {
+                break;
+            }
+            let mut flags = [0; FLAGS];
+            flags.copy_from_slice(&raw[8..]);
+            // hash is written last, erased one marks a torn record
+            match words(&raw).0 {
+                u64::MAX => (),
+                hash => visit(RomHash(hash), flags)?,
+            }
+            offset += RECORD;
+        }
+        Ok(offset)
+    }
+
+    fn append(
+        &mut self,
+        page: usize,
+        offset: usize,
+        rom: RomHash,
+        flags: &Flags,
+    ) -> Result<(), &'static str> {
+        self.pages[page].write(offset + 8, flags)?;
+        self.pages[page].write(offset, &rom.0.to_le_bytes())
+    }
+
+    /// Write latest flags of all programs from a full page to the other one
+    fn compact(
+        &mut self,
+        page: usize,
+        sequence: u64,
+        rom: RomHash,
+        flags: &Flags,
+    ) -> Result<(), &'static str> {
+        let mut all = LinearMap::<RomHash, Flags, U16>::new();
+        self.scan(page, |r, f| {
+            all.insert(r, f)
+                .map(|_| ())
+                .or(Err("Too many programs in flash storage"))
+        })?;
+        all.insert(rom, *flags)
+            .or(Err("Too many programs in flash storage"))?;
+        let target = 1 - page;
+        if (all.len() + 1) * RECORD > self.pages[target].capacity() {
+            return Err("Too many programs in flash storage");
+        }
+
+        debug!("Compacting flash storage of {} programs", all.len());
+        self.pages[target].erase()?;
+        for (n, (&rom, flags)) in all.iter().enumerate() {
+            self.append(target, (n + 1) * RECORD, rom, flags)?;
+        }
+        self.pages[target].write(0, &(sequence + 1).to_le_bytes())?;
+        self.pages[page].write(8, &0u64.to_le_bytes())?;
+        self.pages[page].erase()
+    }
+}
+
+/// Little endian words of a record
+fn words(raw: &[u8; RECORD]) -> (u64, u64) {
+    let (mut first, mut second) = ([0; 8], [0; 8]);
+    first.copy_from_slice(&raw[..8]);
+    second.copy_from_slice(&raw[8..]);
+    (u64::from_le_bytes(first), u64::from_le_bytes(second))
+}
+
+impl<F: Flash> Storage for FlashStorage<F> {
+    fn load(&mut self, rom: RomHash, flags: &mut Flags) -> Result<bool, &'static str> {
+        let page = match self.active()? {
+            Some((page, _)) => page,
+            None => return Ok(false),
+        };
+        let mut found = None;
+        self.scan(page, |r, f| {
+            if r == rom {
+                found = Some(f);
+            }
+            Ok(())
+        })?;
+        if let Some(saved) = found {
+            *flags = saved;
+        }
+        Ok(found.is_some())
+    }
+
+    fn save(&mut self, rom: RomHash, flags: &Flags) -> Result<(), &'static str> {
+        let (page, sequence) = match self.active()? {
+            Some(active) => active,
+            None => {
+                self.pages[0].erase()?;
+                self.pages[0].write(0, &0u64.to_le_bytes())?;
+                (0, 0)
+            }
+        };
+        let mut latest = None;
+        let end = self.scan(page, |r, f| {
+            if r == rom {
+                latest = Some(f);
+            }
+            Ok(())
+        })?;
+        if latest.as_ref() == Some(flags) {
+            return Ok(());
+        }
+        if end + RECORD <= self.pages[page].capacity() {
+            return self.append(page, end, rom, flags);
+        }
+        self.compact(page, sequence, rom, flags)
+    }
+}
+
+/// `Context` adapter providing `Storage` for SCHIP flags
+pub struct WithStorage<C, S> {
+    pub inner: C,
+    pub storage: S,
+}
+
+impl<C: Context, S: Storage> WithStorage<C, S> {
+    pub fn new(inner: C, storage: S) -> Self {
+        Self { inner, storage }
+    }
+
+    /// Drop the adapter, releasing wrapped context and storage
+    pub fn release(self) -> (C, S) {
+        (self.inner, self.storage)
+    }
+}
+
+impl<C: Context, S: Storage> Context for WithStorage<C, S> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        Some(&mut self.storage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+    use std::{format, vec};
+
+    /// `Flash` in RAM, rejecting writes over programmed bytes
+    struct RamFlash {
+        bytes: Vec<u8>,
+        erases: u32,
+        /// Number of writes succeeding before all fail
+        writes_left: Option<usize>,
+    }
+
+    impl RamFlash {
+        fn new(capacity: usize) -> Self {
+            Self {
+                bytes: vec![0xFF; capacity],
+                erases: 0,
+                writes_left: None,
+            }
+        }
+    }
+
+    impl Flash for RamFlash {
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+
+        fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), &'static str> {
+            buf.copy_from_slice(&self.bytes[offset..offset + buf.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), &'static str> {
+            assert_eq!((offset % 8, data.len() % 8), (0, 0), "unaligned write");
+            match &mut self.writes_left {
+                Some(0) => return Err("Flash write failed"),
+                Some(n) => *n -= 1,
+                None => (),
+            }
+            let target = &mut self.bytes[offset..offset + data.len()];
+            if target.iter().any(|&b| b != 0xFF) {
+                return Err("Writing over programmed flash");
+            }
+            target.copy_from_slice(data);
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), &'static str> {
+            self.bytes.iter_mut().for_each(|b| *b = 0xFF);
+            self.erases += 1;
+            Ok(())
+        }
+    }
+
+    /// Save and load flags of two programs
+    fn check(storage: &mut impl Storage) {
+        let (a, b) = (RomHash::of(b"a"), RomHash::of(b"b"));
+        let mut flags = [7; FLAGS];
+        assert_eq!(storage.load(a, &mut flags), Ok(false));
+        assert_eq!(flags, [7; FLAGS]);
+
+        storage.save(a, &[1; FLAGS]).unwrap();
+        storage.save(b, &[2; FLAGS]).unwrap();
+        storage.save(a, &[3; FLAGS]).unwrap();
+        assert_eq!(storage.load(a, &mut flags), Ok(true));
+        assert_eq!(flags, [3; FLAGS]);
+        assert_eq!(storage.load(b, &mut flags), Ok(true));
+        assert_eq!(flags, [2; FLAGS]);
+    }
+
+    #[test]
+    fn rom_hash() {
+        assert_eq!(RomHash::of(&[]), RomHash(0xCBF2_9CE4_8422_2325));
+        assert_eq!(RomHash::of(b"a"), RomHash(0xAF63_DC4C_8601_EC8C));
+        assert_eq!(format!("{}", RomHash(0xAB)), "00000000000000ab");
+    }
+
+    #[test]
+    fn memory_storage() {
+        let mut storage = MemoryStorage::new();
+        check(&mut storage);
+        for n in 2..16 {
+            storage.save(RomHash(n), &[0; FLAGS]).unwrap();
+        }
+        assert_eq!(
+            storage.save(RomHash(16), &[0; FLAGS]),
+            Err("Storage is full")
+        );
+        storage.save(RomHash(2), &[1; FLAGS]).unwrap();
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_storage() {
+        let dir = std::env::temp_dir().join(format!("peach8-storage-{}", std::process::id()));
+        let mut storage = FileStorage::new(&dir);
+        check(&mut storage);
+
+        // flags survive restarts
+        let mut flags = [0; FLAGS];
+        let mut storage = FileStorage::new(&dir);
+        assert_eq!(storage.load(RomHash::of(b"a"), &mut flags), Ok(true));
+        assert_eq!(flags, [3; FLAGS]);
+
+        std::fs::write(dir.join("0000000000000001.rpl"), [0; 3]).unwrap();
+        assert_eq!(
+            storage.load(RomHash(1), &mut flags),
+            Err("Corrupted file of flags")
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Storage over two pages, each holding a header and `records`
+    fn flash_storage(records: usize) -> FlashStorage<RamFlash> {
+        let capacity = (records + 1) * RECORD;
+        FlashStorage::new([RamFlash::new(capacity), RamFlash::new(capacity)])
+    }
+
+    #[test]
+    fn flash_storage_saves_records() {
+        let mut storage = flash_storage(63);
+        check(&mut storage);
+        // erased once before the first save
+        assert_eq!((storage.pages[0].erases, storage.pages[1].erases), (1, 0));
+
+        // unchanged flags aren't written again
+        storage.save(RomHash::of(b"a"), &[3; FLAGS]).unwrap();
+        let mut records = 0;
+        storage
+            .scan(0, |_, _| {
+                records += 1;
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(records, 3);
+    }
+
+    #[test]
+    fn flash_storage_compacts_full_page() {
+        let mut storage = flash_storage(3);
+        let (a, b) = (RomHash::of(b"a"), RomHash::of(b"b"));
+        storage.save(b, &[9; FLAGS]).unwrap();
+        for n in 0..10 {
+            storage.save(a, &[n; FLAGS]).unwrap();
+        }
+        assert!(storage.pages[1].erases > 0);
+        assert!(storage.pages[0].erases > 1);
+
+        let mut flags = [0; FLAGS];
+        assert_eq!(storage.load(a, &mut flags), Ok(true));
+        assert_eq!(flags, [9; FLAGS]);
+        assert_eq!(storage.load(b, &mut flags), Ok(true));
+        assert_eq!(flags, [9; FLAGS]);
+    }
+
+    #[test]
+    fn flash_storage_skips_torn_record() {
+        let mut storage = flash_storage(63);
+        let a = RomHash::of(b"a");
+        storage.save(a, &[1; FLAGS]).unwrap();
+        // power lost after writing flags of the second record
+        storage.pages[0].write(2 * RECORD + 8, &[2; FLAGS]).unwrap();
+        storage.save(a, &[3; FLAGS]).unwrap();
+
+        let mut flags = [0; FLAGS];
+        assert_eq!(storage.load(a, &mut flags), Ok(true));
+        assert_eq!(flags, [3; FLAGS]);
+    }
+
+    #[test]
+    fn flash_storage_keeps_flags_when_compaction_fails() {
+        let (a, b, c) = (RomHash::of(b"a"), RomHash::of(b"b"), RomHash::of(b"c"));
+        // fail each of 7 writes to the other page, then the mark of the full one
+        for writes in 0..8 {
+            let mut storage = flash_storage(3);
+            storage.save(a, &[1; FLAGS]).unwrap();
+            storage.save(b, &[2; FLAGS]).unwrap();
+            storage.save(c, &[3; FLAGS]).unwrap();
+
+            if writes < 7 {
+                storage.pages[1].writes_left = Some(writes);
+            } else {
+                storage.pages[0].writes_left = Some(0);
+            }
+            let saved = storage.save(a, &[4; FLAGS]);
+            assert_eq!(saved, Err("Flash write failed"), "{} writes", writes);
+
+            let mut flags = [0; FLAGS];
+            assert_eq!(storage.load(a, &mut flags), Ok(true));
+            // new flags are kept once the header of the other page is written
+            let expected = if writes < 7 { 1 } else { 4 };
+            assert_eq!(flags, [expected; FLAGS], "{} writes", writes);
+            assert_eq!(storage.load(b, &mut flags), Ok(true));
+            assert_eq!(flags, [2; FLAGS]);
+            assert_eq!(storage.load(c, &mut flags), Ok(true));
+            assert_eq!(flags, [3; FLAGS]);
+
+            // saving again completes compaction
+            storage.pages[0].writes_left = None;
+            storage.pages[1].writes_left = None;
+            storage.save(a, &[5; FLAGS]).unwrap();
+            assert_eq!(storage.load(a, &mut flags), Ok(true));
+            assert_eq!(flags, [5; FLAGS]);
+            assert_eq!(storage.load(c, &mut flags), Ok(true));
+            assert_eq!(flags, [3; FLAGS]);
+        }
+    }
+}
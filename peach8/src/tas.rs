@@ -0,0 +1,605 @@
+//! Tool-assisted play
+//!
+//! `Session` plays a program frame by frame, with keys of each frame taken
+//! from an editable list of inputs, like a movie editor of tool-assisted
+//! speedruns. Emulation is paused between calls of `Session::advance`, which
+//! plays exactly one frame of `CHIP_FREQ` instructions per `TIMERS_FREQ` ticks
+//! of timers, interleaved by `schedule::Schedule`. Unlike `runner::Runner`, a
+//! session runs in emulated time only, so it never drops time nor catches up.
+//!
+//! Frames in which the program executed none of `EX9E`, `EXA1` or `FX0A` are
+//! lag frames: keys held during them had no effect.
+//!
+//! `SavePoint`s capture the whole emulation, with RPL user flags of the
+//! program in storage of the context, so that a run can branch from them
+//! with different inputs. Random values are drawn from a list, extended
+//! from a seeded `rng::Rng` when exhausted, so that the n-th random value is
+//! the same in every branch.
+//!
+//! Inputs convert from and to movies of `movie` sampled per frame, which are
+//! replayed with `movie::Replay`.
+//!
+//! ```ignore
+//! let mut session = Session::new(ctx, rom, seed)?;
+//! session.run(60)?;
+//! let start = session.save()?;
+//! session.set_input(session.frame(), 1 << 0x5);
+//! let lag = session.advance()?;
+//! session.load(&start)?;
+//! ```
+
+use std::vec::Vec;
+
+use crate::builder::Builder;
+use crate::context::Context;
+use crate::frame::{Frame, FrameView};
+use crate::movie::{self, Header, Recorder, Sampling, TAG_KEYS, TAG_RANDOM, TAG_VBLANK};
+use crate::peach::{Peach8, Snapshot, State};
+use crate::rng::Rng;
+use crate::schedule::Schedule;
+use crate::storage::{Flags, MemoryStorage, RomHash, Storage, WithStorage};
+use crate::trace::IoSink;
+
+/// Frequency of chip in Hz
+pub const CHIP_FREQ: u32 = 500;
+/// Frequency of timers in Hz, ie. frames per second
+pub const TIMERS_FREQ: u32 = 60;
+
+/// Whether the instruction reads keys
+pub fn polls_input(opcode: u16) -> bool {
+    matches!(opcode & 0xF0FF, 0xE09E | 0xE0A1 | 0xF00A)
+}
+
+/// `Context` adapter feeding keys and random values of a `Session`
+///
+/// Input of the wrapped context is ignored, other calls are forwarded.
+pub struct Controller<C> {
+    pub inner: C,
+    keys: u16,
+    randoms: Vec<u8>,
+    /// Number of random values drawn so far
+    drawn: usize,
+    /// Generator extending `randoms`
+    rng: Rng,
+    polled: bool,
+    screen: Frame,
+}
+
+impl<C: Context> Controller<C> {
+    fn new(inner: C, randoms: Vec<u8>, seed: u64) -> Self {
+        Self {
+            inner,
+            keys: 0,
+            randoms,
+            drawn: 0,
+            rng: Rng::new(seed),
+            polled: false,
+            screen: Frame::new(),
+        }
+    }
+
+    /// Controller drawing the same random values from the start
+    fn fork<D: Context>(&self, inner: D) -> Controller<D> {
+        Controller {
+            rng: self.rng,
+            ..Controller::new(inner, self.randoms.clone(), 0)
+        }
+    }
+
+    /// Keys pressed in the current frame, key 0 in the lowest bit
+    pub fn keys(&self) -> u16 {
+        self.keys
+    }
+
+    /// Drop the adapter, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+}
+
+impl<C: Context> Context for Controller<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.screen.as_raw_mut().copy_from_slice(frame.as_raw());
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        movie::to_keys(self.keys)
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        if self.drawn == self.randoms.len() {
+            let value = self.rng.next_u8();
+            self.randoms.push(value);
+        }
+        self.drawn += 1;
+        self.randoms[self.drawn - 1]
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.polled |= polls_input(opcode);
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+/// Context without output, eg. of a `Session` which provides all input
+pub struct Silent;
+
+impl Context for Silent {
+    fn on_frame(&mut self, _frame: FrameView<'_>) {}
+
+    fn sound_on(&mut self) {}
+
+    fn sound_off(&mut self) {}
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        [false; 16]
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        0
+    }
+}
+
+/// Whole state of a `Session` at some frame
+#[derive(Clone)]
+pub struct SavePoint {
+    snapshot: Snapshot,
+    schedule: Schedule,
+    drawn: usize,
+    lags: Vec<bool>,
+    /// RPL user flags of the program
+    flags: Flags,
+}
+
+impl SavePoint {
+    /// Number of frames played before the save point
+    pub fn frame(&self) -> usize {
+        self.lags.len()
+    }
+
+    pub fn snapshot(&self) -> &Snapshot {
+        &self.snapshot
+    }
+}
+
+/// Frame by frame emulation of a program with editable inputs
+///
+/// Inputs are keys of each frame as bitmasks, key 0 in the lowest bit.
+/// Playing beyond the last input appends frames without keys. Edits of frames
+/// already played take effect once they are played again, after `load` or
+/// `seek` to an earlier frame.
+pub struct Session<C: Context> {
+    chip: Peach8<Controller<C>>,
+    schedule: Schedule,
+    rom: Vec<u8>,
+    inputs: Vec<u16>,
+    /// Whether each played frame was a lag frame
+    lags: Vec<bool>,
+    start: SavePoint,
+}
+
+impl<C: Context> Session<C> {
+    /// Start a session of a program, seeding its random values
+    pub fn new(inner: C, rom: &[u8], seed: u64) -> Result<Self, &'static str> {
+        Self::start(Controller::new(inner, Vec::new(), seed), rom, Vec::new())
+    }
+
+    /// Start a session with inputs and random values of a movie sampled per frame
+    ///
+    /// Random values drawn beyond those of the movie are seeded with `seed`.
+    pub fn from_movie(inner: C, rom: &[u8], movie: &[u8], seed: u64) -> Result<Self, &'static str> {
+        let header = Header::parse(movie)?;
+        if header.rom != RomHash::of(rom) {
+            return Err("Movie was recorded for another program");
+        }
+        if header.sampling != Sampling::Frame {
+            return Err("Only movies sampled per frame can be edited");
+        }
+        let (inputs, randoms) = decode(&movie[movie::HEADER_SIZE..])?;
+        Self::start(Controller::new(inner, randoms, seed), rom, inputs)
+    }
+
+    fn start(ctx: Controller<C>, rom: &[u8], inputs: Vec<u16>) -> Result<Self, &'static str> {
+        let mut chip = Builder::new().with_context(ctx).with_program(rom).build()?;
+        let start = SavePoint {
+            snapshot: chip.snapshot(),
+            schedule: Schedule::new(CHIP_FREQ, TIMERS_FREQ),
+            drawn: 0,
+            lags: Vec::new(),
+            flags: load_flags(&mut chip)?,
+        };
+        Ok(Self {
+            chip,
+            schedule: start.schedule,
+            rom: rom.to_vec(),
+            inputs,
+            lags: Vec::new(),
+            start,
+        })
+    }
+
+    /// Number of frames played so far, ie. index of the next frame
+    pub fn frame(&self) -> usize {
+        self.lags.len()
+    }
+
+    /// Play the next frame with its input, returning whether it lagged
+    ///
+    /// After an error the frame is played partially, load a `SavePoint`
+    /// to continue.
+    pub fn advance(&mut self) -> Result<bool, &'static str> {
+        let frame = self.frame();
+        if frame == self.inputs.len() {
+            self.inputs.push(0);
+        }
+        self.chip.ctx.keys = self.inputs[frame];
+        self.chip.ctx.polled = false;
+        self.schedule.run_frame(&mut self.chip)?;
+        let lag = !self.chip.ctx.polled;
+        self.lags.push(lag);
+        Ok(lag)
+    }
+
+    /// Play the next `frames` frames, returning how many of them lagged
+    pub fn run(&mut self, frames: usize) -> Result<usize, &'static str> {
+        let mut lags = 0;
+        for _ in 0..frames {
+            lags += self.advance()? as usize;
+        }
+        Ok(lags)
+    }
+
+    /// Replay inputs from the start up to the frame
+    pub fn seek(&mut self, frame: usize) -> Result<(), &'static str> {
+        if frame > self.inputs.len() {
+            return Err("Frame is beyond the end of inputs");
+        }
+        if frame < self.frame() {
+            let start = self.start.clone();
+            self.load(&start)?;
+        }
+        while self.frame() < frame {
+            self.advance()?;
+        }
+        Ok(())
+    }
+
+    /// Capture the emulation, failing if RPL user flags can't be read
+    pub fn save(&mut self) -> Result<SavePoint, &'static str> {
+        Ok(SavePoint {
+            snapshot: self.chip.snapshot(),
+            schedule: self.schedule,
+            drawn: self.chip.ctx.drawn,
+            lags: self.lags.clone(),
+            flags: load_flags(&mut self.chip)?,
+        })
+    }
+
+    /// Bring emulation back to the save point, keeping inputs as they are
+    ///
+    /// Fails if RPL user flags can't be written back, the rest of emulation
+    /// is restored anyway.
+    pub fn load(&mut self, save: &SavePoint) -> Result<(), &'static str> {
+        self.chip.restore(&save.snapshot);
+        self.schedule = save.schedule;
+        let ctx = &mut self.chip.ctx;
+        ctx.drawn = save.drawn;
+        ctx.screen
+            .as_raw_mut()
+            .copy_from_slice(save.snapshot.frame().as_raw());
+        self.lags.clone_from(&save.lags);
+        if load_flags(&mut self.chip)? != save.flags {
+            let rom = self.chip.rom_hash();
+            if let Some(storage) = self.chip.ctx.storage() {
+                storage.save(rom, &save.flags)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn inputs(&self) -> &[u16] {
+        &self.inputs
+    }
+
+    pub fn input(&self, frame: usize) -> Option<u16> {
+        self.inputs.get(frame).copied()
+    }
+
+    /// Set keys of the frame, filling frames up to it with no keys
+    pub fn set_input(&mut self, frame: usize, keys: u16) {
+        if frame >= self.inputs.len() {
+            self.inputs.resize(frame + 1, 0);
+        }
+        self.inputs[frame] = keys;
+    }
+
+    /// Insert a frame of keys, shifting later frames
+    pub fn insert_input(&mut self, frame: usize, keys: u16) {
+        if frame > self.inputs.len() {
+            self.inputs.resize(frame, 0);
+        }
+        self.inputs.insert(frame, keys);
+    }
+
+    /// Remove keys of the frame, shifting later frames
+    pub fn remove_input(&mut self, frame: usize) -> Option<u16> {
+        if frame < self.inputs.len() {
+            Some(self.inputs.remove(frame))
+        } else {
+            None
+        }
+    }
+
+    /// Keep only inputs of the first `frames` frames
+    pub fn truncate(&mut self, frames: usize) {
+        self.inputs.truncate(frames);
+    }
+
+    /// Whether each played frame was a lag frame
+    pub fn lags(&self) -> &[bool] {
+        &self.lags
+    }
+
+    /// Screen at the current frame
+    pub fn screen(&self) -> FrameView<'_> {
+        self.chip.ctx.screen.view()
+    }
+
+    pub fn chip(&self) -> &Peach8<Controller<C>> {
+        &self.chip
+    }
+
+    /// Wrapped context, eg. to take frames it gathered
+    pub fn context_mut(&mut self) -> &mut C {
+        &mut self.chip.ctx.inner
+    }
+
+    /// Movie of all inputs sampled per frame, replayable with `movie::Replay`
+    ///
+    /// Inputs are played again from the start, without the wrapped context,
+    /// with RPL user flags the session started with.
+    pub fn export(&self) -> Result<Vec<u8>, &'static str> {
+        let rom = RomHash::of(&self.rom);
+        let mut storage = MemoryStorage::new();
+        storage.save(rom, &self.start.flags)?;
+        let recorder = Recorder::new(
+            self.chip.ctx.fork(WithStorage::new(Silent, storage)),
+            IoSink(Vec::new()),
+            rom,
+            Sampling::Frame,
+        );
+        let mut chip = Builder::new()
+            .with_context(recorder)
+            .with_program(&self.rom)
+            .build()?;
+        let mut schedule = self.start.schedule;
+        for &keys in &self.inputs {
+            chip.ctx.inner.keys = keys;
+            schedule.run_frame(&mut chip)?;
+        }
+        let (_, IoSink(movie)) = chip.release().release();
+        Ok(movie)
+    }
+
+    /// Drop the session, releasing wrapped context
+    pub fn release(self) -> C {
+        self.chip.release().release()
+    }
+}
+
+/// RPL user flags of the program in storage of the context, zeros if none
+/// are saved, like `FX85` reads them
+fn load_flags<C: Context>(chip: &mut Peach8<C>) -> Result<Flags, &'static str> {
+    let rom = chip.rom_hash();
+    let mut flags = Flags::default();
+    if let Some(storage) = chip.ctx.storage() {
+        storage.load(rom, &mut flags)?;
+    }
+    Ok(flags)
+}
+
+/// Keys of each frame and random values of records of a movie sampled per frame
+fn decode(mut records: &[u8]) -> Result<(Vec<u16>, Vec<u8>), &'static str> {
+    let mut inputs = Vec::new();
+    let mut randoms = Vec::new();
+    let mut keys = 0;
+    while let Some((&tag, rest)) = records.split_first() {
+        let len = match tag {
+            TAG_KEYS => 2,
+            TAG_RANDOM => 1,
+            TAG_VBLANK => 0,
+            _ => return Err("Unknown record of movie"),
+        };
+        if rest.len() < len {
+            return Err("Movie ends inside a record");
+        }
+        match tag {
+            TAG_KEYS => keys = u16::from_le_bytes([rest[0], rest[1]]),
+            TAG_RANDOM => randoms.push(rest[0]),
+            _ => inputs.push(keys),
+        }
+        records = &rest[len..];
+    }
+    Ok((inputs, randoms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::testing::TestingContext;
+
+    // V0 = random digit, V2 += 1 if key 0 is pressed, draw digit V0, loop
+    const PROGRAM: [u8; 16] = [
+        0xC0, 0x0F, 0xE1, 0x9E, 0x12, 0x08, 0x72, 0x01, 0xF0, 0x29, 0x00, 0xE0, 0xD3, 0x45, 0x12,
+        0x00,
+    ];
+
+    fn session() -> Session<TestingContext> {
+        Session::new(TestingContext::new(0), &PROGRAM, 1).unwrap()
+    }
+
+    #[test]
+    fn frames_follow_clock() {
+        let mut session = session();
+        session.run(6).unwrap();
+        assert_eq!(session.frame(), 6);
+        assert_eq!(session.chip().cycles(), 50);
+        assert_eq!(session.inputs(), &[0; 6]);
+
+        // 500 ticks of chip per 60 frames, 8 or 9 in each
+        for _ in 6..60 {
+            let cycles = session.chip().cycles();
+            session.advance().unwrap();
+            assert!(matches!(session.chip().cycles() - cycles, 8 | 9));
+        }
+        assert_eq!(session.chip().cycles(), 500);
+
+        // loading a save point brings back its place in the schedule
+        let save = session.save().unwrap();
+        session.run(1).unwrap();
+        let cycles = session.chip().cycles();
+        session.load(&save).unwrap();
+        session.run(1).unwrap();
+        assert_eq!(session.chip().cycles(), cycles);
+    }
+
+    #[test]
+    fn lag_frames_are_marked() {
+        // delay = 2, wait for it, skip if key 2 is pressed, loop
+        let program = [
+            0x60, 0x02, 0xF0, 0x15, 0xF1, 0x07, 0x31, 0x00, 0x12, 0x04, 0xE0, 0x9E, 0x12, 0x00,
+            0x12, 0x00,
+        ];
+        let mut session = Session::new(TestingContext::new(0), &program, 0).unwrap();
+        assert_eq!(session.advance(), Ok(true));
+        assert_eq!(session.advance(), Ok(true));
+        assert_eq!(session.advance(), Ok(false));
+        // polled every other frame
+        assert_eq!(session.run(57), Ok(29));
+        assert_eq!(session.lags().iter().filter(|&&lag| !lag).count(), 29);
+
+        assert!(polls_input(0xEFA1));
+        assert!(polls_input(0xF30A));
+        assert!(!polls_input(0xE0A0));
+    }
+
+    #[test]
+    fn branches_from_save_points() {
+        let mut session = session();
+        session.run(10).unwrap();
+        let save = session.save().unwrap();
+        assert_eq!(save.frame(), 10);
+        (10..20).for_each(|frame| session.set_input(frame, 1));
+        session.run(10).unwrap();
+        let pressed = session.save().unwrap();
+        let presses = session.chip().state().v[2];
+        assert!(presses > 0);
+
+        // the same inputs lead to the same state, with the same random values
+        session.load(&save).unwrap();
+        assert_eq!(session.frame(), 10);
+        session.run(10).unwrap();
+        assert!(session.save().unwrap().snapshot() == pressed.snapshot());
+        assert_eq!(session.screen(), pressed.snapshot().frame());
+
+        // another branch
+        session.load(&save).unwrap();
+        session.set_input(12, 0);
+        session.run(10).unwrap();
+        assert!(session.chip().state().v[2] < presses);
+    }
+
+    #[test]
+    fn save_points_restore_flags() {
+        // V0 = flag 0 + 1, save it to flag 0, loop
+        let program = [0xF0, 0x85, 0x70, 0x01, 0xF0, 0x75, 0x12, 0x00];
+        let ctx = WithStorage::new(TestingContext::new(0), MemoryStorage::new());
+        let mut session = Session::new(ctx, &program, 0).unwrap();
+        session.run(5).unwrap();
+        let save = session.save().unwrap();
+        session.run(5).unwrap();
+        let end = session.save().unwrap();
+
+        session.load(&save).unwrap();
+        session.run(5).unwrap();
+        assert!(session.save().unwrap().snapshot() == end.snapshot());
+
+        // no flags were saved at the start, so they read as zeros again
+        session.seek(1).unwrap();
+        assert_eq!(session.chip().state().v[0], 2);
+        let rom = session.chip().rom_hash();
+        let mut flags = [0; crate::storage::FLAGS];
+        session.context_mut().storage.load(rom, &mut flags).unwrap();
+        assert_eq!(flags[0], 2);
+    }
+
+    #[test]
+    fn inputs_are_edited() {
+        let mut session = session();
+        session.set_input(3, 0x10);
+        assert_eq!(session.inputs(), &[0, 0, 0, 0x10]);
+        session.insert_input(1, 0x2);
+        assert_eq!(session.inputs(), &[0, 0x2, 0, 0, 0x10]);
+        assert_eq!(session.remove_input(0), Some(0));
+        assert_eq!(session.remove_input(9), None);
+        session.truncate(3);
+        assert_eq!(session.inputs(), &[0x2, 0, 0]);
+        assert_eq!(session.input(0), Some(0x2));
+
+        session.run(5).unwrap();
+        assert_eq!(session.inputs(), &[0x2, 0, 0, 0, 0]);
+        // edits of played frames apply once they are played again
+        session.set_input(0, 0x1);
+        let v2 = session.chip().state().v[2];
+        session.seek(5).unwrap();
+        assert_eq!(session.chip().state().v[2], v2);
+        session.seek(0).unwrap();
+        session.seek(5).unwrap();
+        assert!(session.chip().state().v[2] > v2);
+        assert_eq!(session.seek(6), Err("Frame is beyond the end of inputs"));
+    }
+
+    #[test]
+    fn movies_round_trip() {
+        let mut session = session();
+        (0..30).for_each(|frame| session.set_input(frame, (frame % 3) as u16));
+        session.run(20).unwrap();
+        let movie = session.export().unwrap();
+
+        let mut imported =
+            Session::from_movie(TestingContext::new(0), &PROGRAM, &movie, 9).unwrap();
+        assert_eq!(imported.inputs(), session.inputs());
+        imported.run(20).unwrap();
+        assert!(imported.save().unwrap().snapshot() == session.save().unwrap().snapshot());
+        assert_eq!(imported.export().unwrap(), movie);
+
+        assert_eq!(
+            Session::from_movie(TestingContext::new(0), &PROGRAM[2..], &movie, 0).err(),
+            Some("Movie was recorded for another program")
+        );
+        let mut truncated = movie.clone();
+        truncated.push(TAG_KEYS);
+        assert_eq!(
+            Session::from_movie(TestingContext::new(0), &PROGRAM, &truncated, 0).err(),
+            Some("Movie ends inside a record")
+        );
+    }
+}
@@ -0,0 +1,929 @@
+//! Support for testing programs and frontends
+//!
+//! Available with `testing` feature on, which requires `std`:
+//! - `TestingContext`, a `Context` with seeded random numbers and keys that
+//!   can be pressed directly or scripted to change at given cycles,
+//! - `RecordingContext`, an adapter logging calls to the wrapped context,
+//!   stamped with the cycle they happened in,
+//! - `ImageMask`, a frame in form of ASCII art of `#` (lit) and `.` (unlit)
+//!   characters, with a readable diff, and `assert_eq_2d!` macro comparing
+//!   parts of masks,
+//! - `VirtualClock`, interleaving ticks of chip and timers at exact
+//!   frequencies over simulated time, independent of host's speed and load,
+//! - with `async` feature, `VirtualTimer` for `Peach8::run`, moving its time
+//!   straight to deadlines and scripted key events, and `block_on` executor,
+//! - `fuzz`, harness of fuzz targets, shared with tests replaying their inputs.
+//!
+//! Cycle stamps are values of `Peach8::cycles` at the time of a call, ie. the
+//! number of instructions fetched so far. They are tracked with
+//! `Context::on_instruction`, so a context wrapping `TestingContext` should
+//! forward it.
+
+use core::fmt;
+use core::ops::RangeBounds;
+use core::time::Duration;
+
+use std::boxed::Box;
+use std::vec::Vec;
+#[cfg(feature = "async")]
+use {
+    core::cell::Cell,
+    core::future::Future,
+    core::task::{self, Poll},
+    std::collections::VecDeque,
+    std::rc::Rc,
+};
+
+#[cfg(feature = "embedded-graphics")]
+use embedded_graphics::{drawable::Pixel, pixelcolor::BinaryColor};
+
+use crate::context::Context;
+use crate::frame::{Frame, FrameView, HEIGHT, WIDTH};
+use crate::peach::{Peach8, State};
+pub use crate::rng::Rng;
+use crate::schedule::Schedule;
+pub use crate::schedule::Tick;
+use crate::storage::Storage;
+
+pub mod fuzz;
+
+/// Assert that given ranges of two `ImageMask`s are equal
+///
+/// On failure, panics with both masks and positions of differing pixels.
+#[macro_export]
+macro_rules! assert_eq_2d {
+    (x_range: $xrange:expr, y_range: $yrange:expr; $lhs:expr, $rhs:expr $(,)?) => {{
+        let mut lhs_mask = $crate::testing::ImageMask::new();
+        let mut rhs_mask = $crate::testing::ImageMask::new();
+        lhs_mask.set_slice($xrange, $yrange, &$lhs);
+        rhs_mask.set_slice($xrange, $yrange, &$rhs);
+        if let Some(diff) = lhs_mask.diff(&rhs_mask) {
+            panic!("masks differ:\n{}", diff);
+        }
+    }};
+}
+
+/// Frame in a form of two-dimensional array of pixels
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+pub struct ImageMask([[bool; WIDTH]; HEIGHT]);
+
+impl ImageMask {
+    pub fn new() -> Self {
+        Self([[false; WIDTH]; HEIGHT])
+    }
+
+    /// Parse ASCII art of `#` (lit) and `.` (unlit) characters
+    ///
+    /// Rows are separated by whitespace, so the art can be indented. Missing
+    /// rows and pixels at the end of rows are unlit.
+    pub fn parse(art: &str) -> Result<Self, &'static str> {
+        let mut mask = Self::new();
+        for (y, row) in art.split_whitespace().enumerate() {
+            let m_row = mask.0.get_mut(y).ok_or("Too many rows in the mask")?;
+            if row.chars().count() > WIDTH {
+                return Err("Row of the mask is too long");
+            }
+            for (m, c) in m_row.iter_mut().zip(row.chars()) {
+                *m = match c {
+                    '#' => true,
+                    '.' => false,
+                    _ => return Err("Unexpected character in the mask"),
+                };
+            }
+        }
+        Ok(mask)
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> Option<bool> {
+        self.0.get(y).and_then(|row| row.get(x)).copied()
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, lit: bool) {
+        if let Some(pixel) = self.0.get_mut(y).and_then(|row| row.get_mut(x)) {
+            *pixel = lit;
+        }
+    }
+
+    /// Move content of the mask right and down, pixels moved out are lost
+    pub fn offset(&mut self, xoffset: usize, yoffset: usize) -> &Self {
+        let height = self.0.len();
+        let width = self.0[0].len();
+        for y in (0..height).rev() {
+            for x in (0..width).rev() {
+                self.0[y][x] = y >= yoffset && x >= xoffset && self.0[y - yoffset][x - xoffset];
+            }
+        }
+        self
+    }
+
+    /// Copy pixels in given ranges from other mask
+    pub fn set_slice<T>(&mut self, range_x: T, range_y: T, other: &Self)
+    where
+        T: RangeBounds<usize>,
+    {
+        let width = self.0[0].len();
+        let height = self.0.len();
+        for x in 0..width {
+            for y in 0..height {
+                if range_x.contains(&x) && range_y.contains(&y) {
+                    self.0[y][x] = other.0[y][x];
+                }
+            }
+        }
+    }
+
+    /// Compare with other mask, `None` if they are equal
+    pub fn diff<'a>(&'a self, other: &'a Self) -> Option<Diff<'a>> {
+        if self == other {
+            None
+        } else {
+            Some(Diff {
+                lhs: self,
+                rhs: other,
+            })
+        }
+    }
+
+    fn write_row(&self, f: &mut fmt::Formatter<'_>, y: usize) -> fmt::Result {
+        self.0[y]
+            .iter()
+            .try_for_each(|&p| f.write_str(if p { "#" } else { "." }))
+    }
+}
+
+impl Default for ImageMask {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for ImageMask {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f)?;
+        for y in 0..HEIGHT {
+            self.write_row(f, y)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Differences between two `ImageMask`s
+///
+/// Displayed as both masks side by side, followed by a mask of differing
+/// pixels marked with `X` and their number.
+pub struct Diff<'a> {
+    lhs: &'a ImageMask,
+    rhs: &'a ImageMask,
+}
+
+impl Diff<'_> {
+    /// Positions of differing pixels, row by row
+    pub fn pixels(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..HEIGHT)
+            .flat_map(|y| (0..WIDTH).map(move |x| (x, y)))
+            .filter(move |&(x, y)| self.lhs.0[y][x] != self.rhs.0[y][x])
+    }
+}
+
+impl fmt::Display for Diff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{:<w$} | {:<w$} | diff", "left", "right", w = WIDTH)?;
+        for y in 0..HEIGHT {
+            self.lhs.write_row(f, y)?;
+            f.write_str(" | ")?;
+            self.rhs.write_row(f, y)?;
+            f.write_str(" | ")?;
+            for x in 0..WIDTH {
+                let differs = self.lhs.0[y][x] != self.rhs.0[y][x];
+                f.write_str(if differs { "X" } else { "." })?;
+            }
+            writeln!(f)?;
+        }
+        write!(f, "{} pixels differ", self.pixels().count())
+    }
+}
+
+impl fmt::Debug for Diff<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Conversion into an `ImageMask`
+pub trait ToMask {
+    fn to_mask(&self) -> ImageMask;
+}
+
+/// Lenient conversion of ASCII art, any character other than `#` is unlit
+impl ToMask for str {
+    fn to_mask(&self) -> ImageMask {
+        let mut mask = ImageMask::new();
+        mask.0
+            .iter_mut()
+            .zip(self.split_whitespace())
+            .for_each(|(m_row, c_row)| {
+                m_row
+                    .iter_mut()
+                    .zip(c_row.chars())
+                    .for_each(|(m, c)| *m = c == '#')
+            });
+        mask
+    }
+}
+
+#[cfg(feature = "embedded-graphics")]
+impl<I> ToMask for I
+where
+    I: Iterator<Item = Pixel<BinaryColor>> + Clone,
+{
+    fn to_mask(&self) -> ImageMask {
+        let mut mask = ImageMask::new();
+        self.clone().for_each(|Pixel(point, color)| {
+            if color == BinaryColor::On {
+                mask.set(point.x as usize, point.y as usize, true);
+            }
+        });
+        mask
+    }
+}
+
+impl<'a> ToMask for FrameView<'a> {
+    fn to_mask(&self) -> ImageMask {
+        let mut mask = ImageMask::new();
+        self.iter_rows_as_bitslices()
+            .zip(mask.0.iter_mut())
+            .for_each(|(g_row, m_row)| m_row.iter_mut().zip(g_row).for_each(|(m, &g)| *m = g));
+        mask
+    }
+}
+
+impl ToMask for Frame {
+    fn to_mask(&self) -> ImageMask {
+        self.view().to_mask()
+    }
+}
+
+/// `Context` for tests, keeping the last frame, state of sound and keys
+pub struct TestingContext {
+    sound: bool,
+    frame: Option<ImageMask>,
+    keys: [bool; 16],
+    /// Scripted changes of keys as (cycle, key, pressed), sorted by cycle
+    script: Vec<(u64, u8, bool)>,
+    cycles: u64,
+    rng: Rng,
+}
+
+impl TestingContext {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            sound: false,
+            frame: None,
+            keys: [false; 16],
+            script: Vec::new(),
+            cycles: 0,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Create context with scripted changes of keys, given as (cycle, key, pressed)
+    pub fn with_script(seed: u64, script: &[(u64, u8, bool)]) -> Self {
+        let mut ctx = Self::new(seed);
+        script
+            .iter()
+            .for_each(|&(cycle, key, pressed)| ctx.schedule(cycle, key, pressed));
+        ctx
+    }
+
+    /// Press or release a key once given cycle is reached
+    pub fn schedule(&mut self, cycle: u64, key: u8, pressed: bool) {
+        let at = self.script.partition_point(|&(c, _, _)| c <= cycle);
+        self.script.insert(at, (cycle, key, pressed));
+    }
+
+    pub fn is_sound_on(&self) -> bool {
+        self.sound
+    }
+
+    /// The last frame passed to `on_frame`
+    pub fn get_frame(&self) -> Option<&ImageMask> {
+        self.frame.as_ref()
+    }
+
+    pub fn set_key(&mut self, n: u8) {
+        self.keys[n as usize] = true;
+    }
+
+    pub fn reset_key(&mut self, n: u8) {
+        self.keys[n as usize] = false;
+    }
+
+    /// Value of `Peach8::cycles` as observed by the context
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    fn run_script(&mut self) {
+        let due = self
+            .script
+            .iter()
+            .take_while(|&&(cycle, _, _)| cycle <= self.cycles)
+            .count();
+        for (_, key, pressed) in self.script.drain(..due) {
+            self.keys[key as usize] = pressed;
+        }
+    }
+}
+
+impl Context for TestingContext {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.frame = Some(frame.to_mask());
+    }
+
+    fn sound_on(&mut self) {
+        self.sound = true;
+    }
+
+    fn sound_off(&mut self) {
+        self.sound = false;
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.rng.next_u8()
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.run_script();
+        self.keys
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, _opcode: u16) {
+        self.cycles = state.cycle + 1;
+    }
+}
+
+/// Call of a `Context` method logged by `RecordingContext`
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Call {
+    Frame(Box<Frame>),
+    SoundOn,
+    SoundOff,
+    /// Keys returned by the wrapped context
+    Keys([bool; 16]),
+    /// Number returned by the wrapped context
+    Random(u8),
+}
+
+/// Logged call with the cycle it happened in
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Record {
+    pub cycle: u64,
+    pub call: Call,
+}
+
+/// `Context` adapter logging every call of `on_frame`, `sound_on`,
+/// `sound_off`, `get_keys` and `gen_random`
+///
+/// `on_frame` is called after every instruction, `changed_frames` skips
+/// frames equal to the previous one.
+pub struct RecordingContext<C> {
+    pub inner: C,
+    records: Vec<Record>,
+    cycles: u64,
+}
+
+impl<C: Context> RecordingContext<C> {
+    pub fn new(inner: C) -> Self {
+        Self {
+            inner,
+            records: Vec::new(),
+            cycles: 0,
+        }
+    }
+
+    /// Drop the recorder, releasing wrapped context
+    pub fn release(self) -> C {
+        self.inner
+    }
+
+    /// Logged calls, in order of calling
+    pub fn records(&self) -> &[Record] {
+        &self.records
+    }
+
+    /// Take logged calls, leaving the log empty
+    pub fn take_records(&mut self) -> Vec<Record> {
+        core::mem::take(&mut self.records)
+    }
+
+    /// Logged frames with cycles they were drawn in
+    pub fn frames(&self) -> impl Iterator<Item = (u64, &Frame)> + '_ {
+        self.records.iter().filter_map(|r| match &r.call {
+            Call::Frame(frame) => Some((r.cycle, &**frame)),
+            _ => None,
+        })
+    }
+
+    /// Logged frames which differ from the previous one, with cycles they
+    /// were drawn in
+    pub fn changed_frames(&self) -> impl Iterator<Item = (u64, &Frame)> + '_ {
+        let mut last = None;
+        self.frames().filter(move |&(_, frame)| {
+            let changed = last != Some(frame);
+            last = Some(frame);
+            changed
+        })
+    }
+
+    fn record(&mut self, call: Call) {
+        self.records.push(Record {
+            cycle: self.cycles,
+            call,
+        });
+    }
+}
+
+impl<C: Context> Context for RecordingContext<C> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.record(Call::Frame(Box::new(frame.copy_frame())));
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.record(Call::SoundOn);
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.record(Call::SoundOff);
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        let keys = self.inner.get_keys();
+        self.record(Call::Keys(keys));
+        keys
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        let value = self.inner.gen_random();
+        self.record(Call::Random(value));
+        value
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.cycles = state.cycle + 1;
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+/// Scheduler of chip and timer ticks over simulated time
+///
+/// Ticks follow `schedule::Schedule`, so the schedule never drifts: eg. at
+/// 500Hz and 60Hz there are always 8 or 9 instructions between ticks of timers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct VirtualClock {
+    schedule: Schedule,
+    /// Simulated time reached by `run_for`
+    now: Duration,
+}
+
+impl VirtualClock {
+    /// Create clock ticking chip and timers with given frequencies in Hz
+    ///
+    /// Panics when any frequency is 0.
+    pub fn new(chip_freq: u32, timers_freq: u32) -> Self {
+        Self {
+            schedule: Schedule::new(chip_freq, timers_freq),
+            now: Duration::ZERO,
+        }
+    }
+
+    /// Number of chip ticks so far
+    pub fn chip_ticks(&self) -> u64 {
+        self.schedule.chip_ticks()
+    }
+
+    /// Number of timer ticks so far
+    pub fn timers_ticks(&self) -> u64 {
+        self.schedule.timers_ticks()
+    }
+
+    /// Simulated time of the last tick, or reached by `run_for` if later
+    pub fn elapsed(&self) -> Duration {
+        self.now.max(self.schedule.last_tick())
+    }
+
+    /// The next tick, without advancing the clock
+    pub fn peek(&self) -> Tick {
+        self.schedule.peek()
+    }
+
+    /// Advance the clock to the next tick and return it
+    pub fn advance(&mut self) -> Tick {
+        self.schedule.advance()
+    }
+
+    /// Run every tick due in the next `duration` of simulated time
+    ///
+    /// Stops at the first error of `Peach8::tick_chip`.
+    pub fn run_for<C: Context>(
+        &mut self,
+        chip: &mut Peach8<C>,
+        duration: Duration,
+    ) -> Result<(), &'static str> {
+        self.now = self.elapsed() + duration;
+        while self.schedule.is_due(self.now) {
+            self.schedule.step(chip)?;
+        }
+        Ok(())
+    }
+
+    /// Run ticks until timers ticked `frames` times, ie. for that many frames
+    ///
+    /// Stops at the first error of `Peach8::tick_chip`.
+    pub fn run_frames<C: Context>(
+        &mut self,
+        chip: &mut Peach8<C>,
+        frames: u64,
+    ) -> Result<(), &'static str> {
+        for _ in 0..frames {
+            self.schedule.run_frame(chip)?;
+        }
+        Ok(())
+    }
+
+    /// Run ticks until the condition is met or `timeout` of simulated time passes
+    ///
+    /// Returns whether the condition was met.
+    pub fn run_until<C: Context>(
+        &mut self,
+        chip: &mut Peach8<C>,
+        timeout: Duration,
+        mut condition: impl FnMut(&Peach8<C>) -> bool,
+    ) -> Result<bool, &'static str> {
+        let start = self.elapsed();
+        self.now = start + timeout;
+        while !condition(chip) {
+            if !self.schedule.is_due(self.now) {
+                return Ok(false);
+            }
+            self.schedule.step(chip)?;
+        }
+        // don't skip ticks due before the deadline on the next run
+        self.now = start.max(self.schedule.last_tick());
+        Ok(true)
+    }
+}
+
+impl Default for VirtualClock {
+    /// Chip at 500Hz and timers at 60Hz
+    fn default() -> Self {
+        Self::new(500, 60)
+    }
+}
+
+/// `asynch::Timer` in simulated time, which jumps to each awaited deadline
+///
+/// Completes `wait_for_keys` at the next scripted key event, if it's earlier
+/// than the deadline. A context can read the time through `now_handle`.
+#[cfg(feature = "async")]
+#[derive(Clone, Debug, Default)]
+pub struct VirtualTimer {
+    now: Rc<Cell<Duration>>,
+    /// Times of key events, sorted
+    key_events: VecDeque<Duration>,
+}
+
+#[cfg(feature = "async")]
+impl VirtualTimer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Schedule events of keys at given times
+    pub fn key_events(&mut self, times: &[Duration]) {
+        self.key_events.extend(times);
+        self.key_events.make_contiguous().sort();
+    }
+
+    /// Shared handle to the current time
+    pub fn now_handle(&self) -> Rc<Cell<Duration>> {
+        self.now.clone()
+    }
+
+    /// Move time forward to `time`, dropping key events that happened before
+    fn advance(&mut self, time: Duration) {
+        self.now.set(self.now.get().max(time));
+        while self.key_events.front().is_some_and(|&e| e < self.now.get()) {
+            self.key_events.pop_front();
+        }
+    }
+}
+
+#[cfg(feature = "async")]
+impl crate::asynch::Timer for VirtualTimer {
+    fn now(&mut self) -> Duration {
+        self.now.get()
+    }
+
+    fn wait_until(&mut self, deadline: Duration) -> impl Future<Output = ()> {
+        self.advance(deadline);
+        core::future::ready(())
+    }
+
+    fn wait_for_keys(&mut self, deadline: Duration) -> impl Future<Output = ()> {
+        match self.key_events.front() {
+            Some(&event) if event <= deadline => {
+                self.key_events.pop_front();
+                self.advance(event);
+            }
+            _ => self.advance(deadline),
+        }
+        core::future::ready(())
+    }
+}
+
+/// Run the future to completion on the current thread
+///
+/// Polls it in a loop, so it suits futures that never wait for external
+/// events, eg. `Peach8::run` with `VirtualTimer`.
+#[cfg(feature = "async")]
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = core::pin::pin!(future);
+    let mut cx = task::Context::from_waker(task::Waker::noop());
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::format;
+
+    use crate::builder::Builder;
+    use crate::frame::MEM_LENGTH;
+
+    #[test]
+    fn to_image_mask() {
+        let empty_mask_str = include_str!("../test-data/context/empty_mask");
+        let full_mask_str = include_str!("../test-data/context/full_mask");
+        let full_frame = [255; MEM_LENGTH];
+
+        assert_eq!(ImageMask::parse(empty_mask_str), Ok(ImageMask::new()));
+        assert_eq!(empty_mask_str.to_mask(), ImageMask::new());
+        assert_eq!(
+            full_mask_str.to_mask(),
+            FrameView::new(&full_frame).to_mask()
+        );
+        assert_eq!(ImageMask::parse(full_mask_str), Ok(full_mask_str.to_mask()));
+    }
+
+    #[cfg(feature = "embedded-graphics")]
+    #[test]
+    fn pixels_to_image_mask() {
+        use embedded_graphics::image::{ImageRaw, IntoPixelIter};
+
+        let full_mask_str = include_str!("../test-data/context/full_mask");
+        let full_mask_data: &[u8] = &[255; MEM_LENGTH];
+        let full_image: ImageRaw<BinaryColor> =
+            ImageRaw::new(full_mask_data, WIDTH as u32, HEIGHT as u32);
+
+        assert_eq!(full_mask_str.to_mask(), full_image.pixel_iter().to_mask());
+    }
+
+    #[test]
+    fn parse_mask() {
+        let mask = ImageMask::parse(
+            "#.
+             .#",
+        )
+        .unwrap();
+        assert_eq!(
+            (
+                mask.get(0, 0),
+                mask.get(1, 0),
+                mask.get(1, 1),
+                mask.get(64, 0)
+            ),
+            (Some(true), Some(false), Some(true), None)
+        );
+        assert_eq!(
+            ImageMask::parse("#x"),
+            Err("Unexpected character in the mask")
+        );
+        assert_eq!(
+            ImageMask::parse(&"#".repeat(65)),
+            Err("Row of the mask is too long")
+        );
+        assert_eq!(
+            ImageMask::parse(&". ".repeat(33)),
+            Err("Too many rows in the mask")
+        );
+    }
+
+    #[test]
+    fn diff() {
+        let lhs = "#..#".to_mask();
+        let rhs = "#.#.".to_mask();
+        assert!(lhs.diff(&lhs).is_none());
+
+        let diff = lhs.diff(&rhs).unwrap();
+        assert_eq!(diff.pixels().collect::<Vec<_>>(), [(2, 0), (3, 0)]);
+        let text = format!("{}", diff);
+        assert_eq!(
+            text.lines().nth(1),
+            Some(&*format!(
+                "{:.<64} | {:.<64} | {:.<64}",
+                "#..#", "#.#.", "..XX"
+            ))
+        );
+        assert!(text.ends_with("\n2 pixels differ"));
+    }
+
+    #[test]
+    #[should_panic(expected = "2 pixels differ")]
+    fn assert_eq_2d_fails() {
+        assert_eq_2d!(x_range: .., y_range: ..; "#..#".to_mask(), "#.#.".to_mask());
+    }
+
+    #[test]
+    fn testing_context() {
+        let mut ctx = TestingContext::new(0);
+
+        let full_mask_str = include_str!("../test-data/context/full_mask");
+        let full_mask_data: &[u8; MEM_LENGTH] = &[255; MEM_LENGTH];
+
+        ctx.on_frame(FrameView::new(full_mask_data));
+        assert!(ctx.frame.is_some());
+        assert_eq!(ctx.frame.unwrap(), full_mask_str.to_mask());
+
+        ctx.sound_on();
+        assert!(ctx.is_sound_on());
+
+        ctx.sound_off();
+        assert!(!ctx.is_sound_on());
+
+        ctx.set_key(0x01u8);
+        ctx.set_key(0x0Fu8);
+        assert_eq!(ctx.get_keys().iter().filter(|&&k| k).count(), 2);
+        assert_eq!((ctx.keys[0x01], ctx.keys[0x0F]), (true, true));
+
+        ctx.reset_key(0x0Fu8);
+        assert_eq!(ctx.get_keys().iter().filter(|&&k| k).count(), 1);
+        assert_eq!((ctx.keys[0x01], ctx.keys[0x0F]), (true, false));
+    }
+
+    #[rustfmt::skip]
+    const PROGRAM: &[u8] = &[
+        0xF0, 0x0A, // 0x200 LD V0, K
+        0xC1, 0xFF, // 0x202 RND V1, 0xFF
+        0xF0, 0x29, // 0x204 LD F, V0
+        0xD0, 0x05, // 0x206 DRW V0, V0, 5
+        0x12, 0x08, // 0x208 JP 0x208
+    ];
+
+    #[test]
+    fn scripted_recording() {
+        let ctx = TestingContext::with_script(3, &[(4, 0x7, false), (2, 0x7, true)]);
+        let mut chip = Builder::new()
+            .with_context(RecordingContext::new(ctx))
+            .with_program(PROGRAM)
+            .build()
+            .unwrap();
+        for _ in 0..10 {
+            chip.tick_chip().unwrap();
+        }
+        chip.tick_timers();
+        let recorder = chip.release();
+
+        // FX0A waits for the key pressed at cycle 2 and released at cycle 4
+        let keys: Vec<_> = recorder
+            .records()
+            .iter()
+            .filter_map(|r| match r.call {
+                Call::Keys(keys) => Some((r.cycle, keys[0x7])),
+                _ => None,
+            })
+            .take(6)
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                (0, false),
+                (1, false),
+                (2, true),
+                (3, true),
+                (4, false),
+                (5, false)
+            ]
+        );
+
+        let random = recorder
+            .records()
+            .iter()
+            .find(|r| matches!(r.call, Call::Random(_)))
+            .unwrap();
+        assert_eq!(random.call, Call::Random(Rng::new(3).next_u8()));
+
+        // a frame after each instruction, changed only by DXYN
+        let cycles: Vec<_> = recorder.frames().map(|(cycle, _)| cycle).collect();
+        assert_eq!(cycles, (1..=10).collect::<Vec<_>>());
+        let frames: Vec<_> = recorder.changed_frames().collect();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].0, frames[0].1), (1, &Frame::new()));
+        assert_eq_2d!(
+            x_range: .., y_range: ..;
+            frames[1].1.to_mask(), "####
+                                    ...#
+                                    ..#.
+                                    .#..
+                                    .#..".to_mask().offset(7, 7)
+        );
+        assert_eq!(recorder.release().cycles(), 10);
+    }
+
+    #[test]
+    fn virtual_clock_schedule() {
+        let mut clock = VirtualClock::default();
+        let ticks: Vec<_> = (0..30).map(|_| clock.advance()).collect();
+        let frame = |n| core::iter::repeat_n(Tick::Chip, n).chain(Some(Tick::Timers));
+        // 8.33 instructions per frame, chip ticks first at 50ms
+        let expected: Vec<_> = frame(8).chain(frame(8)).chain(frame(9)).collect();
+        assert_eq!(ticks[..expected.len()], expected[..]);
+
+        let mut clock = VirtualClock::new(10, 3);
+        let (mut chip, mut timers) = (0, 0);
+        while clock.elapsed() < Duration::from_secs(3) {
+            match clock.advance() {
+                Tick::Chip => chip += 1,
+                Tick::Timers => timers += 1,
+            }
+        }
+        assert_eq!((chip, timers), (30, 8));
+        assert_eq!(clock.elapsed(), Duration::from_secs(3));
+        assert_eq!(clock.peek(), Tick::Timers);
+    }
+
+    #[test]
+    fn virtual_clock_runs_chip() {
+        let build = || {
+            Builder::new()
+                .with_context(RecordingContext::new(TestingContext::new(0)))
+                .with_program(PROGRAM)
+                .build()
+                .unwrap()
+        };
+
+        let mut chip = build();
+        let mut clock = VirtualClock::default();
+        clock.run_for(&mut chip, Duration::from_secs(3)).unwrap();
+        assert_eq!((clock.chip_ticks(), clock.timers_ticks()), (1500, 180));
+        assert_eq!(chip.cycles(), 1500);
+
+        // split runs tick the same way as one long run
+        let mut split = build();
+        let mut split_clock = VirtualClock::default();
+        for _ in 0..3 {
+            split_clock
+                .run_for(&mut split, Duration::from_millis(999))
+                .unwrap();
+            split_clock
+                .run_for(&mut split, Duration::from_millis(1))
+                .unwrap();
+        }
+        assert_eq!(split_clock, clock);
+        assert_eq!(split.release().records(), chip.release().records());
+
+        let mut chip = build();
+        let mut clock = VirtualClock::default();
+        clock.run_frames(&mut chip, 3).unwrap();
+        assert_eq!((clock.chip_ticks(), clock.timers_ticks()), (25, 3));
+        assert_eq!(clock.elapsed(), Duration::from_millis(50));
+
+        let met = clock
+            .run_until(&mut chip, Duration::from_secs(1), |chip| {
+                chip.cycles() == 40
+            })
+            .unwrap();
+        assert!(met);
+        assert_eq!(clock.chip_ticks(), 40);
+        let met = clock
+            .run_until(&mut chip, Duration::from_millis(10), |_| false)
+            .unwrap();
+        assert!(!met);
+        assert_eq!(clock.chip_ticks(), 45);
+    }
+}
@@ -0,0 +1,192 @@
+//! Harness of fuzz targets in `fuzz` directory
+//!
+//! Fuzz targets call `run` and `restore` with their inputs, so tests replaying
+//! inputs which crashed them run exactly the same code. Malformed inputs are
+//! skipped without running the interpreter.
+
+use crate::builder::Builder;
+use crate::context::Context;
+use crate::extension::Registers;
+use crate::frame::FrameView;
+use crate::peach::{Peach8, Snapshot, SNAPSHOT_SIZE};
+
+/// Instructions run by `run` for each input, unless the program fails earlier
+pub const CYCLES: u32 = 10_000;
+/// Instructions run by `restore` for each input
+pub const SNAPSHOT_CYCLES: u32 = 1_000;
+/// Undefined `5XY1`, setting I to VX:VY like an extension is allowed to
+pub const SET_I: (u16, u16) = (0xF00F, 0x5001);
+
+/// Input of the fuzzer, laid out as:
+/// - number of instructions per tick of timers,
+/// - number of key states N, followed by N states of keys as bits of `u16`,
+/// - number of random numbers M, followed by M random numbers,
+/// - the program, taking the rest of bytes.
+///
+/// Key states and random numbers are consumed one per cycle, repeating.
+pub struct Input<'a> {
+    pub ticks_per_frame: u8,
+    pub keys: &'a [u8],
+    pub random: &'a [u8],
+    pub rom: &'a [u8],
+}
+
+impl<'a> Input<'a> {
+    /// Split `data` into parts, or `None` if it's too short
+    pub fn parse(data: &'a [u8]) -> Option<Self> {
+        let (&ticks_per_frame, data) = data.split_first()?;
+        let (&keys, data) = data.split_first()?;
+        let keys = data.get(..keys as usize * 2)?;
+        let data = &data[keys.len()..];
+        let (&random, data) = data.split_first()?;
+        let random = data.get(..random as usize)?;
+        let rom = &data[random.len()..];
+        Some(Self {
+            ticks_per_frame,
+            keys,
+            random,
+            rom,
+        })
+    }
+
+    fn context(&self) -> FuzzContext<'a> {
+        FuzzContext {
+            keys: self.keys,
+            random: self.random,
+            cycle: 0,
+        }
+    }
+}
+
+/// Context repeating key states and random numbers of an `Input`
+struct FuzzContext<'a> {
+    keys: &'a [u8],
+    random: &'a [u8],
+    cycle: usize,
+}
+
+impl Context for FuzzContext<'_> {
+    fn on_frame(&mut self, _frame: FrameView<'_>) {}
+
+    fn sound_on(&mut self) {}
+
+    fn sound_off(&mut self) {}
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.cycle += 1;
+        let mut state = [false; 16];
+        if !self.keys.is_empty() {
+            let n = self.cycle % (self.keys.len() / 2) * 2;
+            let keys = u16::from_le_bytes([self.keys[n], self.keys[n + 1]]);
+            state
+                .iter_mut()
+                .enumerate()
+                .for_each(|(n, key)| *key = keys & 1 << n != 0);
+        }
+        state
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        match self.random.len() {
+            0 => 0,
+            len => self.random[self.cycle % len],
+        }
+    }
+}
+
+/// Handler of `SET_I`
+fn set_i(_: &mut FuzzContext<'_>, regs: Registers<'_>, raw: u16) -> Result<(), &'static str> {
+    let (x, y) = ((raw >> 8 & 0xF) as usize, (raw >> 4 & 0xF) as usize);
+    *regs.i = (regs.v[x] as u16) << 8 | regs.v[y] as u16;
+    Ok(())
+}
+
+/// Run `cycles` instructions, ticking timers as `input` says
+fn tick(chip: &mut Peach8<FuzzContext<'_>>, input: &Input<'_>, cycles: u32) {
+    let ticks_per_frame = input.ticks_per_frame.max(1) as u32;
+    for n in 1..=cycles {
+        if chip.tick_chip().is_err() {
+            break;
+        }
+        if n % ticks_per_frame == 0 {
+            chip.tick_timers();
+        }
+    }
+}
+
+/// Load the program of an `Input` and run it for `CYCLES` instructions
+pub fn run(data: &[u8]) {
+    let input = match Input::parse(data) {
+        Some(input) => input,
+        None => return,
+    };
+    let mut chip = Builder::new()
+        .with_context(input.context())
+        .with_program(input.rom)
+        .with_instruction(SET_I.0, SET_I.1, set_i)
+        .build()
+        .unwrap();
+    tick(&mut chip, &input, CYCLES);
+}
+
+/// Restore a snapshot, laid out as by `Snapshot::to_bytes`, and run it for
+/// `SNAPSHOT_CYCLES` instructions
+///
+/// Bytes after the snapshot are an `Input`, whose program is ignored. Both
+/// the restored state and the state reached are checked to be valid
+/// snapshots.
+pub fn restore(data: &[u8]) {
+    if data.len() < SNAPSHOT_SIZE {
+        return;
+    }
+    let (snapshot, data) = data.split_at(SNAPSHOT_SIZE);
+    let (snapshot, input) = match (Snapshot::parse(snapshot), Input::parse(data)) {
+        (Ok(snapshot), Some(input)) => (snapshot, input),
+        _ => return,
+    };
+    let mut chip = Builder::new()
+        .with_context(input.context())
+        .with_program(&[])
+        .build()
+        .unwrap();
+    chip.restore(&snapshot);
+    assert!(chip.snapshot() == snapshot);
+    tick(&mut chip, &input, SNAPSHOT_CYCLES);
+    let snapshot = chip.snapshot();
+    assert!(Snapshot::parse(&snapshot.to_bytes()) == Ok(snapshot));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::vec::Vec;
+
+    #[test]
+    fn parse_input() {
+        let input = Input::parse(&[8, 1, 0xFF, 0x00, 2, 7, 9, 0x12]).unwrap();
+        assert_eq!(input.ticks_per_frame, 8);
+        assert_eq!(input.keys, &[0xFF, 0x00]);
+        assert_eq!(input.random, &[7, 9]);
+        assert_eq!(input.rom, &[0x12]);
+
+        // truncated inputs are rejected
+        assert!(Input::parse(&[]).is_none());
+        assert!(Input::parse(&[8, 2, 0xFF, 0x00, 0xFF]).is_none());
+        assert!(Input::parse(&[8, 0, 3, 7]).is_none());
+        run(&[8, 2, 0xFF]);
+    }
+
+    #[test]
+    fn restore_snapshot() {
+        let mut chip = Builder::new()
+            .with_context(Input::parse(&[1, 0, 0]).unwrap().context())
+            .with_program(&[0x70, 0x01, 0x12, 0x00])
+            .build()
+            .unwrap();
+        chip.tick_chip().unwrap();
+        let mut data: Vec<u8> = chip.snapshot().to_bytes().to_vec();
+        data.extend_from_slice(&[8, 0, 0]);
+        restore(&data);
+        restore(&data[..SNAPSHOT_SIZE]);
+    }
+}
@@ -1,65 +1,94 @@
-#![allow(dead_code)]
+//! Delay and sound timers
+//!
+//! `Timers` hold both timers of `Peach8`. Placed in a static and passed to
+//! `Builder::with_timers`, they can be ticked at 60Hz from an interrupt through
+//! a `TimerHandle`, independently of `Peach8::tick_chip`.
+//!
+//! Each timer is an `AtomicU8` with `atomic` feature on. On targets without
+//! atomic operations on `u8`, eg. `thumbv6m-none-eabi`, `critical-section`
+//! feature guards them with critical sections instead. Without any of them
+//! timers can't be shared, and `Peach8::tick_timers` must not interrupt
+//! `Peach8::tick_chip`.
 
+/// State of a timer before its tick
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum TimerState {
+    /// Timer was running and still is
     On,
+    /// Timer was stopped
     Off,
+    /// Timer was running and has just stopped
     Finished,
 }
 
-pub mod racy {
+impl TimerState {
+    fn before_tick(value: u8) -> Self {
+        match value {
+            0 => TimerState::Off,
+            1 => TimerState::Finished,
+            _ => TimerState::On,
+        }
+    }
+}
+
+#[cfg(not(any(feature = "atomic", feature = "critical-section")))]
+mod racy {
     use super::TimerState;
+    use core::cell::Cell;
 
     #[derive(Debug)]
-    pub struct Timer(u8);
+    pub struct Timer(Cell<u8>);
 
     impl Timer {
-        pub fn new() -> Self {
-            Self(0)
+        pub const fn new() -> Self {
+            Self(Cell::new(0))
         }
 
         #[inline]
-        pub fn store(&mut self, value: u8) {
-            self.0 = value;
+        pub fn store(&self, value: u8) {
+            self.0.set(value);
         }
 
         #[inline]
         pub fn load(&self) -> u8 {
-            self.0
+            self.0.get()
         }
 
         #[inline]
-        pub fn decrement(&mut self) -> TimerState {
-            if self.0 > 0 {
-                self.0 -= 1;
-                if self.0 == 0 {
-                    TimerState::Finished
-                } else {
-                    TimerState::On
-                }
-            } else {
-                TimerState::Off
-            }
+        pub fn decrement(&self) -> TimerState {
+            let value = self.0.get();
+            self.0.set(value.saturating_sub(1));
+            TimerState::before_tick(value)
         }
     }
 }
 
 #[cfg(feature = "atomic")]
-pub mod atomic {
+mod atomic {
     use super::TimerState;
+    #[cfg(not(loom))]
     use core::sync::atomic::{AtomicU8, Ordering};
+    #[cfg(loom)]
+    use loom::sync::atomic::{AtomicU8, Ordering};
 
     #[derive(Debug)]
     pub struct Timer(AtomicU8);
 
     impl Timer {
+        #[cfg(not(loom))]
+        pub const fn new() -> Self {
+            Self(AtomicU8::new(0))
+        }
+
+        #[cfg(loom)]
         pub fn new() -> Self {
             Self(AtomicU8::new(0))
         }
 
+        /// Swapped rather than stored, so loom orders it exactly against `decrement`
         #[inline]
-        pub fn store(&mut self, value: u8) {
-            self.0.store(value, Ordering::Release);
+        pub fn store(&self, value: u8) {
+            self.0.swap(value, Ordering::AcqRel);
         }
 
         #[inline]
@@ -68,21 +97,280 @@ pub mod atomic {
         }
 
         #[inline]
-        pub fn decrement(&mut self) -> TimerState {
+        pub fn decrement(&self) -> TimerState {
             self.0
-                .fetch_update(Ordering::Release, Ordering::Relaxed, |value| {
-                    if value > 0 {
-                        Some(value - 1)
-                    } else {
-                        Some(value)
-                    }
-                })
-                .map(|value| match value {
-                    0 => TimerState::Off,
-                    1 => TimerState::Finished,
-                    _ => TimerState::On,
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |value| {
+                    Some(value.saturating_sub(1))
                 })
+                .map(TimerState::before_tick)
                 .unwrap()
         }
     }
 }
+
+#[cfg(all(feature = "critical-section", not(feature = "atomic")))]
+mod guarded {
+    use super::TimerState;
+    use core::cell::Cell;
+    use critical_section::Mutex;
+
+    #[derive(Debug)]
+    pub struct Timer(Mutex<Cell<u8>>);
+
+    impl Timer {
+        pub const fn new() -> Self {
+            Self(Mutex::new(Cell::new(0)))
+        }
+
+        #[inline]
+        pub fn store(&self, value: u8) {
+            critical_section::with(|cs| self.0.borrow(cs).set(value));
+        }
+
+        #[inline]
+        pub fn load(&self) -> u8 {
+            critical_section::with(|cs| self.0.borrow(cs).get())
+        }
+
+        #[inline]
+        pub fn decrement(&self) -> TimerState {
+            critical_section::with(|cs| {
+                let timer = self.0.borrow(cs);
+                let value = timer.get();
+                timer.set(value.saturating_sub(1));
+                TimerState::before_tick(value)
+            })
+        }
+    }
+}
+
+#[cfg(feature = "atomic")]
+use atomic::Timer;
+#[cfg(all(feature = "critical-section", not(feature = "atomic")))]
+use guarded::Timer;
+#[cfg(not(any(feature = "atomic", feature = "critical-section")))]
+use racy::Timer;
+
+/// Delay and sound timers of `Peach8`
+#[derive(Debug)]
+pub struct Timers {
+    delay: Timer,
+    sound: Timer,
+}
+
+impl Timers {
+    #[cfg(not(loom))]
+    pub const fn new() -> Self {
+        Self {
+            delay: Timer::new(),
+            sound: Timer::new(),
+        }
+    }
+
+    #[cfg(loom)]
+    pub fn new() -> Self {
+        Self {
+            delay: Timer::new(),
+            sound: Timer::new(),
+        }
+    }
+
+    /// Handle ticking the timers, eg. from an interrupt
+    #[cfg(any(feature = "atomic", feature = "critical-section"))]
+    pub fn handle(&self) -> TimerHandle<'_> {
+        TimerHandle(self)
+    }
+
+    pub(crate) fn delay(&self) -> u8 {
+        self.delay.load()
+    }
+
+    pub(crate) fn sound(&self) -> u8 {
+        self.sound.load()
+    }
+
+    pub(crate) fn set_delay(&self, value: u8) {
+        self.delay.store(value);
+    }
+
+    pub(crate) fn set_sound(&self, value: u8) {
+        self.sound.store(value);
+    }
+
+    /// Decrement both timers, returning the state of sound timer
+    pub(crate) fn tick(&self) -> TimerState {
+        self.delay.decrement();
+        self.sound.decrement()
+    }
+}
+
+impl Default for Timers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Shared handle to `Timers`, which can tick them concurrently with `Peach8`
+///
+/// # Example
+/// ```ignore
+/// static TIMERS: Timers = Timers::new();
+///
+/// static mut TICKS: Queue<TimerState, U4> = Queue(heapless::i::Queue::new());
+///
+/// #[interrupt]
+/// fn TIM1() {
+///     let state = TIMERS.handle().tick();
+///     unsafe { TICKS.enqueue(state).ok() };
+/// }
+///
+/// // main loop
+/// while let Some(state) = ticks.dequeue() {
+///     chip.vblank(state);
+/// }
+/// ```
+#[cfg(any(feature = "atomic", feature = "critical-section"))]
+#[derive(Copy, Clone, Debug)]
+pub struct TimerHandle<'a>(&'a Timers);
+
+#[cfg(any(feature = "atomic", feature = "critical-section"))]
+impl TimerHandle<'_> {
+    /// Decrement both timers, returning the state of sound timer
+    ///
+    /// Unlike `Peach8::tick_timers`, it calls no methods of `Context`: the
+    /// returned state should be passed to `Peach8::vblank` in the main loop.
+    pub fn tick(&self) -> TimerState {
+        self.0.tick()
+    }
+
+    /// Current value of delay timer
+    pub fn delay(&self) -> u8 {
+        self.0.delay()
+    }
+
+    /// Current value of sound timer
+    pub fn sound(&self) -> u8 {
+        self.0.sound()
+    }
+}
+
+/// `Timers` owned by `Peach8`, or shared with an interrupt
+#[derive(Debug)]
+pub(crate) enum Slot {
+    Owned(Timers),
+    #[cfg(any(feature = "atomic", feature = "critical-section"))]
+    Shared(&'static Timers),
+}
+
+impl core::ops::Deref for Slot {
+    type Target = Timers;
+
+    fn deref(&self) -> &Timers {
+        match self {
+            Slot::Owned(timers) => timers,
+            #[cfg(any(feature = "atomic", feature = "critical-section"))]
+            Slot::Shared(timers) => timers,
+        }
+    }
+}
+
+#[cfg(all(test, not(loom)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tick() {
+        let timers = Timers::new();
+        timers.set_delay(2);
+        timers.set_sound(1);
+        assert_eq!(timers.tick(), TimerState::Finished);
+        assert_eq!((timers.delay(), timers.sound()), (1, 0));
+        assert_eq!(timers.tick(), TimerState::Off);
+        assert_eq!((timers.delay(), timers.sound()), (0, 0));
+        timers.set_sound(3);
+        assert_eq!(timers.tick(), TimerState::On);
+        assert_eq!(timers.sound(), 2);
+    }
+
+    #[cfg(any(feature = "atomic", feature = "critical-section"))]
+    #[test]
+    fn handle_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>(_: T) {}
+
+        static TIMERS: Timers = Timers::new();
+        TIMERS.set_sound(2);
+        let handle = TIMERS.handle();
+        assert_send_sync(handle);
+        assert_eq!(handle.tick(), TimerState::On);
+        assert_eq!(handle.sound(), 1);
+    }
+}
+
+/// Run with `RUSTFLAGS="--cfg loom" cargo test -p peach8 --lib --release loom`
+#[cfg(all(test, loom, feature = "atomic"))]
+mod loom_tests {
+    use super::*;
+    use loom::sync::Arc;
+    use loom::thread;
+
+    /// Interrupt ticking sound timer while FX18 stores into it
+    #[test]
+    fn tick_races_store() {
+        loom::model(|| {
+            let timers = Arc::new(Timers::new());
+            timers.set_sound(1);
+            let isr = {
+                let timers = timers.clone();
+                thread::spawn(move || timers.handle().tick())
+            };
+            timers.set_sound(5);
+            let state = isr.join().unwrap();
+            // either the store overwrote the tick, or the tick decremented it
+            match timers.sound() {
+                5 => assert_eq!(state, TimerState::Finished),
+                4 => assert_eq!(state, TimerState::On),
+                value => panic!("lost update, sound timer is {}", value),
+            }
+        });
+    }
+
+    /// Interrupt ticking timers while `tick_timers` ticks them too
+    #[test]
+    fn ticks_are_not_lost() {
+        loom::model(|| {
+            let timers = Arc::new(Timers::new());
+            timers.set_delay(3);
+            timers.set_sound(2);
+            let isr = {
+                let timers = timers.clone();
+                thread::spawn(move || timers.handle().tick())
+            };
+            let main = timers.tick();
+            let isr = isr.join().unwrap();
+            assert_eq!((timers.delay(), timers.sound()), (1, 0));
+            // sound stops exactly once
+            let mut states = [main, isr];
+            states.sort_by_key(|&s| s == TimerState::Finished);
+            assert_eq!(states, [TimerState::On, TimerState::Finished]);
+        });
+    }
+
+    /// FX07 reading delay timer while it's ticked
+    #[test]
+    fn load_sees_whole_values() {
+        loom::model(|| {
+            let timers = Arc::new(Timers::new());
+            timers.set_delay(0x80);
+            let isr = {
+                let timers = timers.clone();
+                thread::spawn(move || {
+                    timers.handle().tick();
+                })
+            };
+            let delay = timers.delay();
+            isr.join().unwrap();
+            assert!(delay == 0x80 || delay == 0x7F, "{:#04X}", delay);
+            assert_eq!(timers.delay(), 0x7F);
+        });
+    }
+}
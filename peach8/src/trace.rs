@@ -0,0 +1,448 @@
+//! Instruction execution tracing
+//!
+//! `Tracer` wraps a `Context` and emits one record per executed instruction
+//! to a `Sink`. Records are emitted either as text lines, eg. to compare the run
+//! with traces of other emulators, or in a compact binary form, suitable for
+//! streaming from `no_std` targets.
+//!
+//! Text line consists of `KEY:VALUE` fields separated by spaces, followed by
+//! disassembly of the instruction:
+//! ```text
+//! CYC:0 PC:0200 OP:6A02 V0:00 V1:00 (...) VF:00 I:0000 SP:00 DT:00 ST:00 ; LD VA, 0x02
+//! ```
+//! Which fields are emitted is configured with `Fields`. State of registers is
+//! captured before the instruction is executed.
+//!
+//! Traces are compared with `diff`, which takes into account only the fields
+//! present in both lines, so that traces of emulators logging different sets
+//! of fields can still be compared.
+
+use core::convert::TryFrom;
+use core::fmt::{self, Write};
+
+use heapless::{consts::U256, String};
+
+use crate::context::Context;
+use crate::frame::FrameView;
+use crate::opcode::OpCode;
+use crate::peach::State;
+use crate::storage::Storage;
+
+/// Size of the binary representation of `Record`
+pub const RECORD_SIZE: usize = 33;
+
+/// State of `Peach8` captured before execution of an instruction
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Record {
+    pub cycle: u64,
+    pub pc: u16,
+    pub opcode: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub sp: u8,
+    pub delay: u8,
+    pub sound: u8,
+}
+
+/// Fields emitted in text records
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Fields {
+    pub cycle: bool,
+    pub registers: bool,
+    pub index: bool,
+    pub stack_pointer: bool,
+    pub timers: bool,
+    pub disassembly: bool,
+}
+
+/// Format of emitted records
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// One line of text per record
+    Text(Fields),
+    /// `RECORD_SIZE` bytes per record, see `Record::to_bytes`
+    Binary,
+}
+
+/// Destination of the trace, eg. UART or a file
+pub trait Sink {
+    fn write(&mut self, bytes: &[u8]);
+}
+
+/// `Sink` writing to any `std::io::Write`, write errors are ignored
+#[cfg(feature = "std")]
+pub struct IoSink<W: std::io::Write>(pub W);
+
+#[cfg(feature = "std")]
+impl<W: std::io::Write> Sink for IoSink<W> {
+    fn write(&mut self, bytes: &[u8]) {
+        let _ = self.0.write_all(bytes);
+    }
+}
+
+impl Fields {
+    /// Emit every field
+    pub const fn all() -> Self {
+        Self {
+            cycle: true,
+            registers: true,
+            index: true,
+            stack_pointer: true,
+            timers: true,
+            disassembly: true,
+        }
+    }
+}
+
+impl Default for Fields {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl Record {
+    pub fn new(state: &State<'_>, opcode: u16) -> Self {
+        Self {
+            cycle: state.cycle,
+            pc: state.pc,
+            opcode,
+            v: *state.v,
+            i: state.i,
+            sp: state.stack.len() as u8,
+            delay: state.delay,
+            sound: state.sound,
+        }
+    }
+
+    /// Encode record in little endian, fields in order of declaration
+    pub fn to_bytes(&self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..8].copy_from_slice(&self.cycle.to_le_bytes());
+        bytes[8..10].copy_from_slice(&self.pc.to_le_bytes());
+        bytes[10..12].copy_from_slice(&self.opcode.to_le_bytes());
+        bytes[12..28].copy_from_slice(&self.v);
+        bytes[28..30].copy_from_slice(&self.i.to_le_bytes());
+        bytes[30] = self.sp;
+        bytes[31] = self.delay;
+        bytes[32] = self.sound;
+        bytes
+    }
+
+    /// Decode record encoded with `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, &'static str> {
+        if bytes.len() < RECORD_SIZE {
+            return Err("Trace record too short");
+        }
+        let mut cycle = [0; 8];
+        let mut v = [0; 16];
+        cycle.copy_from_slice(&bytes[0..8]);
+        v.copy_from_slice(&bytes[12..28]);
+        Ok(Self {
+            cycle: u64::from_le_bytes(cycle),
+            pc: u16::from_le_bytes([bytes[8], bytes[9]]),
+            opcode: u16::from_le_bytes([bytes[10], bytes[11]]),
+            v,
+            i: u16::from_le_bytes([bytes[28], bytes[29]]),
+            sp: bytes[30],
+            delay: bytes[31],
+            sound: bytes[32],
+        })
+    }
+
+    /// Write record as a line of text, without trailing newline
+    pub fn write_text<W: Write>(&self, fields: &Fields, w: &mut W) -> fmt::Result {
+        if fields.cycle {
+            write!(w, "CYC:{} ", self.cycle)?;
+        }
+        write!(w, "PC:{:04X} OP:{:04X}", self.pc, self.opcode)?;
+        if fields.registers {
+            for (n, v) in self.v.iter().enumerate() {
+                write!(w, " V{:X}:{:02X}", n, v)?;
+            }
+        }
+        if fields.index {
+            write!(w, " I:{:04X}", self.i)?;
+        }
+        if fields.stack_pointer {
+            write!(w, " SP:{:02X}", self.sp)?;
+        }
+        if fields.timers {
+            write!(w, " DT:{:02X} ST:{:02X}", self.delay, self.sound)?;
+        }
+        if fields.disassembly {
+            match OpCode::try_from(self.opcode) {
+                Ok(opcode) => write!(w, " ; {}", opcode)?,
+                Err(_) => write!(w, " ; DW 0x{:04X}", self.opcode)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// `Context` adapter emitting a trace record for each executed instruction
+pub struct Tracer<C, S> {
+    pub inner: C,
+    pub sink: S,
+    format: Format,
+}
+
+impl<C: Context, S: Sink> Tracer<C, S> {
+    pub fn new(inner: C, sink: S, format: Format) -> Self {
+        Self {
+            inner,
+            sink,
+            format,
+        }
+    }
+
+    /// Drop the tracer, releasing wrapped context and sink
+    pub fn release(self) -> (C, S) {
+        (self.inner, self.sink)
+    }
+}
+
+impl<C: Context, S: Sink> Context for Tracer<C, S> {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.inner.on_frame(frame);
+    }
+
+    fn sound_on(&mut self) {
+        self.inner.sound_on();
+    }
+
+    fn sound_off(&mut self) {
+        self.inner.sound_off();
+    }
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        let record = Record::new(state, opcode);
+        match self.format {
+            Format::Text(ref fields) => {
+                let mut line = String::<U256>::new();
+                // line fits in the buffer even with all fields enabled
+                let _ = record.write_text(fields, &mut line);
+                let _ = line.push('\n');
+                self.sink.write(line.as_bytes());
+            }
+            Format::Binary => self.sink.write(&record.to_bytes()),
+        }
+        self.inner.on_instruction(state, opcode);
+    }
+
+    fn on_vblank(&mut self) {
+        self.inner.on_vblank();
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        self.inner.storage()
+    }
+}
+
+/// First difference found between two traces
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Divergence<'a> {
+    /// Index of the line, counting from 0
+    pub line: usize,
+    /// Key of the first differing field, `None` if lines have no fields in
+    /// common, or one of the traces ended
+    pub field: Option<&'a str>,
+    pub lhs: Option<&'a str>,
+    pub rhs: Option<&'a str>,
+}
+
+fn fields(line: &str) -> impl Iterator<Item = (&str, &str)> {
+    line.split(';')
+        .next()
+        .unwrap_or("")
+        .split_whitespace()
+        .filter_map(|field| {
+            let mut kv = field.splitn(2, ':');
+            Some((kv.next()?, kv.next()?))
+        })
+}
+
+fn same_value(lhs: &str, rhs: &str) -> bool {
+    let lhs = lhs.trim_start_matches('0');
+    let rhs = rhs.trim_start_matches('0');
+    lhs.eq_ignore_ascii_case(rhs)
+}
+
+fn compare_lines<'a>(lhs: &'a str, rhs: &'a str) -> Result<(), Option<&'a str>> {
+    let mut common = false;
+    for (key, lvalue) in fields(lhs) {
+        if let Some((_, rvalue)) = fields(rhs).find(|(k, _)| k.eq_ignore_ascii_case(key)) {
+            common = true;
+            if !same_value(lvalue, rvalue) {
+                return Err(Some(key));
+            }
+        }
+    }
+    if common || lhs.trim() == rhs.trim() {
+        Ok(())
+    } else {
+        Err(None)
+    }
+}
+
+/// Find first divergence between two text traces
+///
+/// Values of fields are compared case insensitively, ignoring leading zeros.
+pub fn diff<'a, L, R>(lhs: L, rhs: R) -> Option<Divergence<'a>>
+where
+    L: IntoIterator<Item = &'a str>,
+    R: IntoIterator<Item = &'a str>,
+{
+    let mut lhs = lhs.into_iter();
+    let mut rhs = rhs.into_iter();
+    let mut line = 0;
+    loop {
+        match (lhs.next(), rhs.next()) {
+            (None, None) => return None,
+            (Some(l), Some(r)) => {
+                if let Err(field) = compare_lines(l, r) {
+                    return Some(Divergence {
+                        line,
+                        field,
+                        lhs: Some(l),
+                        rhs: Some(r),
+                    });
+                }
+            }
+            (l, r) => {
+                return Some(Divergence {
+                    line,
+                    field: None,
+                    lhs: l,
+                    rhs: r,
+                })
+            }
+        }
+        line += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate std;
+
+    use super::*;
+    use std::vec::Vec;
+
+    use crate::builder::Builder;
+    use crate::testing::TestingContext;
+
+    impl Sink for Vec<u8> {
+        fn write(&mut self, bytes: &[u8]) {
+            self.extend_from_slice(bytes);
+        }
+    }
+
+    const PROGRAM: &[u8] = &[
+        0x6A, 0x02, // LD VA, 0x02
+        0xA2, 0x10, // LD I, 0x210
+        0x22, 0x08, // CALL 0x208
+        0x12, 0x06, // JP 0x206
+        0x00, 0xEE, // RET
+    ];
+
+    fn run(format: Format, cycles: usize) -> Vec<u8> {
+        let ctx = Tracer::new(TestingContext::new(0), Vec::new(), format);
+        let mut chip = Builder::new()
+            .with_context(ctx)
+            .with_program(PROGRAM)
+            .build()
+            .unwrap();
+        for _ in 0..cycles {
+            chip.tick_chip().unwrap();
+        }
+        chip.release().release().1
+    }
+
+    #[test]
+    fn text_trace() {
+        let trace = run(Format::Text(Fields::all()), 4);
+        let trace = std::str::from_utf8(&trace).unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(
+            lines[0],
+            "CYC:0 PC:0200 OP:6A02 V0:00 V1:00 V2:00 V3:00 V4:00 V5:00 V6:00 V7:00 \
+             V8:00 V9:00 VA:00 VB:00 VC:00 VD:00 VE:00 VF:00 I:0000 SP:00 DT:00 ST:00 \
+             ; LD VA, 0x02"
+        );
+        assert!(lines[3].starts_with("CYC:3 PC:0208 OP:00EE"));
+        assert!(lines[3].contains(" VA:02 "));
+        assert!(lines[3].ends_with("I:0210 SP:01 DT:00 ST:00 ; RET"));
+
+        let fields = Fields {
+            registers: false,
+            disassembly: false,
+            ..Fields::all()
+        };
+        let trace = run(Format::Text(fields), 1);
+        assert_eq!(
+            std::str::from_utf8(&trace).unwrap(),
+            "CYC:0 PC:0200 OP:6A02 I:0000 SP:00 DT:00 ST:00\n"
+        );
+    }
+
+    #[test]
+    fn binary_trace() {
+        let trace = run(Format::Binary, 4);
+        assert_eq!(trace.len(), 4 * RECORD_SIZE);
+
+        let records: Vec<_> = trace
+            .chunks(RECORD_SIZE)
+            .map(|bytes| Record::from_bytes(bytes).unwrap())
+            .collect();
+        assert_eq!(records[1].cycle, 1);
+        assert_eq!(records[1].pc, 0x0202);
+        assert_eq!(records[1].opcode, 0xA210);
+        assert_eq!(records[1].v[0xA], 0x02);
+        assert_eq!(records[3].i, 0x0210);
+        assert_eq!(records[3].sp, 1);
+        assert_eq!(Record::from_bytes(&records[3].to_bytes()), Ok(records[3]));
+        assert_eq!(
+            Record::from_bytes(&[0; RECORD_SIZE - 1]),
+            Err("Trace record too short")
+        );
+    }
+
+    #[test]
+    fn diff_traces() {
+        let lhs = [
+            "CYC:0 PC:0200 OP:6A02 VA:00 ; LD VA, 0x02",
+            "CYC:1 PC:0202 OP:A210 VA:02 ; LD I, 0x210",
+        ];
+        let same = ["pc:200 op:6a02", "pc:202 op:a210 va:2 i:0"];
+        let diverged = ["PC:0200 OP:6A02 VA:00", "PC:0202 OP:A210 VA:03"];
+
+        assert_eq!(diff(lhs.iter().copied(), same.iter().copied()), None);
+        assert_eq!(
+            diff(lhs.iter().copied(), diverged.iter().copied()),
+            Some(Divergence {
+                line: 1,
+                field: Some("VA"),
+                lhs: Some(lhs[1]),
+                rhs: Some(diverged[1]),
+            })
+        );
+        assert_eq!(
+            diff(lhs.iter().copied(), same[..1].iter().copied()),
+            Some(Divergence {
+                line: 1,
+                field: None,
+                lhs: Some(lhs[1]),
+                rhs: None,
+            })
+        );
+    }
+}
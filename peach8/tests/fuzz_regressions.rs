@@ -0,0 +1,30 @@
+//! Inputs of `run` fuzz target, which crashed the interpreter
+//!
+//! Each file in `test-data/fuzz/run` is replayed by `testing::fuzz::run`, the
+//! same function the fuzz target calls.
+
+use std::fs;
+use std::path::Path;
+
+use peach8::testing::fuzz;
+
+#[test]
+fn fuzz_regressions() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test-data/fuzz/run");
+    let mut inputs: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .collect();
+    inputs.sort();
+    assert!(!inputs.is_empty());
+    for input in inputs {
+        println!("replaying {}", input.display());
+        let data = fs::read(&input).unwrap();
+        assert!(
+            fuzz::Input::parse(&data).is_some(),
+            "{} is truncated",
+            input.display()
+        );
+        fuzz::run(&data);
+    }
+}
@@ -0,0 +1,162 @@
+//! Golden-frame regression suite over games bundled in `roms/`
+//!
+//! Each ROM runs for `FRAMES` frames at 500Hz with timers at 60Hz, with the
+//! same scripted key presses. Frame is hashed at every checkpoint, and the
+//! last one is stored as ASCII art. Results are compared against files in
+//! `test-data/golden`, regenerate them after intended changes of behaviour:
+//! ```text
+//! BLESS=1 cargo test -p peach8 --test golden_frames
+//! ```
+
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use peach8::testing::{ImageMask, TestingContext, ToMask, VirtualClock};
+use peach8::{Builder, Context, Frame, FrameView, State};
+
+const CHIP_FREQ: u32 = 500;
+const TIMERS_FREQ: u32 = 60;
+const FRAMES: u64 = 600;
+const CHECKPOINT: u64 = 60;
+/// Key pressed in each checkpoint period, for 20 frames from its middle
+const KEYS: [u8; 10] = [0x5, 0x4, 0x6, 0x8, 0x2, 0x4, 0x6, 0x5, 0xA, 0x1];
+
+/// `TestingContext` keeping a copy of the last frame
+struct GoldenContext {
+    inner: TestingContext,
+    frame: Option<Frame>,
+}
+
+impl Context for GoldenContext {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.frame = Some(frame.copy_frame());
+    }
+
+    fn sound_on(&mut self) {}
+
+    fn sound_off(&mut self) {}
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.inner.get_keys()
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.inner.gen_random()
+    }
+
+    fn on_instruction(&mut self, state: &State<'_>, opcode: u16) {
+        self.inner.on_instruction(state, opcode);
+    }
+}
+
+/// Cycle at which given frame starts
+fn cycle_of(frame: u64) -> u64 {
+    frame * CHIP_FREQ as u64 / TIMERS_FREQ as u64
+}
+
+/// FNV-1a hash of frame's memory
+fn hash(frame: FrameView<'_>) -> u64 {
+    frame
+        .as_raw()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+}
+
+/// Run the ROM and describe frames at checkpoints
+fn run(name: &str, rom: &[u8]) -> String {
+    let script: Vec<_> = KEYS
+        .iter()
+        .enumerate()
+        .flat_map(|(n, &key)| {
+            let start = n as u64 * CHECKPOINT + CHECKPOINT / 2;
+            vec![
+                (cycle_of(start), key, true),
+                (cycle_of(start + 20), key, false),
+            ]
+        })
+        .collect();
+    let ctx = GoldenContext {
+        inner: TestingContext::with_script(0, &script),
+        frame: None,
+    };
+    let mut chip = Builder::new()
+        .with_context(ctx)
+        .with_program(rom)
+        .build()
+        .unwrap();
+
+    let mut report = String::new();
+    writeln!(
+        report,
+        "# {}: {} frames at {}Hz, timers at {}Hz",
+        name, FRAMES, CHIP_FREQ, TIMERS_FREQ
+    )
+    .unwrap();
+    let mut clock = VirtualClock::new(CHIP_FREQ, TIMERS_FREQ);
+    let mut mask = ImageMask::new();
+    for frame in 1..=FRAMES {
+        if let Err(error) = clock.run_frames(&mut chip, 1) {
+            writeln!(report, "frame {}: error: {}", frame, error).unwrap();
+            return report;
+        }
+        if frame % CHECKPOINT == 0 {
+            let view = chip.ctx.frame.as_ref().unwrap().view();
+            writeln!(report, "frame {}: {:016x}", frame, hash(view)).unwrap();
+            mask = view.to_mask();
+        }
+    }
+    writeln!(report, "last checkpoint:{:?}", mask).unwrap();
+    report
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("test-data/golden")
+        .join(name)
+        .with_extension("txt")
+}
+
+#[test]
+fn golden_frames() {
+    let bless = std::env::var_os("BLESS").is_some_and(|v| v == "1");
+    let roms = Path::new(env!("CARGO_MANIFEST_DIR")).join("../roms");
+    let mut names: Vec<_> = fs::read_dir(&roms)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names.len(), 23);
+
+    let mut failures = vec![];
+    for name in &names {
+        let report = run(name, &fs::read(roms.join(name)).unwrap());
+        let path = golden_path(name);
+        if bless {
+            fs::write(&path, &report).unwrap();
+            continue;
+        }
+        match fs::read_to_string(&path) {
+            Ok(golden) if golden == report => (),
+            Ok(golden) => {
+                let line = golden
+                    .lines()
+                    .zip(report.lines())
+                    .find(|(lhs, rhs)| lhs != rhs)
+                    .map_or("", |(lhs, _)| lhs)
+                    .to_owned();
+                failures.push(format!("{}: differs at `{}`\n{}", name, line, report));
+            }
+            Err(_) => failures.push(format!("{}: missing {}", name, path.display())),
+        }
+    }
+    assert!(
+        failures.is_empty(),
+        "{} of {} ROMs differ from golden files, rerun with BLESS=1 if intended:\n\n{}",
+        failures.len(),
+        names.len(),
+        failures.join("\n")
+    );
+}
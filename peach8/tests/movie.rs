@@ -0,0 +1,87 @@
+//! Replaying movies of bundled games reproduces their frames
+
+use std::fs;
+use std::path::Path;
+
+use peach8::movie::{Recorder, Replay, Sampling};
+use peach8::storage::RomHash;
+use peach8::testing::{RecordingContext, TestingContext, VirtualClock};
+use peach8::trace::IoSink;
+use peach8::{Builder, Context, Frame};
+
+const FRAMES: u64 = 300;
+
+/// Key presses as (cycle, key, pressed)
+const SCRIPT: [(u64, u8, bool); 6] = [
+    (400, 0x4, true),
+    (700, 0x4, false),
+    (1000, 0x6, true),
+    (1500, 0x6, false),
+    (2000, 0x5, true),
+    (2100, 0x5, false),
+];
+
+fn rom(name: &str) -> Vec<u8> {
+    fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../roms")
+            .join(name),
+    )
+    .unwrap()
+}
+
+/// Run the ROM for `FRAMES` frames, returning its distinct frames with cycles
+fn run<C: Context>(rom: &[u8], ctx: C) -> (Vec<(u64, Frame)>, C) {
+    let mut chip = Builder::new()
+        .with_context(RecordingContext::new(ctx))
+        .with_program(rom)
+        .build()
+        .unwrap();
+    VirtualClock::new(500, 60)
+        .run_frames(&mut chip, FRAMES)
+        .unwrap();
+    let recording = chip.release();
+    let frames = recording
+        .changed_frames()
+        .map(|(cycle, frame)| (cycle, frame.clone()))
+        .collect();
+    (frames, recording.release())
+}
+
+fn check(name: &str, sampling: Sampling) {
+    let rom = rom(name);
+    let recorder = Recorder::new(
+        TestingContext::with_script(1, &SCRIPT),
+        IoSink(Vec::new()),
+        RomHash::of(&rom),
+        sampling,
+    );
+    let (recorded, recorder) = run(&rom, recorder);
+    let (_, IoSink(movie)) = recorder.release();
+
+    // another seed and no keys, input comes only from the movie
+    let replay = Replay::new(TestingContext::new(2), &movie, RomHash::of(&rom)).unwrap();
+    let (replayed, replay) = run(&rom, replay);
+    assert_eq!(replay.error(), None, "{}", name);
+    assert!(replay.is_finished(), "{}", name);
+    assert!(recorded.len() > 1, "{} drew nothing", name);
+    assert!(recorded == replayed, "{} diverged", name);
+
+    // without the movie emulation differs
+    let (unrecorded, _) = run(&rom, TestingContext::new(2));
+    assert!(recorded != unrecorded, "{} doesn't depend on input", name);
+}
+
+#[test]
+fn replay_reproduces_frames_per_tick() {
+    for name in &["BRIX", "PONG", "TETRIS"] {
+        check(name, Sampling::Tick);
+    }
+}
+
+#[test]
+fn replay_reproduces_frames_per_frame() {
+    for name in &["BRIX", "PONG", "TETRIS"] {
+        check(name, Sampling::Frame);
+    }
+}
@@ -0,0 +1,98 @@
+//! Runs of bundled games edited in a `Session` replay as movies
+
+use std::fs;
+use std::path::Path;
+
+use peach8::movie::Replay;
+use peach8::storage::RomHash;
+use peach8::tas::Session;
+use peach8::testing::{RecordingContext, TestingContext, VirtualClock};
+use peach8::{Builder, Frame};
+
+const FRAMES: usize = 300;
+
+fn rom(name: &str) -> Vec<u8> {
+    fs::read(
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("../roms")
+            .join(name),
+    )
+    .unwrap()
+}
+
+/// Keys of a frame, left and right in turns with rotation in between
+fn keys(frame: usize) -> u16 {
+    match frame / 20 % 4 {
+        0 => 1 << 0x4,
+        2 => 1 << 0x6,
+        _ if frame % 40 == 30 => 1 << 0x5,
+        _ => 0,
+    }
+}
+
+/// Replay the movie, returning the last frame
+fn replay(rom: &[u8], movie: &[u8]) -> Frame {
+    let replay = Replay::new(TestingContext::new(0), movie, RomHash::of(rom)).unwrap();
+    let mut chip = Builder::new()
+        .with_context(RecordingContext::new(replay))
+        .with_program(rom)
+        .build()
+        .unwrap();
+    VirtualClock::new(500, 60)
+        .run_frames(&mut chip, FRAMES as u64)
+        .unwrap();
+    let recording = chip.release();
+    let (_, last) = recording.frames().last().unwrap();
+    let last = last.clone();
+    let replay = recording.release();
+    assert_eq!(replay.error(), None);
+    assert!(replay.is_finished());
+    last
+}
+
+fn check(name: &str) {
+    let rom = rom(name);
+    let mut session = Session::new(TestingContext::new(0), &rom, 7).unwrap();
+    session.run(FRAMES / 3).unwrap();
+    let save = session.save().unwrap();
+
+    // a branch without keys, then another one with them
+    session.run(FRAMES / 3).unwrap();
+    let idle = session.save().unwrap();
+    session.load(&save).unwrap();
+    (save.frame()..FRAMES).for_each(|frame| session.set_input(frame, keys(frame)));
+    session.seek(FRAMES).unwrap();
+    let lags = &session.lags()[save.frame()..];
+    assert!(lags.contains(&false), "{} never polled keys", name);
+    assert!(lags.contains(&true), "{} has no lag frames", name);
+
+    let movie = session.export().unwrap();
+    assert!(
+        replay(&rom, &movie).view() == session.screen(),
+        "{} diverged",
+        name
+    );
+
+    // the branch without keys looks different
+    session.seek(idle.frame()).unwrap();
+    assert!(
+        session.screen() != idle.snapshot().frame(),
+        "{} ignores keys",
+        name
+    );
+
+    let mut imported = Session::from_movie(TestingContext::new(0), &rom, &movie, 0).unwrap();
+    assert_eq!(imported.inputs(), session.inputs());
+    imported.seek(FRAMES).unwrap();
+    assert!(imported.screen() == replay(&rom, &movie).view());
+}
+
+#[test]
+fn tetris_run_replays() {
+    check("TETRIS");
+}
+
+#[test]
+fn brix_run_replays() {
+    check("BRIX");
+}
@@ -1,91 +1,24 @@
-use std::sync::{
-    atomic::{AtomicUsize, Ordering},
-    Arc, Mutex,
-};
-use std::time::{Duration, Instant};
+use std::time::Duration;
 
-use crossbeam_utils::thread;
+use peach8::assert_eq_2d;
+use peach8::testing::{ImageMask, TestingContext, Tick, VirtualClock};
+use peach8::{Builder, Context, Peach8};
 
-use peach8::{Builder, Context, FrameView};
-
-macro_rules! schedule_for {
-    ($scope:expr, $f:expr, $freq:expr, $timeout:expr) => {{
-        let started = Instant::now();
-        let period = Duration::from_nanos(1_000_000_000u64 / $freq);
-        let mut previous = started;
-        $scope.spawn(move |_| loop {
-            let now = Instant::now();
-            if now.duration_since(started) >= $timeout {
-                break;
-            }
-            if now.duration_since(previous) >= period {
-                $f();
-                previous = now;
-            }
-        })
-    }};
+fn run_for<C: Context>(chip: &mut Peach8<C>, duration: Duration) {
+    VirtualClock::default().run_for(chip, duration).unwrap();
 }
 
-#[ignore]
 #[test]
 fn scheduler_tests() {
-    let counter = Arc::new(AtomicUsize::new(0));
-    thread::scope(|s| {
-        let counter_cln = Arc::clone(&counter);
-        schedule_for!(
-            s,
-            || {
-                counter_cln.fetch_add(1, Ordering::Relaxed);
-            },
-            10,
-            Duration::from_secs(3)
-        );
-    })
-    .unwrap();
-    assert_eq!(counter.load(Ordering::Relaxed), 29);
-}
-
-struct TestingContext(Vec<String>);
-
-impl TestingContext {
-    fn new() -> Self {
-        let mut row = String::new();
-        for _ in 0..64 {
-            row.push('.');
+    let mut clock = VirtualClock::new(10, 1);
+    let mut ticks = 0;
+    while clock.elapsed() < Duration::from_secs(3) {
+        if clock.advance() == Tick::Chip {
+            ticks += 1;
         }
-        let mut inner = vec![];
-        inner.resize_with(32, || row.clone());
-        Self(inner)
-    }
-
-    fn formatted(&self) -> String {
-        self.0.join("\n") + "\n"
-    }
-}
-
-impl Context for TestingContext {
-    fn on_frame(&mut self, frame: FrameView<'_>) {
-        frame
-            .iter_rows_as_bitslices()
-            .zip(self.0.iter_mut())
-            .for_each(|(frame_row, ctx_row)| {
-                frame_row.iter().enumerate().for_each(|(n, &frame_px)| {
-                    ctx_row.replace_range(n..n + 1, if frame_px { "#" } else { "." });
-                });
-            });
-    }
-
-    fn sound_on(&mut self) {}
-
-    fn sound_off(&mut self) {}
-
-    fn get_keys(&mut self) -> [bool; 16] {
-        [false; 16]
-    }
-
-    fn gen_random(&mut self) -> u8 {
-        rand::random::<u8>()
     }
+    assert_eq!(ticks, 30);
+    assert_eq!(clock.timers_ticks(), 2);
 }
 
 /// Not working currently as using modern opcode's behaviours. For future impl of compatibility
@@ -119,33 +52,16 @@ fn rom_skosulor_c8int() {
     let _ = env_logger::builder().is_test(true).try_init();
 
     let rom = include_bytes!("../test-data/skosulor_c8int/test.c8");
-    let chip = Builder::new()
-        .with_context(TestingContext::new())
+    let mut chip = Builder::new()
+        .with_context(TestingContext::new(0))
         .with_program(rom)
         .build()
         .unwrap();
-    let chip = Arc::new(Mutex::new(chip));
-    let chip_timers = Arc::clone(&chip);
-    let chip_test = Arc::clone(&chip);
-    thread::scope(|s| {
-        schedule_for!(
-            s,
-            || chip.lock().unwrap().tick_chip().unwrap(),
-            500,
-            Duration::from_millis(300)
-        );
-        schedule_for!(
-            s,
-            || chip_timers.lock().unwrap().tick_timers(),
-            60,
-            Duration::from_millis(300)
-        );
-    })
-    .unwrap();
+    run_for(&mut chip, Duration::from_millis(300));
 
-    let lhs = chip_test.lock().unwrap().ctx.formatted();
-    let rhs = include_str!("../test-data/context/empty_mask");
-    assert_eq!(&lhs, rhs, "\nlhs:\n{}\n\nrhs:\n{}", lhs, rhs,);
+    let lhs = *chip.ctx.get_frame().unwrap();
+    let rhs = ImageMask::parse(include_str!("../test-data/context/empty_mask")).unwrap();
+    assert_eq_2d!(x_range: .., y_range: ..; lhs, rhs);
 }
 
 #[test]
@@ -153,31 +69,17 @@ fn rom_corax89_chip8_test_rom() {
     let _ = env_logger::builder().is_test(true).try_init();
 
     let rom = include_bytes!("../test-data/corax89_chip8-test-rom/test_opcode.ch8");
-    let chip = Builder::new()
-        .with_context(TestingContext::new())
+    let mut chip = Builder::new()
+        .with_context(TestingContext::new(0))
         .with_program(rom)
         .build()
         .unwrap();
-    let chip = Arc::new(Mutex::new(chip));
-    let chip_timers = Arc::clone(&chip);
-    let chip_test = Arc::clone(&chip);
-    thread::scope(|s| {
-        schedule_for!(
-            s,
-            || chip.lock().unwrap().tick_chip().unwrap(),
-            500,
-            Duration::from_millis(700)
-        );
-        schedule_for!(
-            s,
-            || chip_timers.lock().unwrap().tick_timers(),
-            60,
-            Duration::from_millis(700)
-        );
-    })
-    .unwrap();
+    run_for(&mut chip, Duration::from_millis(700));
 
-    let lhs = chip_test.lock().unwrap().ctx.formatted();
-    let rhs = include_str!("../test-data/corax89_chip8-test-rom/expected_result");
-    assert_eq!(&lhs, rhs, "\nlhs:\n{}\n\nrhs:\n{}", lhs, rhs,);
+    let lhs = *chip.ctx.get_frame().unwrap();
+    let rhs = ImageMask::parse(include_str!(
+        "../test-data/corax89_chip8-test-rom/expected_result"
+    ))
+    .unwrap();
+    assert_eq_2d!(x_range: .., y_range: ..; lhs, rhs);
 }
@@ -23,7 +23,7 @@ pub trait ClocksExt {
 impl ClocksExt for rcc::Clocks {
     fn set_tpiu_async_cpr(self, baud_rate: MegaHertz) -> Self {
         let tpiu_async_presc = self.hclk().0 / (baud_rate.0 * 1_000_000) - 1;
-        unsafe { (*pac::TPIU::ptr()).acpr.write(tpiu_async_presc) }
+        unsafe { (*pac::TPIU::PTR).acpr.write(tpiu_async_presc) }
         trace!("HCLK set to: {}hz", self.hclk().0);
         trace!("setting tpiu baud rate to: {}mhz", baud_rate.0);
         trace!("setting async clock prescaller: {}", tpiu_async_presc);
@@ -19,7 +19,7 @@ pub fn init_tim3_pwm_on_pb5(
     clocks: rcc::Clocks,
 ) -> PwmChannel<TIM3_CH2, WithPins> {
     info!("configuring timer3 in pwm mode");
-    let resolution = core::u16::MAX;
+    let resolution = u16::MAX;
     debug!("resolution: {}, frequency: {}hz", resolution, freq.0);
     debug!("using channels: 1");
     let (_, tim3_ch2, ..) = pwm::tim3(tim3, resolution, freq, &clocks);
@@ -0,0 +1,523 @@
+//! Reference model of Chip-8 for differential testing of `peach8`
+//!
+//! Written from the specification, not from `peach8` sources, and kept
+//! deliberately naive: each step decodes an instruction by its nibbles and
+//! applies it to plain arrays, one pixel at a time. Behaviours that differ
+//! between interpreters are selected with `Quirks`.
+//!
+//! The specification doesn't say what happens at limits of the address space.
+//! There the model follows `peach8` policy of stopping with a `Fault`: jumps
+//! into the interpreter's area below 0x200, and accesses or increments of PC
+//! and I beyond 4KiB of memory.
+
+pub const MEMORY_SIZE: usize = 4096;
+pub const PROGRAM_START: u16 = 0x200;
+pub const FONT_START: u16 = 0x050;
+pub const STACK_SIZE: usize = 64;
+pub const FLAGS: usize = 8;
+pub const WIDTH: usize = 64;
+pub const HEIGHT: usize = 32;
+
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// Behaviours that differ between interpreters
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Quirks {
+    /// 8XY6 and 8XYE shift VY into VX (COSMAC VIP), instead of shifting VX
+    pub shift_vy: bool,
+    /// 8XY6 and 8XYE store the result in VY as well (`peach8`)
+    pub shift_store_vy: bool,
+    /// FX55 and FX65 leave I increased by X + 1 (COSMAC VIP)
+    pub increment_i: bool,
+    /// BNNN jumps to XNN + VX (SUPER-CHIP), instead of NNN + V0
+    pub jump_vx: bool,
+    /// 8XY1, 8XY2 and 8XY3 reset VF to 0 (COSMAC VIP)
+    pub vf_reset: bool,
+    /// Sprites are clipped at edges of the screen, instead of wrapping around
+    pub clip: bool,
+    /// FX75 and FX85 save V0 to VX into RPL user flags and load them back
+    /// (SUPER-CHIP), instead of being undefined
+    pub rpl_flags: bool,
+}
+
+impl Quirks {
+    /// Behaviours implemented by `peach8`
+    pub const PEACH8: Self = Self {
+        shift_vy: true,
+        shift_store_vy: true,
+        increment_i: true,
+        jump_vx: false,
+        vf_reset: false,
+        clip: true,
+        rpl_flags: true,
+    };
+
+    /// Behaviours of the original interpreter of COSMAC VIP
+    pub const COSMAC_VIP: Self = Self {
+        shift_vy: true,
+        shift_store_vy: false,
+        increment_i: true,
+        jump_vx: false,
+        vf_reset: true,
+        clip: true,
+        rpl_flags: false,
+    };
+
+    /// Behaviours of SUPER-CHIP 1.1 in low resolution
+    pub const SUPER_CHIP: Self = Self {
+        shift_vy: false,
+        shift_store_vy: false,
+        increment_i: false,
+        jump_vx: true,
+        vf_reset: false,
+        clip: true,
+        rpl_flags: true,
+    };
+
+    /// Every combination of quirks
+    pub fn all() -> impl Iterator<Item = Self> {
+        (0..128u8).map(|bits| Self {
+            shift_vy: bits & 1 != 0,
+            shift_store_vy: bits & 2 != 0,
+            increment_i: bits & 4 != 0,
+            jump_vx: bits & 8 != 0,
+            vf_reset: bits & 16 != 0,
+            clip: bits & 32 != 0,
+            rpl_flags: bits & 64 != 0,
+        })
+    }
+}
+
+/// Reason why the machine stopped
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Fault {
+    /// Instruction isn't defined
+    UnknownOpcode(u16),
+    /// 0NNN, machine code can't be executed
+    MachineCode(u16),
+    /// Jump or call to the interpreter's area
+    ProtectedJump(u16),
+    /// 00EE without matching 2NNN
+    StackUnderflow,
+    /// 2NNN with full stack
+    StackOverflow,
+    /// PC or I beyond the address space
+    OutOfMemory,
+    /// FX75 or FX85 with X beyond RPL user flags
+    UserFlags(u16),
+}
+
+/// State of the machine
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Machine {
+    pub quirks: Quirks,
+    pub memory: Vec<u8>,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub pc: u16,
+    pub stack: Vec<u16>,
+    pub delay: u8,
+    pub sound: u8,
+    /// Pixels, indexed by row and column
+    pub screen: [[bool; WIDTH]; HEIGHT],
+    /// Keys held during the current step
+    pub keys: [bool; 16],
+    /// Keys held during the previous step
+    pub previous_keys: [bool; 16],
+    /// RPL user flags, which outlive the program on SUPER-CHIP
+    pub flags: [u8; FLAGS],
+}
+
+impl Machine {
+    /// Machine with font and given program loaded, truncated to fit memory
+    pub fn new(program: &[u8], quirks: Quirks) -> Self {
+        let mut memory = vec![0; MEMORY_SIZE];
+        for (n, &byte) in FONT.iter().enumerate() {
+            memory[FONT_START as usize + n] = byte;
+        }
+        for (n, &byte) in program.iter().enumerate() {
+            if PROGRAM_START as usize + n < MEMORY_SIZE {
+                memory[PROGRAM_START as usize + n] = byte;
+            }
+        }
+        Self {
+            quirks,
+            memory,
+            v: [0; 16],
+            i: 0,
+            pc: PROGRAM_START,
+            stack: Vec::new(),
+            delay: 0,
+            sound: 0,
+            screen: [[false; WIDTH]; HEIGHT],
+            keys: [false; 16],
+            previous_keys: [false; 16],
+            flags: [0; FLAGS],
+        }
+    }
+
+    /// Decrease timers, at 60Hz
+    pub fn tick_timers(&mut self) {
+        if self.delay > 0 {
+            self.delay -= 1;
+        }
+        if self.sound > 0 {
+            self.sound -= 1;
+        }
+    }
+
+    /// Read keys, then fetch and execute one instruction
+    ///
+    /// `random` is called once for each random number needed by CXNN.
+    pub fn step(&mut self, keys: [bool; 16], random: impl FnOnce() -> u8) -> Result<(), Fault> {
+        self.previous_keys = self.keys;
+        self.keys = keys;
+
+        let pc = self.pc as usize;
+        if pc + 1 >= MEMORY_SIZE {
+            return Err(Fault::OutOfMemory);
+        }
+        let opcode = (self.memory[pc] as u16) << 8 | self.memory[pc + 1] as u16;
+
+        let kind = opcode >> 12;
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let n = opcode & 0xF;
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        match kind {
+            0x0 if opcode == 0x00E0 => {
+                self.screen = [[false; WIDTH]; HEIGHT];
+                self.next()
+            }
+            0x0 if opcode == 0x00EE => {
+                self.pc = self.stack.pop().ok_or(Fault::StackUnderflow)?;
+                self.next()
+            }
+            0x0 => Err(Fault::MachineCode(nnn)),
+            0x1 => self.jump(nnn as usize),
+            0x2 => {
+                if nnn < PROGRAM_START {
+                    return Err(Fault::ProtectedJump(nnn));
+                }
+                if self.stack.len() == STACK_SIZE {
+                    return Err(Fault::StackOverflow);
+                }
+                self.stack.push(self.pc);
+                self.pc = nnn;
+                Ok(())
+            }
+            0x3 => self.skip_if(self.v[x] == nn),
+            0x4 => self.skip_if(self.v[x] != nn),
+            0x5 if n == 0 => self.skip_if(self.v[x] == self.v[y]),
+            0x6 => {
+                self.v[x] = nn;
+                self.next()
+            }
+            0x7 => {
+                self.v[x] = self.v[x].wrapping_add(nn);
+                self.next()
+            }
+            0x8 => {
+                self.arithmetic(opcode, x, y, n)?;
+                self.next()
+            }
+            0x9 if n == 0 => self.skip_if(self.v[x] != self.v[y]),
+            0xA => {
+                self.i = nnn;
+                self.next()
+            }
+            0xB if self.quirks.jump_vx => self.jump(nnn as usize + self.v[x] as usize),
+            0xB => self.jump(nnn as usize + self.v[0] as usize),
+            0xC => {
+                self.v[x] = random() & nn;
+                self.next()
+            }
+            0xD => {
+                self.draw(x, y, n as usize)?;
+                self.next()
+            }
+            0xE if nn == 0x9E => self.skip_if(self.is_held(self.v[x])),
+            0xE if nn == 0xA1 => self.skip_if(!self.is_held(self.v[x])),
+            0xF => self.misc(opcode, x, nn),
+            _ => Err(Fault::UnknownOpcode(opcode)),
+        }
+    }
+
+    /// Move to the next instruction
+    fn next(&mut self) -> Result<(), Fault> {
+        if self.pc as usize + 2 > MEMORY_SIZE {
+            return Err(Fault::OutOfMemory);
+        }
+        self.pc += 2;
+        Ok(())
+    }
+
+    fn skip_if(&mut self, condition: bool) -> Result<(), Fault> {
+        if condition {
+            self.next()?;
+        }
+        self.next()
+    }
+
+    fn jump(&mut self, address: usize) -> Result<(), Fault> {
+        if address < PROGRAM_START as usize {
+            Err(Fault::ProtectedJump(address as u16))
+        } else if address >= MEMORY_SIZE {
+            Err(Fault::OutOfMemory)
+        } else {
+            self.pc = address as u16;
+            Ok(())
+        }
+    }
+
+    fn is_held(&self, key: u8) -> bool {
+        key < 16 && self.keys[key as usize]
+    }
+
+    /// 8XYN instructions
+    fn arithmetic(&mut self, opcode: u16, x: usize, y: usize, n: u16) -> Result<(), Fault> {
+        let (vx, vy) = (self.v[x], self.v[y]);
+        let (result, flag) = match n {
+            0x0 => (vy, None),
+            0x1 => (vx | vy, if self.quirks.vf_reset { Some(0) } else { None }),
+            0x2 => (vx & vy, if self.quirks.vf_reset { Some(0) } else { None }),
+            0x3 => (vx ^ vy, if self.quirks.vf_reset { Some(0) } else { None }),
+            0x4 => {
+                let sum = vx as u16 + vy as u16;
+                (sum as u8, Some(if sum > 0xFF { 1 } else { 0 }))
+            }
+            0x5 => (vx.wrapping_sub(vy), Some(if vx >= vy { 1 } else { 0 })),
+            0x6 => {
+                let source = if self.quirks.shift_vy { vy } else { vx };
+                (source >> 1, Some(source & 1))
+            }
+            0x7 => (vy.wrapping_sub(vx), Some(if vy >= vx { 1 } else { 0 })),
+            0xE => {
+                let source = if self.quirks.shift_vy { vy } else { vx };
+                (source << 1, Some(source >> 7))
+            }
+            _ => return Err(Fault::UnknownOpcode(opcode)),
+        };
+        self.v[x] = result;
+        if self.quirks.shift_store_vy && (n == 0x6 || n == 0xE) {
+            self.v[y] = result;
+        }
+        // flag is written last, so it wins when X is F
+        if let Some(flag) = flag {
+            self.v[0xF] = flag;
+        }
+        Ok(())
+    }
+
+    /// DXYN, XOR sprite of N rows from I onto the screen
+    fn draw(&mut self, x: usize, y: usize, rows: usize) -> Result<(), Fault> {
+        let start = self.i as usize;
+        if start + rows > MEMORY_SIZE {
+            return Err(Fault::OutOfMemory);
+        }
+        let left = self.v[x] as usize % WIDTH;
+        let top = self.v[y] as usize % HEIGHT;
+        let mut collision = false;
+        for row in 0..rows {
+            let sprite = self.memory[start + row];
+            for column in 0..8 {
+                if sprite & (0x80 >> column) == 0 {
+                    continue;
+                }
+                let (mut px, mut py) = (left + column, top + row);
+                if self.quirks.clip && (px >= WIDTH || py >= HEIGHT) {
+                    continue;
+                }
+                px %= WIDTH;
+                py %= HEIGHT;
+                if self.screen[py][px] {
+                    collision = true;
+                }
+                self.screen[py][px] = !self.screen[py][px];
+            }
+        }
+        self.v[0xF] = if collision { 1 } else { 0 };
+        Ok(())
+    }
+
+    /// FXNN instructions
+    fn misc(&mut self, opcode: u16, x: usize, nn: u8) -> Result<(), Fault> {
+        match nn {
+            0x07 => self.v[x] = self.delay,
+            0x0A => {
+                // wait until a key is pressed and released
+                let released = (0..16).find(|&k| self.previous_keys[k] && !self.keys[k]);
+                match released {
+                    Some(key) => self.v[x] = key as u8,
+                    None => return Ok(()),
+                }
+            }
+            0x15 => self.delay = self.v[x],
+            0x18 => self.sound = self.v[x],
+            0x1E => {
+                let i = self.i as usize + self.v[x] as usize;
+                if i >= MEMORY_SIZE {
+                    return Err(Fault::OutOfMemory);
+                }
+                self.i = i as u16;
+            }
+            0x29 => self.i = FONT_START + (self.v[x] as u16 & 0xF) * 5,
+            0x33 => {
+                let i = self.i as usize;
+                if i + 2 >= MEMORY_SIZE {
+                    return Err(Fault::OutOfMemory);
+                }
+                self.memory[i] = self.v[x] / 100;
+                self.memory[i + 1] = self.v[x] / 10 % 10;
+                self.memory[i + 2] = self.v[x] % 10;
+            }
+            0x55 | 0x65 => {
+                let i = self.i as usize;
+                if i + x >= MEMORY_SIZE {
+                    return Err(Fault::OutOfMemory);
+                }
+                for register in 0..=x {
+                    if nn == 0x55 {
+                        self.memory[i + register] = self.v[register];
+                    } else {
+                        self.v[register] = self.memory[i + register];
+                    }
+                }
+                if self.quirks.increment_i {
+                    self.i += x as u16 + 1;
+                }
+            }
+            0x75 | 0x85 if self.quirks.rpl_flags => {
+                if x >= FLAGS {
+                    return Err(Fault::UserFlags(opcode));
+                }
+                for register in 0..=x {
+                    if nn == 0x75 {
+                        self.flags[register] = self.v[register];
+                    } else {
+                        self.v[register] = self.flags[register];
+                    }
+                }
+            }
+            _ => return Err(Fault::UnknownOpcode(opcode)),
+        }
+        self.next()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(program: &[u8], quirks: Quirks, steps: usize) -> Machine {
+        let mut machine = Machine::new(program, quirks);
+        for _ in 0..steps {
+            machine.step([false; 16], || 0xFF).unwrap();
+        }
+        machine
+    }
+
+    #[test]
+    fn arithmetic() {
+        // V0 = 0xF0, V1 = 0x20, V0 += V1, V2 = V1 - V0
+        let machine = run(
+            &[0x60, 0xF0, 0x61, 0x20, 0x80, 0x14, 0x82, 0x07],
+            Quirks::PEACH8,
+            4,
+        );
+        assert_eq!(machine.v[..3], [0x10, 0x20, 0x10]);
+        assert_eq!(machine.v[0xF], 1);
+        assert_eq!(machine.pc, 0x208);
+    }
+
+    #[test]
+    fn quirks() {
+        // V0 = 3, V1 = 0x81, V0 = V1 << 1
+        let program = [0x60, 0x03, 0x61, 0x81, 0x80, 0x1E];
+        let shift_vy = run(&program, Quirks::PEACH8, 3);
+        assert_eq!((shift_vy.v[0], shift_vy.v[0xF]), (0x02, 1));
+        let quirks = Quirks {
+            shift_vy: false,
+            ..Quirks::PEACH8
+        };
+        let shift_vx = run(&program, quirks, 3);
+        assert_eq!((shift_vx.v[0], shift_vx.v[0xF]), (0x06, 0));
+
+        assert_eq!(Quirks::all().count(), 128);
+        assert!(Quirks::all().any(|q| q == Quirks::PEACH8));
+    }
+
+    #[test]
+    fn draw_clips_or_wraps() {
+        // I = font of 0, V0 = 62, draw 5 rows at (62, 30)
+        let program = [0xA0, 0x50, 0x60, 0x3E, 0x61, 0x1E, 0xD0, 0x15];
+        let clipped = run(&program, Quirks::PEACH8, 4);
+        assert!(clipped.screen[30][63] && clipped.screen[31][62]);
+        assert!(!clipped.screen[30][0] && !clipped.screen[0][1]);
+        let wrapped = run(
+            &program,
+            Quirks {
+                clip: false,
+                ..Quirks::PEACH8
+            },
+            4,
+        );
+        assert!(wrapped.screen[30][0] && wrapped.screen[0][1]);
+    }
+
+    #[test]
+    fn faults() {
+        let mut machine = Machine::new(&[0x10, 0x00], Quirks::PEACH8);
+        assert_eq!(
+            machine.step([false; 16], || 0),
+            Err(Fault::ProtectedJump(0))
+        );
+        let mut machine = Machine::new(&[0x00, 0xEE], Quirks::PEACH8);
+        assert_eq!(machine.step([false; 16], || 0), Err(Fault::StackUnderflow));
+        let mut machine = Machine::new(&[0x5A, 0xB1], Quirks::PEACH8);
+        assert_eq!(
+            machine.step([false; 16], || 0),
+            Err(Fault::UnknownOpcode(0x5AB1))
+        );
+        let mut machine = Machine::new(&[0xF8, 0x75], Quirks::PEACH8);
+        assert_eq!(
+            machine.step([false; 16], || 0),
+            Err(Fault::UserFlags(0xF875))
+        );
+        let mut machine = Machine::new(&[0xF0, 0x75], Quirks::COSMAC_VIP);
+        assert_eq!(
+            machine.step([false; 16], || 0),
+            Err(Fault::UnknownOpcode(0xF075))
+        );
+    }
+
+    #[test]
+    fn rpl_flags() {
+        // V0 = 1, V1 = 2, save V0..V1, V0 = 0, V1 = 0, load V0
+        let program = [
+            0x60, 0x01, 0x61, 0x02, 0xF1, 0x75, 0x60, 0x00, 0x61, 0x00, 0xF0, 0x85,
+        ];
+        let machine = run(&program, Quirks::PEACH8, 6);
+        assert_eq!(machine.flags[..3], [1, 2, 0]);
+        assert_eq!(machine.v[..2], [1, 0]);
+    }
+}
@@ -0,0 +1,281 @@
+//! Differential test of `Peach8` against the reference model
+//!
+//! Random programs run on both interpreters with the same keys, random numbers
+//! and ticks of timers. A prelude of 6XNN, ANNN, FX15 and FX18 instructions
+//! gives registers and timers random initial values, and memory after the
+//! program is filled with random bytes. After each step registers, I, PC,
+//! stack, timers, RPL user flags, memory and frame must be equal, and both
+//! must fail at the same step.
+//!
+//! `Peach8` implements only `Quirks::PEACH8`. Other combinations are checked
+//! to make the property fail, so that each quirk is covered: a change of
+//! semantics of any opcode handler shows up as a failing case.
+
+use proptest::prelude::*;
+use proptest::test_runner::{Config, TestCaseError, TestRng, TestRunner};
+
+use peach8::storage::{Flags, MemoryStorage, Storage};
+use peach8::testing::{ImageMask, Rng, ToMask};
+use peach8::{Builder, Context, Frame, FrameView};
+use reference::{Machine, Quirks, MEMORY_SIZE, PROGRAM_START};
+
+/// Number of instructions setting initial registers and timers
+const PRELUDE: usize = 21;
+
+/// `Context` with keys set for each step, a copy of the last frame and
+/// storage of RPL user flags
+struct DiffContext {
+    keys: [bool; 16],
+    rng: Rng,
+    frame: Option<Frame>,
+    storage: MemoryStorage,
+}
+
+impl Context for DiffContext {
+    fn on_frame(&mut self, frame: FrameView<'_>) {
+        self.frame = Some(frame.copy_frame());
+    }
+
+    fn sound_on(&mut self) {}
+
+    fn sound_off(&mut self) {}
+
+    fn get_keys(&mut self) -> [bool; 16] {
+        self.keys
+    }
+
+    fn gen_random(&mut self) -> u8 {
+        self.rng.next_u8()
+    }
+
+    fn storage(&mut self) -> Option<&mut dyn Storage> {
+        Some(&mut self.storage)
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    /// Held keys as bits
+    keys: u16,
+    /// Tick timers after the instruction
+    timers: bool,
+}
+
+#[derive(Clone, Debug)]
+struct Case {
+    v: [u8; 16],
+    i: u16,
+    delay: u8,
+    sound: u8,
+    program: Vec<u16>,
+    filler: Vec<u8>,
+    steps: Vec<Step>,
+    seed: u64,
+}
+
+impl Case {
+    /// Prelude setting initial registers and timers, followed by the program and filler
+    fn rom(&self) -> Vec<u8> {
+        let mut words = vec![0x6000 | self.delay as u16, 0xF015];
+        words.extend(&[0x6000 | self.sound as u16, 0xF018]);
+        words.extend((0..16).map(|x| 0x6000 | x << 8 | self.v[x as usize] as u16));
+        words.push(0xA000 | self.i);
+        debug_assert_eq!(words.len(), PRELUDE);
+        words.extend(&self.program);
+
+        let mut rom: Vec<u8> = words
+            .iter()
+            .flat_map(|w| w.to_be_bytes().to_vec())
+            .collect();
+        rom.extend(&self.filler);
+        rom.truncate(MEMORY_SIZE - PROGRAM_START as usize);
+        rom
+    }
+}
+
+/// Address in the program's area, mostly close to where it's loaded
+fn address() -> impl Strategy<Value = u16> {
+    prop_oneof![
+        3 => 0x200..0x280u16,
+        1 => 0x000..0x1000u16,
+    ]
+}
+
+/// Instruction, mostly of defined opcodes with registers and addresses
+/// that make interesting cases
+fn instruction() -> impl Strategy<Value = u16> {
+    let reg = || 0..16u16;
+    prop_oneof![
+        Just(0x00E0),
+        Just(0x00EE),
+        address().prop_map(|nnn| 0x1000 | nnn),
+        address().prop_map(|nnn| 0x2000 | nnn),
+        (0x3..=0x4u16, reg(), any::<u8>()).prop_map(|(k, x, nn)| k << 12 | x << 8 | nn as u16),
+        (prop_oneof![Just(0x5u16), Just(0x9)], reg(), reg())
+            .prop_map(|(k, x, y)| k << 12 | x << 8 | y << 4),
+        (0x6..=0x7u16, reg(), any::<u8>()).prop_map(|(k, x, nn)| k << 12 | x << 8 | nn as u16),
+        (
+            reg(),
+            reg(),
+            prop::sample::select(vec![0, 1, 2, 3, 4, 5, 6, 7, 0xE])
+        )
+            .prop_map(|(x, y, n)| 0x8000 | x << 8 | y << 4 | n),
+        (0..0x1000u16).prop_map(|nnn| 0xA000 | nnn),
+        address().prop_map(|nnn| 0xB000 | nnn),
+        (reg(), any::<u8>()).prop_map(|(x, nn)| 0xC000 | x << 8 | nn as u16),
+        (reg(), reg(), 0..16u16).prop_map(|(x, y, n)| 0xD000 | x << 8 | y << 4 | n),
+        (reg(), prop_oneof![Just(0x9Eu16), Just(0xA1)]).prop_map(|(x, nn)| 0xE000 | x << 8 | nn),
+        (
+            reg(),
+            prop::sample::select(vec![
+                0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65, 0x75, 0x85
+            ])
+        )
+            .prop_map(|(x, nn)| 0xF000 | x << 8 | nn),
+        any::<u16>(),
+    ]
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    (
+        prop_oneof![3 => Just(0u16), 1 => any::<u16>()],
+        prop::bool::weighted(0.1),
+    )
+        .prop_map(|(keys, timers)| Step { keys, timers })
+}
+
+fn case() -> impl Strategy<Value = Case> {
+    (
+        any::<[u8; 16]>(),
+        0..0x1000u16,
+        any::<u8>(),
+        any::<u8>(),
+        prop::collection::vec(instruction(), 1..64),
+        prop::collection::vec(any::<u8>(), 0..512),
+        prop::collection::vec(step(), 1..256),
+        any::<u64>(),
+    )
+        .prop_map(|(v, i, delay, sound, program, filler, steps, seed)| Case {
+            v,
+            i,
+            delay,
+            sound,
+            program,
+            filler,
+            steps,
+            seed,
+        })
+}
+
+fn keys(bits: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    keys.iter_mut()
+        .enumerate()
+        .for_each(|(n, key)| *key = bits & 1 << n != 0);
+    keys
+}
+
+fn screen_mask(machine: &Machine) -> ImageMask {
+    let mut mask = ImageMask::new();
+    for (y, row) in machine.screen.iter().enumerate() {
+        for (x, &lit) in row.iter().enumerate() {
+            mask.set(x, y, lit);
+        }
+    }
+    mask
+}
+
+/// Run the case on both interpreters, comparing their states after each step
+fn check(case: &Case, quirks: Quirks) -> Result<(), TestCaseError> {
+    let rom = case.rom();
+    let ctx = DiffContext {
+        keys: [false; 16],
+        rng: Rng::new(case.seed),
+        frame: None,
+        storage: MemoryStorage::new(),
+    };
+    let mut chip = Builder::new()
+        .with_context(ctx)
+        .with_program(&rom)
+        .build()
+        .unwrap();
+    let mut machine = Machine::new(&rom, quirks);
+    let mut rng = Rng::new(case.seed);
+
+    let prelude = (0..PRELUDE).map(|_| Step {
+        keys: 0,
+        timers: false,
+    });
+    for (n, step) in prelude.chain(case.steps.iter().cloned()).enumerate() {
+        chip.ctx.keys = keys(step.keys);
+        let expected = machine.step(keys(step.keys), || rng.next_u8());
+        let actual = chip.tick_chip();
+        prop_assert_eq!(
+            actual.is_err(),
+            expected.is_err(),
+            "step {}: peach8 returned {:?}, reference {:?}",
+            n,
+            actual,
+            expected
+        );
+        if expected.is_err() {
+            break;
+        }
+        if step.timers {
+            chip.tick_timers();
+            machine.tick_timers();
+        }
+
+        let mut flags = Flags::default();
+        let rom_hash = chip.rom_hash();
+        chip.ctx.storage.load(rom_hash, &mut flags).unwrap();
+        let state = chip.state();
+        prop_assert_eq!(state.v, &machine.v, "V at step {}", n);
+        prop_assert_eq!(state.i, machine.i, "I at step {}", n);
+        prop_assert_eq!(state.pc, machine.pc, "PC at step {}", n);
+        prop_assert_eq!(state.stack, &machine.stack[..], "stack at step {}", n);
+        prop_assert_eq!(state.delay, machine.delay, "delay timer at step {}", n);
+        prop_assert_eq!(state.sound, machine.sound, "sound timer at step {}", n);
+        prop_assert_eq!(flags, machine.flags, "RPL user flags at step {}", n);
+        if let Some(at) = (0..MEMORY_SIZE).find(|&a| state.memory[a] != machine.memory[a]) {
+            return Err(TestCaseError::fail(format!(
+                "memory at {:#05X} at step {}: {:#04X} != {:#04X}",
+                at, n, state.memory[at], machine.memory[at]
+            )));
+        }
+        let frame = chip.ctx.frame.as_ref().unwrap().to_mask();
+        if let Some(diff) = frame.diff(&screen_mask(&machine)) {
+            return Err(TestCaseError::fail(format!(
+                "frame at step {}:\n{}",
+                n, diff
+            )));
+        }
+    }
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(Config::with_cases(512))]
+
+    #[test]
+    fn peach8_matches_reference(case in case()) {
+        check(&case, Quirks::PEACH8)?;
+    }
+}
+
+/// Each other combination of quirks must be told apart from `Peach8`
+#[test]
+fn other_quirks_are_detected() {
+    for quirks in Quirks::all().filter(|&q| q != Quirks::PEACH8) {
+        let config = Config {
+            cases: 4096,
+            failure_persistence: None,
+            max_shrink_iters: 0,
+            ..Config::default()
+        };
+        let rng = TestRng::deterministic_rng(config.rng_algorithm);
+        let mut runner = TestRunner::new_with_rng(config, rng);
+        let result = runner.run(&case(), |case| check(&case, quirks));
+        assert!(result.is_err(), "{:?} not detected", quirks);
+    }
+}
@@ -0,0 +1,335 @@
+//! Generate single-step test vectors of Chip-8 instructions
+//!
+//! Usage: `gen-vectors [--quirks PROFILE] [--cases N] [--seed S] DIR`
+//!
+//! Writes a gzipped JSON file per opcode family into DIR, each with N cases
+//! (4000 by default) of one instruction executed by the model from `reference`
+//! crate. PROFILE selects its quirks, one of `peach8` (default), `cosmac-vip`
+//! or `super-chip`.
+//!
+//! Each case gives the opcode, keys held during this and the previous step,
+//! the number returned for CXNN, and the initial and final state. Memory and
+//! frame are sparse lists of `[address, byte]`. Initial memory lists the bytes
+//! set before the step, any other byte is zero, and final memory lists only
+//! bytes changed by the instruction. Frame lists lit bytes of 64x32 pixels
+//! stored by rows, with the leftmost pixel in the most significant bit. Flags
+//! are the RPL user flags saved by FX75, given for FXNN only. The final state
+//! is `null` if the instruction fails.
+
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::{env, fs, process};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::Serialize;
+
+use peach8::testing::Rng;
+use reference::{Machine, Quirks, FLAGS, HEIGHT, MEMORY_SIZE, PROGRAM_START, STACK_SIZE, WIDTH};
+
+/// Opcode families, with names of their files
+const FAMILIES: [&str; 16] = [
+    "0nnn", "1nnn", "2nnn", "3xnn", "4xnn", "5xy0", "6xnn", "7xnn", "8xyn", "9xy0", "annn", "bnnn",
+    "cxnn", "dxyn", "exnn", "fxnn",
+];
+
+#[derive(Serialize)]
+struct State {
+    pc: u16,
+    i: u16,
+    v: [u8; 16],
+    stack: Vec<u16>,
+    delay: u8,
+    sound: u8,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<[u8; FLAGS]>,
+    ram: Vec<(u16, u8)>,
+    frame: Vec<(u8, u8)>,
+}
+
+#[derive(Serialize)]
+struct Case {
+    name: String,
+    opcode: u16,
+    keys: u16,
+    previous_keys: u16,
+    random: u8,
+    initial: State,
+    #[serde(rename = "final")]
+    result: Option<State>,
+}
+
+struct Generator {
+    rng: Rng,
+    quirks: Quirks,
+}
+
+impl Generator {
+    fn below(&mut self, n: u32) -> u32 {
+        self.rng.next_u32() % n
+    }
+
+    /// True once in `n` times on average
+    fn one_in(&mut self, n: u32) -> bool {
+        self.below(n) == 0
+    }
+
+    /// Address of a program, rarely in the interpreter's area
+    fn address(&mut self) -> u16 {
+        if self.one_in(8) {
+            self.below(PROGRAM_START as u32) as u16
+        } else {
+            PROGRAM_START + self.below(MEMORY_SIZE as u32 - PROGRAM_START as u32) as u16
+        }
+    }
+
+    fn opcode(&mut self, family: u16) -> u16 {
+        let x = self.below(16) as u16;
+        let y = self.below(16) as u16;
+        let n = self.below(16) as u16;
+        let body = match family {
+            0x0 => match self.below(5) {
+                0 | 1 => 0x0E0,
+                2 | 3 => 0x0EE,
+                _ => self.below(0x1000) as u16,
+            },
+            0x1 | 0x2 | 0xB => self.address(),
+            0x5 | 0x9 if !self.one_in(8) => x << 8 | y << 4,
+            0x8 if !self.one_in(16) => {
+                let ops = [0x0, 0x1, 0x2, 0x3, 0x4, 0x5, 0x6, 0x7, 0xE];
+                x << 8 | y << 4 | ops[self.below(ops.len() as u32) as usize]
+            }
+            0xE if !self.one_in(16) => x << 8 | [0x9E, 0xA1][self.below(2) as usize],
+            0xF if !self.one_in(16) => {
+                let ops = [
+                    0x07, 0x0A, 0x15, 0x18, 0x1E, 0x29, 0x33, 0x55, 0x65, 0x75, 0x85,
+                ];
+                x << 8 | ops[self.below(ops.len() as u32) as usize]
+            }
+            _ => x << 8 | y << 4 | n,
+        };
+        family << 12 | body
+    }
+
+    /// Random machine ready to execute `opcode`, with keys held previously
+    fn machine(&mut self, opcode: u16) -> Machine {
+        let mut machine = Machine::new(&[], self.quirks);
+        machine.memory = vec![0; MEMORY_SIZE];
+        let (x, y) = ((opcode >> 8 & 0xF) as usize, (opcode >> 4 & 0xF) as usize);
+        let nn = opcode as u8;
+
+        for v in machine.v.iter_mut() {
+            *v = self.rng.next_u8();
+        }
+        machine.pc = if self.one_in(16) {
+            MEMORY_SIZE as u16 - 2
+        } else {
+            PROGRAM_START + 2 * self.below((MEMORY_SIZE as u32 - PROGRAM_START as u32) / 2) as u16
+        };
+        machine.i = if self.one_in(8) {
+            MEMORY_SIZE as u16 - 1 - self.below(16) as u16
+        } else {
+            self.below(MEMORY_SIZE as u32) as u16
+        };
+        let depth = match self.below(16) {
+            0 => 0,
+            1 if opcode >> 12 == 0x2 => STACK_SIZE,
+            _ => 1 + self.below(3) as usize,
+        };
+        machine.stack = (0..depth)
+            .map(|_| PROGRAM_START + 2 * self.below(0x700) as u16)
+            .collect();
+        machine.delay = self.rng.next_u8();
+        machine.sound = self.rng.next_u8();
+        if opcode >> 12 == 0xF {
+            for flag in machine.flags.iter_mut() {
+                *flag = self.rng.next_u8();
+            }
+        }
+
+        // make conditions of skips and waits hold in about half of cases
+        match opcode >> 12 {
+            0x3 | 0x4 if self.one_in(2) => machine.v[x] = nn,
+            0x5 | 0x9 if self.one_in(2) => machine.v[y] = machine.v[x],
+            0xE | 0xF if !self.one_in(8) => machine.v[x] &= 0xF,
+            _ => (),
+        }
+        for (key, previous) in machine
+            .keys
+            .iter_mut()
+            .zip(machine.previous_keys.iter_mut())
+        {
+            *key = self.one_in(4);
+            *previous = self.one_in(4);
+        }
+        if self.one_in(2) {
+            let key = machine.v[x] as usize & 0xF;
+            // held for EX9E and EXA1, just released for FX0A
+            machine.keys[key] = opcode >> 12 == 0xE;
+            machine.previous_keys[key] = true;
+        }
+
+        // memory at I for instructions reading or writing it
+        if opcode >> 12 == 0xD || matches!(opcode & 0xF0FF, 0xF033 | 0xF055 | 0xF065) {
+            let i = machine.i as usize;
+            for byte in machine.memory.iter_mut().skip(i).take(16) {
+                *byte = self.rng.next_u8();
+            }
+        }
+        if opcode >> 12 == 0xD || opcode == 0x00E0 {
+            let (left, top) = (
+                machine.v[x] as usize % WIDTH,
+                machine.v[y] as usize % HEIGHT,
+            );
+            // lit pixels under the sprite, or some to clear for 00E0
+            let rows = if opcode == 0x00E0 {
+                4
+            } else {
+                opcode as usize & 0xF
+            };
+            for row in top..top + rows {
+                for column in left..left + 8 {
+                    machine.screen[row % HEIGHT][column % WIDTH] = self.one_in(2);
+                }
+            }
+        }
+        machine.memory[machine.pc as usize] = (opcode >> 8) as u8;
+        machine.memory[machine.pc as usize + 1] = opcode as u8;
+        machine
+    }
+
+    fn case(&mut self, family: u16, n: usize) -> Case {
+        let opcode = self.opcode(family);
+        let mut machine = self.machine(opcode);
+        let random = self.rng.next_u8();
+        let keys = machine.keys;
+        let previous_keys = machine.previous_keys;
+        let initial = machine.clone();
+
+        // `step` moves keys held during the step into previous keys
+        machine.keys = previous_keys;
+        let result = machine
+            .step(keys, || random)
+            .ok()
+            .map(|_| state(&machine, Some(&initial), family));
+        Case {
+            name: format!("{:04X} #{}", opcode, n),
+            opcode,
+            keys: bits(&keys),
+            previous_keys: bits(&previous_keys),
+            random,
+            initial: state(&initial, None, family),
+            result,
+        }
+    }
+}
+
+fn bits(keys: &[bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, &held)| held)
+        .fold(0, |bits, (n, _)| bits | 1 << n)
+}
+
+/// State of `machine` for a case of `family`, listing bytes of memory which are set, or
+/// changed since `initial`
+fn state(machine: &Machine, initial: Option<&Machine>, family: u16) -> State {
+    let ram = (0..MEMORY_SIZE)
+        .filter(|&a| machine.memory[a] != initial.map_or(0, |initial| initial.memory[a]))
+        .map(|a| (a as u16, machine.memory[a]))
+        .collect();
+    let frame = machine
+        .screen
+        .iter()
+        .flat_map(|row| row.chunks(8))
+        .map(|pixels| pixels.iter().fold(0u8, |byte, &lit| byte << 1 | lit as u8))
+        .enumerate()
+        .filter(|&(_, byte)| byte != 0)
+        .map(|(n, byte)| (n as u8, byte))
+        .collect();
+    State {
+        pc: machine.pc,
+        i: machine.i,
+        v: machine.v,
+        stack: machine.stack.clone(),
+        delay: machine.delay,
+        sound: machine.sound,
+        flags: Some(machine.flags).filter(|_| family == 0xF),
+        ram,
+        frame,
+    }
+}
+
+/// Write cases of one family as a gzipped JSON array, a case per line
+fn write_family(
+    generator: &mut Generator,
+    family: u16,
+    cases: usize,
+    path: &Path,
+) -> io::Result<()> {
+    let file = BufWriter::new(fs::File::create(path)?);
+    let mut out = GzEncoder::new(file, Compression::best());
+    writeln!(out, "[")?;
+    for n in 0..cases {
+        let case = generator.case(family, n);
+        let separator = if n + 1 < cases { "," } else { "" };
+        writeln!(out, "{}{}", serde_json::to_string(&case)?, separator)?;
+    }
+    writeln!(out, "]")?;
+    out.finish()?.flush()
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: gen-vectors [--quirks peach8|cosmac-vip|super-chip] [--cases N] [--seed S] DIR"
+    );
+    process::exit(2);
+}
+
+fn main() {
+    let mut quirks = Quirks::PEACH8;
+    let mut cases = 4000;
+    let mut seed = 0;
+    let mut dir = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--quirks" => {
+                quirks = match args.next().as_deref() {
+                    Some("peach8") => Quirks::PEACH8,
+                    Some("cosmac-vip") => Quirks::COSMAC_VIP,
+                    Some("super-chip") => Quirks::SUPER_CHIP,
+                    _ => usage(),
+                }
+            }
+            "--cases" => {
+                cases = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--seed" => {
+                seed = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            _ if dir.is_none() && !arg.starts_with("--") => dir = Some(arg),
+            _ => usage(),
+        }
+    }
+    let dir = dir.unwrap_or_else(|| usage());
+
+    let mut generator = Generator {
+        rng: Rng::new(seed),
+        quirks,
+    };
+    for (family, name) in FAMILIES.iter().enumerate() {
+        let path = Path::new(&dir).join(format!("{}.json.gz", name));
+        if let Err(e) = write_family(&mut generator, family as u16, cases, &path) {
+            eprintln!("failed to write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Edit tool-assisted runs of a program without a display
+//!
+//! Usage: `tas [--seed S] [--movie FILE] ROM` or `tas --verify MOVIE ROM`
+//!
+//! Reads commands from standard input, one per line, playing ROM frame by
+//! frame with `peach8::tas::Session`, optionally starting from inputs of
+//! FILE. KEYS are hex digits of held keys, eg. `46`, or `-` for none.
+//! - `advance [N]` - play N frames, 1 by default,
+//! - `keys KEYS` - set keys of the next frame,
+//! - `set FRAME KEYS`, `insert FRAME [KEYS]`, `delete FRAME` - edit inputs,
+//! - `truncate FRAMES` - drop inputs after the first FRAMES frames,
+//! - `seek FRAME` - replay inputs up to the frame,
+//! - `save NAME`, `load NAME` - keep and restore save points,
+//! - `inputs [FROM [TO]]` - list inputs, marking lag frames,
+//! - `show`, `state` - print the screen or registers,
+//! - `write FILE` - export inputs as a movie,
+//! - `quit`.
+//!
+//! Lines starting with `#` are ignored. Exits with 1 if any command failed.
+//!
+//! With `--verify`, MOVIE is replayed with `peach8::movie::Replay`, checking
+//! that emulation doesn't diverge from it.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::{env, fs, process};
+
+use peach8::export::write_half_blocks;
+use peach8::movie::Replay;
+use peach8::storage::RomHash;
+use peach8::tas::{SavePoint, Session, Silent, CHIP_FREQ, TIMERS_FREQ};
+use peach8::testing::{RecordingContext, VirtualClock};
+use peach8::Builder;
+
+fn usage() -> ! {
+    eprintln!("usage: tas [--seed S] [--movie FILE] ROM");
+    eprintln!("       tas --verify MOVIE ROM");
+    process::exit(2);
+}
+
+fn read(path: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        process::exit(2);
+    })
+}
+
+fn parse_keys(text: &str) -> Result<u16, String> {
+    if text == "-" {
+        return Ok(0);
+    }
+    text.chars().try_fold(0, |keys, c| match c.to_digit(16) {
+        Some(key) => Ok(keys | 1 << key),
+        None => Err(format!("invalid key {:?}", c)),
+    })
+}
+
+fn format_keys(keys: u16) -> String {
+    if keys == 0 {
+        return "-".to_string();
+    }
+    (0..16)
+        .filter(|key| keys & 1 << key != 0)
+        .map(|key| format!("{:X}", key))
+        .collect()
+}
+
+fn parse_number(text: Option<&str>) -> Result<usize, String> {
+    let text = text.ok_or("missing number")?;
+    text.parse()
+        .map_err(|_| format!("invalid number {:?}", text))
+}
+
+struct Editor {
+    session: Session<Silent>,
+    saves: HashMap<String, SavePoint>,
+}
+
+impl Editor {
+    fn advance(&mut self, frames: usize) -> Result<(), String> {
+        let lags = self.session.run(frames)?;
+        println!(
+            "frame {}, {} of {} frames lagged",
+            self.session.frame(),
+            lags,
+            frames
+        );
+        Ok(())
+    }
+
+    fn list(&self, from: usize, to: usize) {
+        let current = self.session.frame();
+        for frame in from..to.min(self.session.inputs().len()) {
+            let keys = format_keys(self.session.inputs()[frame]);
+            let lag = match self.session.lags().get(frame) {
+                Some(true) => " lag",
+                _ => "",
+            };
+            let marker = if frame == current { ">" } else { " " };
+            println!("{}{:6} {}{}", marker, frame, keys, lag);
+        }
+    }
+
+    fn execute(&mut self, line: &str) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let command = match words.next() {
+            Some(command) if !command.starts_with('#') => command,
+            _ => return Ok(true),
+        };
+        let session = &mut self.session;
+        match command {
+            "advance" => {
+                let frames = match words.next() {
+                    Some(n) => parse_number(Some(n))?,
+                    None => 1,
+                };
+                self.advance(frames)?;
+            }
+            "keys" => {
+                let keys = parse_keys(words.next().ok_or("missing keys")?)?;
+                session.set_input(session.frame(), keys);
+            }
+            "set" => {
+                let frame = parse_number(words.next())?;
+                let keys = parse_keys(words.next().ok_or("missing keys")?)?;
+                session.set_input(frame, keys);
+            }
+            "insert" => {
+                let frame = parse_number(words.next())?;
+                let keys = words.next().map_or(Ok(0), parse_keys)?;
+                session.insert_input(frame, keys);
+            }
+            "delete" => {
+                let frame = parse_number(words.next())?;
+                session
+                    .remove_input(frame)
+                    .ok_or_else(|| format!("no input at frame {}", frame))?;
+            }
+            "truncate" => session.truncate(parse_number(words.next())?),
+            "seek" => session.seek(parse_number(words.next())?)?,
+            "save" => {
+                let name = words.next().ok_or("missing name")?;
+                self.saves.insert(name.to_string(), session.save()?);
+            }
+            "load" => {
+                let name = words.next().ok_or("missing name")?;
+                let save = self
+                    .saves
+                    .get(name)
+                    .ok_or_else(|| format!("no save point {:?}", name))?;
+                session.load(save)?;
+            }
+            "inputs" => {
+                let from = words.next().map_or(Ok(0), |n| parse_number(Some(n)))?;
+                let to = words
+                    .next()
+                    .map_or(Ok(usize::MAX), |n| parse_number(Some(n)))?;
+                self.list(from, to);
+            }
+            "show" => {
+                let mut text = String::new();
+                write_half_blocks(session.screen(), 1, &mut text).unwrap();
+                print!("{}", text);
+            }
+            "state" => {
+                let state = session.chip().state();
+                println!(
+                    "frame {} cycle {} pc {:03X} i {:03X} v {:02X?}",
+                    session.frame(),
+                    state.cycle,
+                    state.pc,
+                    state.i,
+                    state.v
+                );
+            }
+            "write" => {
+                let path = words.next().ok_or("missing file")?;
+                let movie = session.export()?;
+                fs::write(path, &movie).map_err(|e| format!("failed to write {}: {}", path, e))?;
+                println!("wrote {} frames to {}", session.inputs().len(), path);
+            }
+            "quit" => return Ok(false),
+            _ => return Err(format!("unknown command {:?}", command)),
+        }
+        Ok(true)
+    }
+}
+
+fn edit(rom: &[u8], movie: Option<&[u8]>, seed: u64) {
+    let session = match movie {
+        Some(movie) => Session::from_movie(Silent, rom, movie, seed),
+        None => Session::new(Silent, rom, seed),
+    };
+    let mut editor = Editor {
+        session: session.unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            process::exit(1);
+        }),
+        saves: HashMap::new(),
+    };
+    let mut failed = false;
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap_or_else(|e| {
+            eprintln!("failed to read commands: {}", e);
+            process::exit(2);
+        });
+        match editor.execute(&line) {
+            Ok(true) => {}
+            Ok(false) => break,
+            Err(e) => {
+                eprintln!("{}: {}", line.trim(), e);
+                failed = true;
+            }
+        }
+    }
+    if failed {
+        process::exit(1);
+    }
+}
+
+/// Replay the movie, exiting with 1 if emulation diverges from it
+fn verify(rom: &[u8], movie: &[u8]) {
+    let fail = |e: &str| -> ! {
+        println!("movie fails: {}", e);
+        process::exit(1);
+    };
+    let replay = Replay::new(Silent, movie, RomHash::of(rom)).unwrap_or_else(|e| fail(e));
+    let mut session = Session::from_movie(Silent, rom, movie, 0).unwrap_or_else(|e| fail(e));
+    let frames = session.inputs().len();
+    let lags = session.run(frames).unwrap_or_else(|e| fail(e));
+
+    let mut chip = Builder::new()
+        .with_context(RecordingContext::new(replay))
+        .with_program(rom)
+        .build()
+        .unwrap_or_else(|e| fail(e));
+    VirtualClock::new(CHIP_FREQ, TIMERS_FREQ)
+        .run_frames(&mut chip, frames as u64)
+        .unwrap_or_else(|e| fail(e));
+    let recording = chip.release();
+    let screen = recording.frames().last().map(|(_, frame)| frame.view());
+    if screen.is_some_and(|screen| screen != session.screen()) {
+        fail("final screen differs from the edited run");
+    }
+    let replay = recording.release();
+    if let Some(e) = replay.error() {
+        fail(e);
+    }
+    if !replay.is_finished() {
+        fail("records left after the last frame");
+    }
+    println!("movie verified: {} frames, {} lag frames", frames, lags);
+}
+
+fn main() {
+    let mut seed = 0;
+    let mut movie = None;
+    let mut verified = None;
+    let mut rom = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--seed" => {
+                seed = args
+                    .next()
+                    .and_then(|n| n.parse().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            "--movie" => movie = Some(args.next().unwrap_or_else(|| usage())),
+            "--verify" => verified = Some(args.next().unwrap_or_else(|| usage())),
+            _ if rom.is_none() && !arg.starts_with("--") => rom = Some(arg),
+            _ => usage(),
+        }
+    }
+    let rom = read(&rom.unwrap_or_else(|| usage()));
+
+    match verified {
+        Some(path) => verify(&rom, &read(&path)),
+        None => edit(&rom, movie.as_deref().map(read).as_deref(), seed),
+    }
+}
@@ -0,0 +1,59 @@
+//! Report the first divergence between two execution traces
+//!
+//! Usage: `trace-diff [--binary] LHS RHS`
+//!
+//! Text traces are compared field by field with `peach8::trace::diff`.
+//! With `--binary`, both files are expected to contain binary records
+//! emitted by `Tracer` with `Format::Binary`.
+
+use std::fmt::Write;
+use std::{env, fs, process};
+
+use peach8::trace::{diff, Fields, Record, RECORD_SIZE};
+
+fn read(path: &str, binary: bool) -> String {
+    let bytes = fs::read(path).unwrap_or_else(|e| {
+        eprintln!("failed to read {}: {}", path, e);
+        process::exit(2);
+    });
+    if !binary {
+        return String::from_utf8_lossy(&bytes).into_owned();
+    }
+    let trailing = bytes.len() % RECORD_SIZE;
+    if trailing > 0 {
+        eprintln!("{}: ignoring trailing {} bytes", path, trailing);
+    }
+    let mut text = String::new();
+    for chunk in bytes.chunks_exact(RECORD_SIZE) {
+        let record = Record::from_bytes(chunk).unwrap();
+        record.write_text(&Fields::all(), &mut text).unwrap();
+        text.push('\n');
+    }
+    text
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let binary = args.iter().any(|arg| arg == "--binary");
+    args.retain(|arg| arg != "--binary");
+    if args.len() != 2 {
+        eprintln!("usage: trace-diff [--binary] LHS RHS");
+        process::exit(2);
+    }
+
+    let lhs = read(&args[0], binary);
+    let rhs = read(&args[1], binary);
+    match diff(lhs.lines(), rhs.lines()) {
+        None => println!("traces are identical"),
+        Some(divergence) => {
+            let mut report = format!("traces diverge at line {}", divergence.line + 1);
+            if let Some(field) = divergence.field {
+                write!(report, " in field {}", field).unwrap();
+            }
+            println!("{}", report);
+            println!("< {}", divergence.lhs.unwrap_or("<end of trace>"));
+            println!("> {}", divergence.rhs.unwrap_or("<end of trace>"));
+            process::exit(1);
+        }
+    }
+}